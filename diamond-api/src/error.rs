@@ -1,3 +1,4 @@
+use actix_web::error::BlockingError;
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
@@ -92,6 +93,26 @@ impl From<io::Error> for ResponseError {
     }
 }
 
+impl From<whisper::error::Error> for ResponseError {
+    fn from(error: whisper::error::Error) -> Self {
+        match error {
+            whisper::error::Error::FileNotExist(_) => ResponseError::NotFound,
+            e => ResponseError::Kind(e.to_string()),
+        }
+    }
+}
+
+impl From<BlockingError<ResponseError>> for ResponseError {
+    fn from(error: BlockingError<ResponseError>) -> Self {
+        match error {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                ResponseError::Kind("Storage request was canceled".to_owned())
+            }
+        }
+    }
+}
+
 impl From<SystemTimeError> for ResponseError {
     fn from(error: SystemTimeError) -> Self {
         ResponseError::SystemTime(error.duration())
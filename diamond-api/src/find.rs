@@ -1,5 +1,5 @@
 use actix_web::error::ErrorInternalServerError;
-use actix_web::web::{Data, Form, Json, Query};
+use actix_web::web::{self, Data, Form, Json, Query};
 use actix_web::{dev, FromRequest, HttpMessage, HttpRequest, HttpResponse, Result};
 use futures::future::{FutureExt, LocalBoxFuture};
 use serde::*;
@@ -7,6 +7,7 @@ use std::convert::From;
 use std::str::FromStr;
 
 use crate::context::Context;
+use crate::error::ResponseError;
 use crate::parse::de_time_parse;
 use crate::render_target::PathExpression;
 use crate::storage::MetricResponseLeaf;
@@ -98,16 +99,18 @@ pub async fn find_handler(ctx: Data<Context>, query: FindQuery) -> Result<HttpRe
     let path_expression =
         PathExpression::from_str(&query.query).map_err(ErrorInternalServerError)?;
 
-    Ok(ctx.storage.find(&path_expression).map(|metrics| {
-        if query.format == FindFormat::TreeJson {
-            let metrics_json: Vec<JsonTreeLeaf> =
-                metrics.into_iter().map(JsonTreeLeaf::from).collect();
-            HttpResponse::Ok().json(metrics_json)
-        } else {
-            let metrics_completer = MetricResponse { metrics };
-            HttpResponse::Ok().json(metrics_completer)
-        }
-    })?)
+    let storage = ctx.storage.clone();
+    let metrics = web::block(move || storage.find(&path_expression))
+        .await
+        .map_err(ResponseError::from)?;
+
+    if query.format == FindFormat::TreeJson {
+        let metrics_json: Vec<JsonTreeLeaf> = metrics.into_iter().map(JsonTreeLeaf::from).collect();
+        Ok(HttpResponse::Ok().json(metrics_json))
+    } else {
+        let metrics_completer = MetricResponse { metrics };
+        Ok(HttpResponse::Ok().json(metrics_completer))
+    }
 }
 
 #[cfg(test)]
@@ -1,7 +1,7 @@
 use chrono::NaiveDateTime;
 
 use actix_web::error::ErrorInternalServerError;
-use actix_web::web::{Data, Json};
+use actix_web::web::{self, Data, Json};
 use actix_web::{dev, FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use futures::future::{ready, FutureExt, LocalBoxFuture};
 use serde::*;
@@ -25,6 +25,9 @@ pub struct RenderQuery {
     from: u32,
     #[serde(deserialize_with = "de_time_parse")]
     until: u32,
+    /// Combine all archives, using the highest resolution available for each part of the range.
+    #[serde(default)]
+    stitch: bool,
 }
 
 impl FromStr for RenderQuery {
@@ -40,6 +43,7 @@ impl FromStr for RenderQuery {
                 "format" => q.format = value.parse()?,
                 "from" => q.from = time_parse(value)?,
                 "until" => q.until = time_parse(value)?,
+                "stitch" => q.stitch = parse_flag(&value)?,
                 _ => {}
             };
         }
@@ -48,6 +52,14 @@ impl FromStr for RenderQuery {
     }
 }
 
+fn parse_flag(value: &str) -> Result<bool, ParseError> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ParseError::Query(format!("Bad flag value {}", value))),
+    }
+}
+
 impl FromRequest for RenderQuery {
     type Error = actix_web::Error;
     type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
@@ -141,13 +153,14 @@ pub async fn render_handler(
         .expect("Time travel beyond Unix epoch is forbidden by Temporal Police.")
         .as_secs();
     let format = query.format;
+    let stitch = query.stitch;
 
     let mut response: Vec<RenderResponseEntry> = Vec::new();
 
     for target in query.target {
         let expression = Expression::from_str(&target).map_err(ErrorInternalServerError)?;
-        let path_expression: &PathExpression = match expression {
-            Expression::Path(ref e) => e,
+        let path_expression: PathExpression = match expression {
+            Expression::Path(e) => e,
             _ => {
                 return Err(ErrorInternalServerError(format!(
                     "Unsupported type of query: {}. For now only path expressions are supported",
@@ -156,7 +169,11 @@ pub async fn render_handler(
             }
         };
 
-        let storage_responses = ctx.storage.query(&path_expression, interval, now)?;
+        let storage = ctx.storage.clone();
+        let storage_responses =
+            web::block(move || storage.query(&path_expression, interval, now, stitch))
+                .await
+                .map_err(ResponseError::from)?;
 
         for storage_response in storage_responses {
             response.push(RenderResponseEntry {
@@ -274,6 +291,7 @@ mod tests {
                 format: format.clone(),
                 from: 0,
                 until: 0,
+                stitch: false,
             };
             let (status, ct, response) = render_response(ctx, query).await;
             assert_eq!(status, StatusCode::BAD_REQUEST);
@@ -297,6 +315,7 @@ mod tests {
             format: RenderFormat::Json,
             from: 0,
             until: 0,
+            stitch: false,
         };
         let (status, ct, response) = render_response(ctx, query).await;
         assert_eq!(status, StatusCode::OK);
@@ -325,6 +344,7 @@ mod tests {
             format: RenderFormat::Json,
             from: 0,
             until: 0,
+            stitch: false,
         };
         let (status, ct, response) = render_response(ctx, query).await;
         assert_eq!(status, StatusCode::OK);
@@ -350,6 +370,7 @@ mod tests {
             format: RenderFormat::Csv,
             from: 0,
             until: 0,
+            stitch: false,
         };
         let (status, ct, response) = render_response(ctx, query).await;
         assert_eq!(status, StatusCode::OK);
@@ -378,6 +399,7 @@ mod tests {
             format: RenderFormat::Csv,
             from: 0,
             until: 0,
+            stitch: false,
         };
         let (status, ct, response) = render_response(ctx, query).await;
         assert_eq!(status, StatusCode::OK);
@@ -395,6 +417,7 @@ mod tests {
             target: ["app.numUsers".to_owned()].to_vec(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(
@@ -412,6 +435,7 @@ mod tests {
             target: ["app.numUsers".to_owned(), "app.numServers".to_owned()].to_vec(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(
@@ -430,6 +454,7 @@ mod tests {
             target: Vec::new(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(
@@ -447,6 +472,7 @@ mod tests {
             target: vec!["m1".to_owned()],
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(
@@ -457,6 +483,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn url_deserialize_stitch() -> Result<(), ParseError> {
+        let params = RenderQuery {
+            format: RenderFormat::Json,
+            target: vec!["m1".to_owned()],
+            from: 0,
+            until: 10,
+            stitch: true,
+        };
+
+        assert_eq!(
+            "target=m1&format=json&from=0&until=10&stitch=true".parse::<RenderQuery>()?,
+            params
+        );
+        assert!("target=m1&stitch=yes".parse::<RenderQuery>().is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn url_deserialize_time_yesterday_now() -> Result<(), ParseError> {
         let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
@@ -466,6 +511,7 @@ mod tests {
             target: ["m1".to_owned(), "m2".to_owned()].to_vec(),
             from: now - 3600 * 24,
             until: now,
+            stitch: false,
         };
 
         assert_eq!(
@@ -485,6 +531,7 @@ mod tests {
             target: ["m1".to_owned(), "m2".to_owned()].to_vec(),
             from: now - 5 * 3600 * 24,
             until: now - 5 * 60,
+            stitch: false,
         };
 
         assert_eq!(
@@ -497,6 +544,7 @@ mod tests {
             target: ["m1".to_owned(), "m2".to_owned()].to_vec(),
             from: now - 5 * 3600 * 24 * 7,
             until: now - 5,
+            stitch: false,
         };
 
         assert_eq!(
@@ -509,6 +557,7 @@ mod tests {
             target: ["m1".to_owned(), "m2".to_owned()].to_vec(),
             from: now - 2 * 3600 * 24 * 365,
             until: now - 5 * 3600,
+            stitch: false,
         };
 
         assert_eq!(
@@ -521,6 +570,7 @@ mod tests {
             target: ["m1".to_owned(), "m2".to_owned()].to_vec(),
             from: now - 5 * 3600 * 24 * 30,
             until: now - 60,
+            stitch: false,
         };
 
         assert_eq!(
@@ -620,6 +670,7 @@ mod tests {
             target: ["app.numUsers".to_owned()].to_vec(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(RenderQuery::extract(&req).await?, params);
@@ -641,6 +692,7 @@ mod tests {
             target: ["app.numUsers".to_owned()].to_vec(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         let res = RenderQuery::from_request(&req, &mut payload).await?;
@@ -665,6 +717,7 @@ mod tests {
             target: ["app.numUsers".to_owned()].to_vec(),
             from: 0,
             until: 10,
+            stitch: false,
         };
 
         assert_eq!(RenderQuery::from_request(&req, &mut pl).await?, params);
@@ -49,5 +49,6 @@ pub trait Storage {
         path_expression: &PathExpression,
         interval: Interval,
         now: u64,
+        stitch: bool,
     ) -> Result<Vec<StorageResponse>, ResponseError>;
 }
@@ -1,10 +1,13 @@
 use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::fs;
+use std::io::{Read, Seek, Write};
 use std::iter::successors;
 use std::path::{Path, PathBuf};
+use whisper::compressed;
+use whisper::error::Error;
 use whisper::interval::Interval;
-use whisper::{ArchiveData, WhisperFile};
+use whisper::{ArchiveData, OpenOptions, ReadMode, WhisperFile};
 
 use super::storage::*;
 use crate::error::ResponseError;
@@ -41,6 +44,7 @@ impl Storage for WhisperFileSystemStorage {
         path_expression: &PathExpression,
         interval: Interval,
         now: u64,
+        stitch: bool,
     ) -> Result<Vec<StorageResponse>, ResponseError> {
         let mut paths = Vec::new();
         walk_tree(
@@ -52,18 +56,17 @@ impl Storage for WhisperFileSystemStorage {
 
         let mut responses = Vec::new();
         for (metric_name, fs_path) in paths {
-            let ArchiveData {
-                from_interval,
-                step,
-                values,
-                ..
-            } = WhisperFile::open(&fs_path)?.fetch_auto_points(interval, now as u32)?;
-            let timestamps = successors(Some(from_interval), |i| i.checked_add(step));
-            let points = values
-                .into_iter()
-                .zip(timestamps)
-                .map(|(value, time)| RenderPoint(value, time))
-                .collect();
+            let points = if compressed::is_compressed(&mut fs::File::open(&fs_path)?)? {
+                let mut file = compressed::open(&fs_path)?;
+                fetch_points(&mut file, interval, now as u32, stitch)?
+            } else {
+                let mut file = OpenOptions::new()
+                    .lock(true)
+                    .cache_headers(true)
+                    .read_mode(ReadMode::Mmap)
+                    .open(&fs_path)?;
+                fetch_points(&mut file, interval, now as u32, stitch)?
+            };
 
             responses.push(StorageResponse {
                 name: metric_name,
@@ -74,6 +77,38 @@ impl Storage for WhisperFileSystemStorage {
     }
 }
 
+fn fetch_points<F: Read + Write + Seek>(
+    file: &mut WhisperFile<F>,
+    interval: Interval,
+    now: u32,
+    stitch: bool,
+) -> Result<Vec<RenderPoint>, Error> {
+    if stitch {
+        Ok(file
+            .fetch_stitched(interval, now)?
+            .into_iter()
+            .flat_map(render_points)
+            .collect())
+    } else {
+        Ok(render_points(file.fetch_auto_points(interval, now)?))
+    }
+}
+
+fn render_points(data: ArchiveData) -> Vec<RenderPoint> {
+    let ArchiveData {
+        from_interval,
+        step,
+        values,
+        ..
+    } = data;
+    let timestamps = successors(Some(from_interval), |i| i.checked_add(step));
+    values
+        .into_iter()
+        .zip(timestamps)
+        .map(|(value, time)| RenderPoint(value, time))
+        .collect()
+}
+
 fn file_name(path: &Path) -> Option<Cow<'_, str>> {
     if path.is_dir() {
         Some(path.file_name()?.to_string_lossy())
@@ -183,4 +218,54 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn query_compressed() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = get_temp_dir();
+        let path = dir.path().join("standard.wsp");
+        let now = 1_528_240_800;
+
+        let mut file = whisper::WhisperBuilder::default()
+            .add_retention(whisper::retention::Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .build(&path)?;
+        file.update(
+            &whisper::point::Point {
+                interval: now - 60,
+                value: 42.0,
+            },
+            now,
+        )?;
+
+        let mut bytes = Vec::new();
+        compressed::compress(&mut file, compressed::DEFAULT_POINTS_PER_BLOCK, &mut bytes)?;
+        fs::write(dir.path().join("compressed.wsp"), bytes)?;
+
+        let interval = Interval::new(now - 120, now)?;
+        let storage = WhisperFileSystemStorage(dir.path().to_owned());
+        let standard = storage.query(
+            &PathExpression::from_str("standard")?,
+            interval,
+            now.into(),
+            false,
+        )?;
+        let compressed = storage.query(
+            &PathExpression::from_str("compressed")?,
+            interval,
+            now.into(),
+            false,
+        )?;
+
+        assert_eq!(
+            compressed[0].data,
+            vec![
+                RenderPoint(None, now - 120),
+                RenderPoint(Some(42.0), now - 60)
+            ]
+        );
+        assert_eq!(compressed[0].data, standard[0].data);
+        Ok(())
+    }
 }
@@ -23,6 +23,7 @@ impl Storage for ConstStorage {
         _path_expression: &PathExpression,
         _interval: Interval,
         _now: u64,
+        _stitch: bool,
     ) -> Result<Vec<StorageResponse>, ResponseError> {
         Ok(vec![StorageResponse {
             name: "i.am.a.metric".parse().unwrap(),
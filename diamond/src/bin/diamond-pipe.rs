@@ -23,7 +23,8 @@ struct Args {
     x_files_factor: f32,
 
     /// Default function to use when aggregating values
-    /// (average, sum, last, max, min, avg_zero, absmax, absmin)
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
     #[structopt(long = "aggregationMethod", default_value = "average")]
     aggregation_method: AggregationMethod,
 
@@ -1,4 +1,5 @@
 use diamond::settings::Settings;
+use diamond::stats::UpdateStats;
 use diamond::update_silently;
 use futures::join;
 use futures::stream::StreamExt;
@@ -6,12 +7,17 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::interval;
 use tokio_util::codec::Framed;
 use tokio_util::codec::LinesCodec;
 use tokio_util::udp::UdpFramed;
 
+/// How often update totals are printed.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "diamond-server")]
 struct Args {
@@ -47,6 +53,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config_tcp = Arc::new(settings);
     let config_udp = config_tcp.clone();
 
+    let stats_tcp = Arc::new(UpdateStats::default());
+    let stats_udp = stats_tcp.clone();
+    let stats = stats_tcp.clone();
+
     let tcp_server = async move {
         let mut incoming = tcp_listener.incoming();
         while let Some(sock) = incoming.next().await {
@@ -55,7 +65,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let mut framed_sock = Framed::new(sock, LinesCodec::new());
                     while let Some(line) = framed_sock.next().await {
                         match line {
-                            Ok(line) => update_silently(&line, &config_tcp),
+                            Ok(line) => update_silently(&line, &config_tcp, &stats_tcp).await,
                             Err(e) => eprintln!("tcp receive error = {:?}", e),
                         }
                     }
@@ -69,13 +79,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut incoming = UdpFramed::new(udp_listener, LinesCodec::new());
         while let Some(line) = incoming.next().await {
             match line {
-                Ok((line, _)) => update_silently(&line, &config_udp),
+                Ok((line, _)) => update_silently(&line, &config_udp, &stats_udp).await,
                 Err(e) => eprintln!("udp receive error = {:?}", e),
             }
         }
     };
 
-    join!(udp_server, tcp_server);
+    let stats_reporter = async move {
+        let mut ticks = interval(STATS_INTERVAL);
+        loop {
+            ticks.tick().await;
+            println!("updates: {}", stats);
+        }
+    };
+
+    join!(udp_server, tcp_server, stats_reporter);
 
     Ok(())
 }
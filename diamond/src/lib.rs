@@ -4,18 +4,23 @@ use std::convert::From;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs;
+use std::io;
 use std::num::{ParseFloatError, ParseIntError};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
-use whisper::builder::WhisperBuilder;
+use whisper::builder::{BuilderError, WhisperBuilder};
+use whisper::error::Error as WhisperError;
 use whisper::point::Point;
-use whisper::WhisperFile;
+use whisper::r#async::WhisperFile as AsyncWhisperFile;
+use whisper::{OpenOptions, UpdateReport};
 
 pub mod settings;
+pub mod stats;
 
 use settings::Settings;
 use settings::WhisperConfig;
+use stats::UpdateStats;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MetricPoint {
@@ -159,44 +164,103 @@ impl From<PathBuf> for MetricPath {
     }
 }
 
-#[inline]
-pub fn line_update<P: AsRef<Path>>(
+fn metric_file_path<P: AsRef<Path>>(
     message: &str,
     dir: P,
-    config: &WhisperConfig,
-    now: u32,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(MetricPoint, PathBuf), Box<dyn Error>> {
     let metric: MetricPoint = message.parse()?;
     let metric_path: MetricPath = metric.name.parse()?;
 
     let file_path = dir.as_ref().join(metric_path.0);
+    Ok((metric, file_path))
+}
+
+fn whisper_builder(config: &WhisperConfig) -> WhisperBuilder {
+    WhisperBuilder::default()
+        .add_retentions(&config.retentions)
+        .x_files_factor(config.x_files_factor)
+        .aggregation_method(config.aggregation_method)
+        .lock(true)
+}
+
+fn open_options() -> OpenOptions {
+    OpenOptions::new().lock(true).cache_headers(true)
+}
+
+#[inline]
+pub fn line_update<P: AsRef<Path>>(
+    message: &str,
+    dir: P,
+    config: &WhisperConfig,
+    now: u32,
+) -> Result<UpdateReport, Box<dyn Error>> {
+    let (metric, file_path) = metric_file_path(message, dir)?;
 
     let mut file = if file_path.exists() {
-        WhisperFile::open(&file_path)?
+        open_options().open(&file_path)?
     } else {
         let dir_path = file_path.parent().unwrap();
         fs::create_dir_all(&dir_path)?;
 
-        WhisperBuilder::default()
-            .add_retentions(&config.retentions)
-            .x_files_factor(config.x_files_factor)
-            .aggregation_method(config.aggregation_method)
-            .build(&file_path)?
+        whisper_builder(config).build(&file_path)?
     };
 
-    file.update(&metric.point, now)?;
+    let report = file.update(&metric.point, now)?;
 
-    Ok(())
+    Ok(report)
 }
 
-#[inline]
-pub fn update_silently(line: &str, conf: &Settings) {
+/// Same as `line_update`, but file IO runs on the blocking pool.
+pub async fn line_update_async<P: AsRef<Path>>(
+    message: &str,
+    dir: P,
+    config: &WhisperConfig,
+    now: u32,
+) -> Result<UpdateReport, Box<dyn Error>> {
+    let (metric, file_path) = metric_file_path(message, dir)?;
+
+    let file = match AsyncWhisperFile::open_with(&file_path, open_options()).await {
+        Err(WhisperError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            let dir_path = file_path.parent().unwrap();
+            tokio::fs::create_dir_all(&dir_path).await?;
+
+            // Another task may have created the file in the meantime
+            match AsyncWhisperFile::create(whisper_builder(config), &file_path).await {
+                Err(BuilderError::Io(ref e)) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    AsyncWhisperFile::open_with(&file_path, open_options()).await?
+                }
+                result => result?,
+            }
+        }
+        result => result?,
+    };
+
+    let report = file.update(metric.point, now).await?;
+
+    Ok(report)
+}
+
+/// Update a metric from `line`, print errors and count the result in `stats`.
+pub async fn update_silently(line: &str, conf: &Settings, stats: &UpdateStats) {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs() as u32;
 
-    line_update(&line, &conf.db_path, &conf.whisper, now).unwrap_or_else(|e| eprintln!("{}", e));
+    match line_update_async(line, &conf.db_path, &conf.whisper, now).await {
+        Ok(report) => stats.record(&report),
+        // Points the file can't hold are counted instead of failing
+        Err(e) => match e.downcast_ref::<WhisperError>() {
+            Some(WhisperError::TimestampNotCovered(interval)) if *interval > now => {
+                stats.record_future()
+            }
+            Some(WhisperError::TimestampNotCovered(_)) => stats.record_too_old(),
+            _ => {
+                stats.record_failure();
+                eprintln!("{}", e);
+            }
+        },
+    }
 }
 
 #[cfg(test)]
@@ -210,6 +274,7 @@ mod tests {
     use tempfile::Builder;
     use whisper::aggregation::AggregationMethod;
     use whisper::retention::Retention;
+    use whisper::WhisperFile;
 
     #[test]
     fn metric_path_validate_ok() {
@@ -428,8 +493,8 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn update_silently_with_absent_wsp() -> Result<(), io::Error> {
+    #[tokio::test]
+    async fn update_silently_with_absent_wsp() -> Result<(), io::Error> {
         let dir = Builder::new()
             .prefix("diamond_silent")
             .tempdir()
@@ -465,7 +530,13 @@ mod tests {
 
         let message = format!("this.is.correct1 {} 124", timestamp);
 
-        update_silently(&message, &config);
+        let stats = UpdateStats::default();
+        update_silently(&message, &config, &stats).await;
+        update_silently("this.is.incorrect", &config, &stats).await;
+
+        assert_eq!(stats.received(), 2);
+        assert_eq!(stats.written(), 1);
+        assert_eq!(stats.failed(), 1);
 
         let file = dir.join("this").join("is").join("correct1.wsp");
         assert_eq!(
@@ -478,4 +549,89 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn line_update_async_creates_once() -> Result<(), Box<dyn Error>> {
+        let dir = Builder::new().prefix("diamond_create").tempdir()?;
+        let config = WhisperConfig {
+            x_files_factor: 0.5,
+            retentions: vec![Retention {
+                seconds_per_point: 1,
+                points: 1000,
+            }],
+            aggregation_method: AggregationMethod::Average,
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        let messages: Vec<String> = (1..=4)
+            .map(|i| format!("this.is.raced {} {}", now - i, i))
+            .collect();
+
+        // Every update races to create the same file
+        let (a, b, c, d) = tokio::join!(
+            line_update_async(&messages[0], dir.path(), &config, now),
+            line_update_async(&messages[1], dir.path(), &config, now),
+            line_update_async(&messages[2], dir.path(), &config, now),
+            line_update_async(&messages[3], dir.path(), &config, now),
+        );
+        for report in &[a, b, c, d] {
+            assert!(
+                report.is_ok(),
+                "{:?}",
+                report.as_ref().err().map(|e| e.to_string())
+            );
+        }
+
+        let file = dir.path().join("this").join("is").join("raced.wsp");
+        let values: Vec<f64> = WhisperFile::open(&file)?
+            .dump(1)?
+            .iter()
+            .filter(|point| point.interval != 0)
+            .map(|point| point.value)
+            .collect();
+        assert_eq!(values.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_silently_counts_uncovered_points() -> Result<(), Box<dyn Error>> {
+        let dir = Builder::new().prefix("diamond_uncovered").tempdir()?;
+        let config = Settings {
+            db_path: dir.path().to_path_buf(),
+            tcp: Net {
+                port: 6142,
+                host: V4("0.0.0.0".parse().unwrap()),
+            },
+            udp: Net {
+                port: 6142,
+                host: V4("0.0.0.0".parse().unwrap()),
+            },
+            whisper: WhisperConfig {
+                x_files_factor: 0.5,
+                retentions: vec![Retention {
+                    seconds_per_point: 1,
+                    points: 1000,
+                }],
+                aggregation_method: AggregationMethod::Average,
+            },
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
+        let stats = UpdateStats::default();
+        update_silently(&format!("this.is.old {} 1", now - 100_000), &config, &stats).await;
+        update_silently(
+            &format!("this.is.future {} 1", now + 100_000),
+            &config,
+            &stats,
+        )
+        .await;
+
+        assert_eq!(stats.received(), 2);
+        assert_eq!(stats.written(), 0);
+        assert_eq!(stats.too_old(), 1);
+        assert_eq!(stats.future(), 1);
+        assert_eq!(stats.failed(), 0);
+        Ok(())
+    }
 }
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use whisper::UpdateReport;
+
+/// Totals of the updates received by the server.
+#[derive(Debug, Default)]
+pub struct UpdateStats {
+    received: AtomicUsize,
+    failed: AtomicUsize,
+    written: AtomicUsize,
+    too_old: AtomicUsize,
+    future: AtomicUsize,
+    duplicates: AtomicUsize,
+}
+
+impl UpdateStats {
+    pub fn record(&self, report: &UpdateReport) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.written.fetch_add(
+            report.written_total() + report.future_written,
+            Ordering::Relaxed,
+        );
+        self.too_old.fetch_add(report.too_old, Ordering::Relaxed);
+        self.duplicates
+            .fetch_add(report.duplicates, Ordering::Relaxed);
+    }
+
+    /// Count a point older than the retention of its file, which was dropped.
+    pub fn record_too_old(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.too_old.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a point newer than now, which was dropped.
+    pub fn record_future(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.future.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self) -> usize {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    pub fn too_old(&self) -> usize {
+        self.too_old.load(Ordering::Relaxed)
+    }
+
+    pub fn future(&self) -> usize {
+        self.future.load(Ordering::Relaxed)
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for UpdateStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received {}, written {}, too old {}, future {}, duplicates {}, failed {}",
+            self.received(),
+            self.written(),
+            self.too_old(),
+            self.future(),
+            self.duplicates(),
+            self.failed()
+        )
+    }
+}
@@ -0,0 +1,211 @@
+use super::*;
+use crate::interval::Interval;
+use std::fmt;
+use std::path::Path;
+
+/**
+ * Options of `aggregate`.
+ *
+ * Values of the sources are combined with the aggregation method when the
+ * share of sources with a known value is at least the xFilesFactor. A
+ * missing destination is created with the schema, or like the first source.
+ */
+#[derive(Clone)]
+pub struct AggregateOptions {
+    aggregation_method: AggregationMethod,
+    x_files_factor: f32,
+    schema: Option<WhisperBuilder>,
+}
+
+impl AggregateOptions {
+    pub fn new(aggregation_method: AggregationMethod) -> Self {
+        Self {
+            aggregation_method,
+            x_files_factor: 0.5,
+            schema: None,
+        }
+    }
+
+    pub fn x_files_factor(mut self, x_files_factor: f32) -> Self {
+        self.x_files_factor = x_files_factor;
+        self
+    }
+
+    /// Create a missing destination with `schema`.
+    pub fn schema(mut self, schema: WhisperBuilder) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+}
+
+/// Totals of `aggregate`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AggregateReport {
+    /// Destination didn't exist and was created.
+    pub created: bool,
+    /// Number of points written to the destination archives.
+    pub written: usize,
+    /// Number of points not written, because too few sources have a value.
+    pub incomplete: usize,
+}
+
+impl fmt::Display for AggregateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created {}, written {}, incomplete {}",
+            self.created, self.written, self.incomplete
+        )
+    }
+}
+
+/**
+ * Combine the points of every source into the destination, archive by
+ * archive, from time_from to time_to. All files are locked while they are
+ * accessed.
+ *
+ * Sources with an archive of the same precision as a destination archive are
+ * read as is, other sources are resampled like `merge` does.
+ */
+pub fn aggregate<P: AsRef<Path>>(
+    paths_src: &[P],
+    path_dst: &Path,
+    time_from: u32,
+    time_to: u32,
+    now: u32,
+    options: &AggregateOptions,
+) -> Result<AggregateReport, Error> {
+    if time_to < time_from {
+        return Err(Error::InvalidInterval(
+            "time_to must be >= time_from".to_owned(),
+        ));
+    }
+    if paths_src.is_empty() {
+        return Err(Error::NoData);
+    }
+
+    let open_options = OpenOptions::new().lock(true);
+    let mut files_src = paths_src
+        .iter()
+        .map(|path| open_options.open(path))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut report = AggregateReport::default();
+    if !path_dst.exists() {
+        let schema = match options.schema {
+            Some(ref schema) => schema.clone(),
+            None => WhisperBuilder::from(files_src[0].info()),
+        };
+        schema.lock(true).build(path_dst)?;
+        report.created = true;
+    }
+    let mut file_dst = open_options.open(path_dst)?;
+
+    let metadata = file_dst.info().clone();
+    for (index, archive) in metadata.archives.iter().enumerate() {
+        let step = archive.seconds_per_point;
+        let from = adjust_instant_up(u32::max(time_from, now - archive.retention()), step);
+        let until = adjust_instant_up(u32::min(time_to, now), step);
+
+        let mut window_from = from;
+        while window_from < until {
+            let window_until =
+                u32::min(until, window_from.saturating_add(iter::CHUNK_POINTS * step));
+            let window = Interval::new(window_from, window_until).unwrap();
+
+            let mut columns = Vec::with_capacity(files_src.len());
+            for file_src in &mut files_src {
+                columns.push(source_values(
+                    file_src,
+                    window,
+                    step,
+                    metadata.archive_aggregation_method(archive),
+                    metadata.archive_x_files_factor(archive),
+                    now,
+                )?);
+            }
+
+            let mut points = Vec::new();
+            let mut row = Vec::with_capacity(columns.len());
+            for (i, interval) in (window_from..window_until)
+                .step_by(step as usize)
+                .enumerate()
+            {
+                row.clear();
+                row.extend(columns.iter().map(|column| column[i]));
+
+                let known = row.iter().filter(|value| value.is_some()).count();
+                if known == 0 {
+                    continue;
+                }
+                if (known as f32 / row.len() as f32) < options.x_files_factor {
+                    report.incomplete += 1;
+                    continue;
+                }
+                points.push(Point {
+                    interval,
+                    value: options
+                        .aggregation_method
+                        .aggregate(&row)
+                        .map_err(Error::Aggregation)?,
+                });
+            }
+
+            file_dst.update_archive(index, &points)?;
+            report.written += points.len();
+            window_from = window_until;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Value of the source for every `step` of `interval`.
+fn source_values(
+    file: &mut WhisperFile,
+    interval: Interval,
+    step: u32,
+    aggregation_method: AggregationMethod,
+    x_files_factor: f32,
+    now: u32,
+) -> Result<Vec<Option<f64>>, Error> {
+    let archive = match file
+        .info()
+        .archives
+        .iter()
+        .find(|archive| archive.seconds_per_point == step)
+    {
+        Some(archive) => *archive,
+        None => {
+            let values = resample::resample(
+                file,
+                interval,
+                step,
+                aggregation_method,
+                x_files_factor,
+                now,
+            )?;
+            return Ok(values.into_iter().map(|(_, value)| value).collect());
+        }
+    };
+
+    let count = ((interval.until() - interval.from()) / step) as usize;
+    let mut values = vec![None; count];
+
+    let from = u32::max(
+        interval.from(),
+        adjust_instant_up(now.saturating_sub(archive.retention()), step),
+    );
+    if from >= interval.until() {
+        return Ok(values);
+    }
+
+    let covered = Interval::new(from, interval.until()).map_err(Error::InvalidInterval)?;
+    for value in file.iter_archive(step, covered)?.values() {
+        let (instant, value) = value?;
+        if instant >= interval.from() && instant < interval.until() {
+            values[((instant - interval.from()) / step) as usize] = value;
+        }
+    }
+    Ok(values)
+}
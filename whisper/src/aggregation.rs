@@ -1,6 +1,5 @@
 use serde::*;
 use std::cmp;
-use std::convert::Into;
 use std::fmt;
 use std::str::FromStr;
 
@@ -14,8 +13,22 @@ fn cmp_f64_abs(a: &f64, b: &f64) -> cmp::Ordering {
     cmp_f64(&a.abs(), &b.abs())
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
-#[serde(rename_all = "lowercase")]
+fn sorted_values(values: &[Option<f64>]) -> Vec<f64> {
+    let mut sorted: Vec<f64> = values.iter().filter_map(|v| *v).collect();
+    sorted.sort_by(cmp_f64);
+    sorted
+}
+
+/// Type id of the first percentile method, `PERCENTILE_TYPE + p` is the id of `Percentile(p)`.
+const PERCENTILE_TYPE: u32 = 512;
+
+/**
+ * Aggregation method of an archive.
+ *
+ * Methods with type ids below 256 are the ones of Graphite whisper, the others
+ * are extensions which Graphite can't read.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AggregationMethod {
     Average,
     Sum,
@@ -25,11 +38,24 @@ pub enum AggregationMethod {
     AvgZero,
     AbsMax,
     AbsMin,
+    Median,
+    /// Number of known points.
+    Count,
+    /// Population standard deviation.
+    StdDev,
+    /// Nearest-rank percentile, between 0 and 100.
+    Percentile(u8),
 }
 
 impl AggregationMethod {
     pub fn from_type(aggregation_type: u32) -> Option<Self> {
         match aggregation_type {
+            256 => Some(AggregationMethod::Median),
+            257 => Some(AggregationMethod::Count),
+            258 => Some(AggregationMethod::StdDev),
+            t if (PERCENTILE_TYPE..=PERCENTILE_TYPE + 100).contains(&t) => {
+                Some(AggregationMethod::Percentile((t - PERCENTILE_TYPE) as u8))
+            }
             1 => Some(AggregationMethod::Average),
             2 => Some(AggregationMethod::Sum),
             3 => Some(AggregationMethod::Last),
@@ -52,9 +78,18 @@ impl AggregationMethod {
             AggregationMethod::AvgZero => 6,
             AggregationMethod::AbsMax => 7,
             AggregationMethod::AbsMin => 8,
+            AggregationMethod::Median => 256,
+            AggregationMethod::Count => 257,
+            AggregationMethod::StdDev => 258,
+            AggregationMethod::Percentile(p) => PERCENTILE_TYPE + u32::from(p),
         }
     }
 
+    /// The method is also supported by Graphite whisper.
+    pub fn is_graphite_compatible(self) -> bool {
+        self.to_type() < 256
+    }
+
     pub fn aggregate(self, values: &[Option<f64>]) -> Result<f64, &'static str> {
         match self {
             AggregationMethod::Average => {
@@ -98,6 +133,34 @@ impl AggregationMethod {
                 .filter_map(|v| *v)
                 .min_by(cmp_f64_abs)
                 .ok_or("Empty list of values"),
+            AggregationMethod::Median => {
+                let sorted = sorted_values(values);
+                let middle = sorted.len() / 2;
+                match sorted.len() {
+                    0 => Err("Empty list of values"),
+                    len if len % 2 == 0 => Ok((sorted[middle - 1] + sorted[middle]) / 2.0),
+                    _ => Ok(sorted[middle]),
+                }
+            }
+            AggregationMethod::Count => Ok(values.iter().filter(|v| v.is_some()).count() as f64),
+            AggregationMethod::StdDev => {
+                let known: Vec<f64> = values.iter().filter_map(|v| *v).collect();
+                if known.is_empty() {
+                    return Err("Empty list of values");
+                }
+                let count = known.len() as f64;
+                let mean = known.iter().sum::<f64>() / count;
+                let variance = known.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+                Ok(variance.sqrt())
+            }
+            AggregationMethod::Percentile(p) => {
+                let sorted = sorted_values(values);
+                if sorted.is_empty() {
+                    return Err("Empty list of values");
+                }
+                let rank = (f64::from(p) / 100.0 * sorted.len() as f64).ceil() as usize;
+                Ok(sorted[rank.max(1) - 1])
+            }
         }
     }
 }
@@ -121,30 +184,50 @@ impl FromStr for AggregationMethod {
             "avg_zero" => Ok(AggregationMethod::AvgZero),
             "absmax" => Ok(AggregationMethod::AbsMax),
             "absmin" => Ok(AggregationMethod::AbsMin),
-            _ => Err(format!("Unsupported aggregation method '{}'.", s)),
+            "median" => Ok(AggregationMethod::Median),
+            "count" => Ok(AggregationMethod::Count),
+            "stddev" => Ok(AggregationMethod::StdDev),
+            _ => match s.strip_prefix('p').map(u8::from_str) {
+                Some(Ok(p)) if p <= 100 => Ok(AggregationMethod::Percentile(p)),
+                _ => Err(format!("Unsupported aggregation method '{}'.", s)),
+            },
         }
     }
 }
 
-impl Into<&'static str> for AggregationMethod {
-    fn into(self) -> &'static str {
+impl fmt::Display for AggregationMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            AggregationMethod::Average => "average",
-            AggregationMethod::Sum => "sum",
-            AggregationMethod::Last => "last",
-            AggregationMethod::Max => "max",
-            AggregationMethod::Min => "min",
-            AggregationMethod::AvgZero => "avg_zero",
-            AggregationMethod::AbsMax => "absmax",
-            AggregationMethod::AbsMin => "absmin",
+            AggregationMethod::Average => write!(f, "average"),
+            AggregationMethod::Sum => write!(f, "sum"),
+            AggregationMethod::Last => write!(f, "last"),
+            AggregationMethod::Max => write!(f, "max"),
+            AggregationMethod::Min => write!(f, "min"),
+            AggregationMethod::AvgZero => write!(f, "avg_zero"),
+            AggregationMethod::AbsMax => write!(f, "absmax"),
+            AggregationMethod::AbsMin => write!(f, "absmin"),
+            AggregationMethod::Median => write!(f, "median"),
+            AggregationMethod::Count => write!(f, "count"),
+            AggregationMethod::StdDev => write!(f, "stddev"),
+            AggregationMethod::Percentile(p) => write!(f, "p{}", p),
         }
     }
 }
 
-impl fmt::Display for AggregationMethod {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s: &str = (*self).into();
-        write!(f, "{}", s)
+impl Serialize for AggregationMethod {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AggregationMethod {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            // Spelling accepted before methods were parsed with `FromStr`
+            "avgzero" => Ok(AggregationMethod::AvgZero),
+            s => AggregationMethod::from_str(s).map_err(de::Error::custom),
+        }
     }
 }
 
@@ -162,6 +245,10 @@ mod tests {
         assert_eq!(AggregationMethod::AvgZero.to_string(), "avg_zero");
         assert_eq!(AggregationMethod::AbsMax.to_string(), "absmax");
         assert_eq!(AggregationMethod::AbsMin.to_string(), "absmin");
+        assert_eq!(AggregationMethod::Median.to_string(), "median");
+        assert_eq!(AggregationMethod::Count.to_string(), "count");
+        assert_eq!(AggregationMethod::StdDev.to_string(), "stddev");
+        assert_eq!(AggregationMethod::Percentile(95).to_string(), "p95");
 
         assert_eq!(AggregationMethod::default().to_string(), "average");
     }
@@ -201,7 +288,24 @@ mod tests {
             Ok(AggregationMethod::AbsMin)
         );
 
+        for method in &[
+            AggregationMethod::Median,
+            AggregationMethod::Count,
+            AggregationMethod::StdDev,
+            AggregationMethod::Percentile(0),
+            AggregationMethod::Percentile(99),
+            AggregationMethod::Percentile(100),
+        ] {
+            assert_eq!(
+                AggregationMethod::from_str(&method.to_string()),
+                Ok(*method)
+            );
+        }
+
         assert!(AggregationMethod::from_str("test").is_err());
+        assert!(AggregationMethod::from_str("p101").is_err());
+        assert!(AggregationMethod::from_str("p").is_err());
+        assert!(AggregationMethod::from_str("p-1").is_err());
     }
 
     #[test]
@@ -314,7 +418,37 @@ mod tests {
             Ok(-1.0)
         );
 
+        let values = [Some(4.0), None, Some(1.0), Some(3.0), None, Some(2.0)];
+        assert_eq!(AggregationMethod::Median.aggregate(&values), Ok(2.5));
+        assert_eq!(AggregationMethod::Median.aggregate(&values[..4]), Ok(3.0));
+        assert_eq!(AggregationMethod::Count.aggregate(&values), Ok(4.0));
+        assert_eq!(
+            AggregationMethod::StdDev.aggregate(&[
+                Some(2.0),
+                Some(4.0),
+                None,
+                Some(4.0),
+                Some(6.0)
+            ]),
+            Ok(2.0_f64.sqrt())
+        );
+        assert_eq!(AggregationMethod::Percentile(0).aggregate(&values), Ok(1.0));
+        assert_eq!(
+            AggregationMethod::Percentile(50).aggregate(&values),
+            Ok(2.0)
+        );
+        assert_eq!(
+            AggregationMethod::Percentile(95).aggregate(&values),
+            Ok(4.0)
+        );
+        assert_eq!(
+            AggregationMethod::Percentile(100).aggregate(&values),
+            Ok(4.0)
+        );
+
         assert!(AggregationMethod::Last.aggregate(&[]).is_err());
+        assert!(AggregationMethod::Median.aggregate(&[None]).is_err());
+        assert!(AggregationMethod::Percentile(95).aggregate(&[]).is_err());
     }
 
     #[test]
@@ -325,5 +459,23 @@ mod tests {
         }
 
         assert_eq!(AggregationMethod::from_type(9), None);
+
+        for &i in &[256, 257, 258, 512, 595, 612] {
+            let method = AggregationMethod::from_type(i).unwrap();
+            assert!(!method.is_graphite_compatible());
+            assert_eq!(AggregationMethod::to_type(method), i);
+        }
+
+        assert_eq!(AggregationMethod::from_type(259), None);
+        assert_eq!(AggregationMethod::from_type(613), None);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let method: AggregationMethod = serde_json::from_str("\"p95\"").unwrap();
+        assert_eq!(method, AggregationMethod::Percentile(95));
+        let method: AggregationMethod = serde_json::from_str("\"avgzero\"").unwrap();
+        assert_eq!(method, AggregationMethod::AvgZero);
+        assert!(serde_json::from_str::<AggregationMethod>("\"unknown\"").is_err());
     }
 }
@@ -1,13 +1,24 @@
+use crate::aggregation::AggregationMethod;
+use crate::error::Error;
 use crate::point::Point;
 use crate::POINT_SIZE;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
 use std::io;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ArchiveInfo {
+    /// Position in the file, optional in schemas.
+    #[serde(default)]
     pub offset: u32,
     pub seconds_per_point: u32,
     pub points: u32,
+    /// Aggregation method of this archive, stored only in extended headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation_method: Option<AggregationMethod>,
+    /// xFilesFactor of this archive, stored only in extended headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_files_factor: Option<f32>,
 }
 
 impl ArchiveInfo {
@@ -27,6 +38,28 @@ impl ArchiveInfo {
             offset,
             seconds_per_point,
             points,
+            aggregation_method: None,
+            x_files_factor: None,
+        })
+    }
+
+    /// Read an archive info of an extended header, followed by its aggregation method and xFilesFactor.
+    pub fn read_extended<R: io::Read>(read: &mut R) -> Result<Self, Error> {
+        let info = Self::read(read)?;
+
+        let aggregation_type = read.read_u32::<BigEndian>()?;
+        let aggregation_method = AggregationMethod::from_type(aggregation_type)
+            .ok_or(Error::BadAggregationMethod(aggregation_type))?;
+
+        let x_files_factor = read.read_f32::<BigEndian>()?;
+        if !(0.0..=1.0).contains(&x_files_factor) {
+            return Err(Error::BadXFilesFactor(x_files_factor));
+        }
+
+        Ok(Self {
+            aggregation_method: Some(aggregation_method),
+            x_files_factor: Some(x_files_factor),
+            ..info
         })
     }
 
@@ -37,6 +70,30 @@ impl ArchiveInfo {
         Ok(())
     }
 
+    /// Write an archive info of an extended header, unset settings fall back to the given ones.
+    pub fn write_extended<W: io::Write>(
+        &self,
+        write: &mut W,
+        aggregation_method: AggregationMethod,
+        x_files_factor: f32,
+    ) -> Result<(), io::Error> {
+        self.write(write)?;
+        write.write_u32::<BigEndian>(
+            self.aggregation_method
+                .unwrap_or(aggregation_method)
+                .to_type(),
+        )?;
+        write.write_f32::<BigEndian>(self.x_files_factor.unwrap_or(x_files_factor))?;
+        Ok(())
+    }
+
+    /// Offset, precision and number of points are the same, settings may differ.
+    pub fn same_layout(&self, other: &Self) -> bool {
+        self.offset == other.offset
+            && self.seconds_per_point == other.seconds_per_point
+            && self.points == other.points
+    }
+
     pub fn read_base<R: io::Read + io::Seek>(&self, r: &mut R) -> Result<Point, io::Error> {
         r.seek(io::SeekFrom::Start(self.offset.into()))?;
         let base = Point::read(r)?;
@@ -54,6 +111,8 @@ mod tests {
             offset: 10,
             seconds_per_point: 2,
             points: 20,
+            aggregation_method: None,
+            x_files_factor: None,
         };
         assert_eq!(info.retention(), 20 * 2);
     }
@@ -64,7 +123,34 @@ mod tests {
             offset: 10,
             seconds_per_point: 2,
             points: 20,
+            aggregation_method: None,
+            x_files_factor: None,
         };
         assert_eq!(info.size(), 20 * 12);
     }
+
+    #[test]
+    fn test_extended_round_trip() -> Result<(), io::Error> {
+        let info = ArchiveInfo {
+            offset: 10,
+            seconds_per_point: 2,
+            points: 20,
+            aggregation_method: None,
+            x_files_factor: Some(0.9),
+        };
+
+        let mut bytes = Vec::new();
+        info.write_extended(&mut bytes, AggregationMethod::Max, 0.5)?;
+        assert_eq!(bytes.len(), crate::EXTENDED_ARCHIVE_INFO_SIZE);
+
+        let read = ArchiveInfo::read_extended(&mut io::Cursor::new(bytes))?;
+        assert_eq!(
+            read,
+            ArchiveInfo {
+                aggregation_method: Some(AggregationMethod::Max),
+                ..info
+            }
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,158 @@
+use crate::builder::{BuilderError, WhisperBuilder};
+use crate::error::Error;
+use crate::interval::Interval;
+use crate::point::Point;
+use crate::{ArchiveData, OpenOptions, UpdateReport, WhisperMetadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
+
+/**
+ * Async wrapper of `WhisperFile`.
+ *
+ * Every operation runs on the tokio blocking pool, so slow disks don't stall
+ * the executor. Clones share the same file.
+ */
+#[derive(Clone)]
+pub struct WhisperFile {
+    inner: Arc<Mutex<crate::WhisperFile>>,
+}
+
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))?
+}
+
+impl WhisperFile {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with(path, OpenOptions::default()).await
+    }
+
+    pub async fn open_with<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<Self, Error> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let file = blocking(move || crate::WhisperFile::open_with(&path, options)).await?;
+        Ok(file.into())
+    }
+
+    /// Create a new file with `builder`, like `WhisperBuilder::build`.
+    pub async fn create<P: AsRef<Path>>(
+        builder: WhisperBuilder,
+        path: P,
+    ) -> Result<Self, BuilderError> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let file = spawn_blocking(move || builder.build(&path))
+            .await
+            .map_err(|e| BuilderError::Io(io::Error::other(e)))??;
+        Ok(file.into())
+    }
+
+    async fn with<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut crate::WhisperFile) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        blocking(move || {
+            let mut file = inner
+                .lock()
+                .map_err(|_| Error::Io(io::Error::other("Whisper file lock is poisoned")))?;
+            f(&mut file)
+        })
+        .await
+    }
+
+    pub async fn info(&self) -> Result<WhisperMetadata, Error> {
+        self.with(|file| Ok(file.info().clone())).await
+    }
+
+    pub async fn update(&self, point: Point, now: u32) -> Result<UpdateReport, Error> {
+        self.with(move |file| file.update(&point, now)).await
+    }
+
+    pub async fn update_many(&self, points: Vec<Point>, now: u32) -> Result<UpdateReport, Error> {
+        self.with(move |file| file.update_many(&points, now)).await
+    }
+
+    pub async fn fetch(
+        &self,
+        seconds_per_point: u32,
+        interval: Interval,
+        now: u32,
+    ) -> Result<ArchiveData, Error> {
+        self.with(move |file| file.fetch(seconds_per_point, interval, now))
+            .await
+    }
+
+    pub async fn fetch_auto_points(
+        &self,
+        interval: Interval,
+        now: u32,
+    ) -> Result<ArchiveData, Error> {
+        self.with(move |file| file.fetch_auto_points(interval, now))
+            .await
+    }
+
+    pub async fn fetch_stitched(
+        &self,
+        interval: Interval,
+        now: u32,
+    ) -> Result<Vec<ArchiveData>, Error> {
+        self.with(move |file| file.fetch_stitched(interval, now))
+            .await
+    }
+}
+
+impl From<crate::WhisperFile> for WhisperFile {
+    fn from(file: crate::WhisperFile) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(file)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    #[tokio::test]
+    async fn test_async_update_and_fetch() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("async.wsp");
+
+        let builder = WhisperBuilder::default().add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        });
+        let file = WhisperFile::create(builder, &path).await?;
+        file.update_many(
+            vec![
+                Point {
+                    interval: NOW - 120,
+                    value: 1.0,
+                },
+                Point {
+                    interval: NOW - 60,
+                    value: 2.0,
+                },
+            ],
+            NOW,
+        )
+        .await?;
+
+        let file = WhisperFile::open(&path).await?;
+        assert_eq!(file.info().await?.archives[0].points, 10);
+
+        let data = file.fetch(60, Interval::new(NOW - 180, NOW)?, NOW).await?;
+        assert_eq!(data.values, vec![None, Some(1.0), Some(2.0)]);
+        Ok(())
+    }
+}
@@ -2,14 +2,16 @@ use std::fs::remove_file;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use walkdir::WalkDir;
+use whisper::check::check;
 
 /// Find and (optionally) delete corrupt Whisper data files.
 #[derive(Debug, StructOpt)]
 #[structopt(name = "find-corrupt-whisper-files")]
 struct Args {
-    /// Delete reported files.
+    /// Delete files with an unreadable header or a wrong size.
     #[structopt(long = "delete-corrupt")]
     delete_corrupt: bool,
 
@@ -17,6 +19,10 @@ struct Args {
     #[structopt(long = "verbose")]
     verbose: bool,
 
+    /// Print a JSON report per file, one per line.
+    #[structopt(long = "json")]
+    json: bool,
+
     /// Directory containing Whisper files.
     #[structopt(
         name = "WHISPER_DIR",
@@ -31,15 +37,13 @@ fn is_whisper_file(path: &Path) -> bool {
     path.extension() == Some(std::ffi::OsStr::new("wsp"))
 }
 
-fn walk_dir(dir: &Path, delete_corrupt: bool, verbose: bool) -> io::Result<()> {
+fn walk_dir(dir: &Path, args: &Args, now: u32) -> io::Result<()> {
     for entry in WalkDir::new(dir).min_depth(1) {
         match entry {
-            Ok(ref entry) if verbose && entry.file_type().is_dir() => {
+            Ok(ref entry) if args.verbose && entry.file_type().is_dir() => {
                 println!("Scanning {}...", entry.path().canonicalize()?.display())
             }
-            Ok(ref entry) if is_whisper_file(entry.path()) => {
-                delete_corrupt_file(&entry.path(), delete_corrupt)?
-            }
+            Ok(ref entry) if is_whisper_file(entry.path()) => check_file(entry.path(), args, now)?,
             Err(e) => eprintln!("{}", e),
             _ => {}
         }
@@ -48,28 +52,38 @@ fn walk_dir(dir: &Path, delete_corrupt: bool, verbose: bool) -> io::Result<()> {
     Ok(())
 }
 
-fn delete_corrupt_file(file: &Path, delete_corrupt: bool) -> io::Result<()> {
-    match whisper::WhisperFile::open(file) {
-        Ok(whisper_file) => {
-            let x: u32 = whisper_file.info().archives.iter().map(|a| a.points).sum();
-            println!("{}: {} points", file.canonicalize()?.display(), x);
-        }
-        _ => {
-            if delete_corrupt {
-                eprintln!(
-                    "Deleting corrupt Whisper file: {}",
-                    file.canonicalize()?.display()
-                );
-                remove_file(file)?;
-            } else {
-                eprintln!("Corrupt Whisper file: {}", file.canonicalize()?.display());
+fn check_file(file: &Path, args: &Args, now: u32) -> io::Result<()> {
+    let path = file.canonicalize()?;
+    let mut report = check(&path, now)?;
+    report.path = path.display().to_string();
+
+    if args.json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else if report.is_ok() {
+        print!("{}", report);
+    } else {
+        eprint!("{}", report);
+    }
+
+    if !report.is_ok() {
+        if args.delete_corrupt && report.is_fatal() {
+            if !args.json {
+                eprintln!("Deleting corrupt Whisper file: {}", path.display());
             }
+            remove_file(file)?;
+        } else if !args.json {
+            eprintln!("Corrupt Whisper file: {}", path.display());
         }
     }
     Ok(())
 }
 
 fn run(args: &Args) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(io::Error::other)?
+        .as_secs() as u32;
+
     for dir in &args.directories {
         if !dir.is_dir() {
             eprintln!("{} is not a directory or not exist!", dir.display());
@@ -80,7 +94,7 @@ fn run(args: &Args) -> io::Result<()> {
             println!("Scanning {}...", dir.canonicalize()?.display());
         }
 
-        walk_dir(dir, args.delete_corrupt, args.verbose)?;
+        walk_dir(dir, args, now)?;
     }
 
     Ok(())
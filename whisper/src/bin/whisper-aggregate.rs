@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+use whisper::aggregate::{aggregate, AggregateOptions};
+use whisper::aggregation::AggregationMethod;
+use whisper::retention::Retention;
+use whisper::WhisperBuilder;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-aggregate")]
+struct Args {
+    /// Function to combine the values of the sources
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
+    #[structopt(long = "aggregationMethod", default_value = "sum")]
+    aggregation_method: AggregationMethod,
+
+    /// Minimal share of sources with a known value to write a point
+    #[structopt(long = "xFilesFactor", default_value = "0.5")]
+    x_files_factor: f32,
+
+    /// Create a missing destination with this retention, see whisper-create; repeat it for more
+    /// archives. Otherwise it is created like the first source
+    #[structopt(long = "retention", number_of_values = 1)]
+    retentions: Vec<Retention>,
+
+    /// Begining of interval, unix timestamp (default: epoch)
+    #[structopt(long = "from")]
+    from: Option<u32>,
+
+    /// End of interval, unix timestamp (default: now)
+    #[structopt(long = "until")]
+    until: Option<u32>,
+
+    /// Path to the destination data file
+    #[structopt(name = "destination", parse(from_os_str))]
+    destination: PathBuf,
+
+    /// Paths to the source data files
+    #[structopt(name = "source", parse(from_os_str), required = true, min_values = 1)]
+    sources: Vec<PathBuf>,
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    for filename in &args.sources {
+        if !filename.is_file() {
+            return Err(format!("[ERROR] File \"{:?}\" does not exist!", filename).into());
+        }
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let from = args.from.unwrap_or(0);
+    let until = args.until.unwrap_or(now);
+
+    let mut options =
+        AggregateOptions::new(args.aggregation_method).x_files_factor(args.x_files_factor);
+    if !args.retentions.is_empty() {
+        options = options.schema(WhisperBuilder::default().add_retentions(&args.retentions));
+    }
+
+    let report = aggregate(&args.sources, &args.destination, from, until, now, &options)?;
+    println!("{}", report);
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
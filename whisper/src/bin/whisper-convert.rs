@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use whisper::compressed;
+
+/// Convert whisper files between the standard and the compressed (go-whisper) formats.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-convert")]
+struct Args {
+    /// Convert to the compressed format
+    #[structopt(
+        long = "compressed",
+        conflicts_with = "standard",
+        required_unless = "standard"
+    )]
+    compressed: bool,
+
+    /// Convert to the standard format
+    #[structopt(long = "standard")]
+    standard: bool,
+
+    /// Points per compressed block
+    #[structopt(long = "points-per-block", default_value = "7200")]
+    points_per_block: u32,
+
+    /// Path to data file
+    #[structopt(name = "path", parse(from_os_str))]
+    path: PathBuf,
+
+    /// Write the converted database to a new file instead of replacing the existing one
+    #[structopt(name = "newfile", parse(from_os_str))]
+    newfile: Option<PathBuf>,
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let is_compressed = compressed::is_compressed(&mut fs::File::open(&args.path)?)?;
+
+    let bytes = if args.standard {
+        if !is_compressed {
+            return Err(format!("{} is not compressed", args.path.display()).into());
+        }
+        compressed::open(&args.path)?.into_inner().into_inner()
+    } else {
+        if is_compressed {
+            return Err(format!("{} is already compressed", args.path.display()).into());
+        }
+        let mut file = whisper::OpenOptions::new().lock(true).open(&args.path)?;
+        let mut bytes = Vec::new();
+        compressed::compress(&mut file, args.points_per_block, &mut bytes)?;
+        bytes
+    };
+
+    let target = args.newfile.as_ref().unwrap_or(&args.path);
+    let tmpfile = PathBuf::from(format!("{}.tmp", target.display()));
+    fs::write(&tmpfile, &bytes)?;
+    fs::rename(&tmpfile, target)?;
+
+    println!(
+        "Converted {} to the {} format: {} ({} bytes)",
+        args.path.display(),
+        if args.compressed {
+            "compressed"
+        } else {
+            "standard"
+        },
+        target.display(),
+        bytes.len()
+    );
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
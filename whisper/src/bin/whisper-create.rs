@@ -1,12 +1,12 @@
 use humansize::{file_size_opts as options, FileSize};
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use structopt::StructOpt;
 use whisper::aggregation::AggregationMethod;
 use whisper::retention::Retention;
-use whisper::WhisperBuilder;
+use whisper::{WhisperBuilder, WhisperMetadata};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "whisper-create")]
@@ -27,14 +27,24 @@ struct Args {
     #[structopt(long = "fallocate")]
     fallocate: bool,
 
-    /// XFILESFACTOR
-    #[structopt(long = "xFilesFactor", default_value = "0.5")]
-    x_files_factor: f32,
+    /// XFILESFACTOR, 0.5 if there is no template
+    #[structopt(long = "xFilesFactor")]
+    x_files_factor: Option<f32>,
 
-    /// Function to use when aggregating values
-    /// (average, sum, last, max, min, avg_zero, absmax, absmin)
-    #[structopt(long = "aggregationMethod", default_value = "average")]
-    aggregation_method: AggregationMethod,
+    /// Function to use when aggregating values, average if there is no template
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
+    #[structopt(long = "aggregationMethod")]
+    aggregation_method: Option<AggregationMethod>,
+
+    /// Copy retentions, aggregation and xFilesFactor of an existing file
+    #[structopt(long = "like", parse(from_os_str), conflicts_with = "schema")]
+    like: Option<PathBuf>,
+
+    /// Read retentions, aggregation and xFilesFactor from a schema file (.toml or .json),
+    /// in the form of serialized WhisperMetadata
+    #[structopt(long = "schema", parse(from_os_str))]
+    schema: Option<PathBuf>,
 
     /// Path to data file
     #[structopt(name = "path", parse(from_os_str))]
@@ -48,7 +58,8 @@ struct Args {
 1h:7d        1 hour per datapoint, 7 days of retention
 12h:2y       12 hours per datapoint, 2 years of retention
 "#,
-        required = true,
+        required_unless_one = &["like", "schema"],
+        conflicts_with_all = &["like", "schema"],
         min_values = 1
     )]
     retentions: Vec<Retention>,
@@ -95,9 +106,34 @@ fn estimate_info(retentions: &[Retention]) {
     }
 }
 
+fn read_schema(path: &Path) -> Result<WhisperMetadata, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let schema = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        _ => serde_json::from_str(&content)?,
+    };
+    Ok(schema)
+}
+
+fn template(args: &Args) -> Result<Option<WhisperMetadata>, Box<dyn Error>> {
+    if let Some(ref like) = args.like {
+        let file = whisper::OpenOptions::new().lock(true).open(like)?;
+        return Ok(Some(file.info().clone()));
+    }
+    match args.schema {
+        Some(ref schema) => Ok(Some(read_schema(schema)?)),
+        None => Ok(None),
+    }
+}
+
 fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let template = template(args)?;
+
     if args.estimate {
-        estimate_info(&args.retentions);
+        match template {
+            Some(ref template) => estimate_info(&template.retentions()),
+            None => estimate_info(&args.retentions),
+        }
     } else {
         if args.overwrite && args.path.exists() {
             println!(
@@ -107,12 +143,24 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
             fs::remove_file(&args.path)?;
         }
 
-        WhisperBuilder::default()
-            .add_retentions(&args.retentions)
-            .x_files_factor(args.x_files_factor)
-            .aggregation_method(args.aggregation_method)
-            .sparse(args.sparse)
-            .build(&args.path)?;
+        let builder = match template {
+            Some(mut template) => {
+                // Explicit settings also replace the ones of every archive of the template
+                if let Some(x_files_factor) = args.x_files_factor {
+                    template.set_x_files_factor(x_files_factor);
+                }
+                if let Some(aggregation_method) = args.aggregation_method {
+                    template.set_aggregation_method(aggregation_method);
+                }
+                WhisperBuilder::from(&template)
+            }
+            None => WhisperBuilder::default()
+                .add_retentions(&args.retentions)
+                .x_files_factor(args.x_files_factor.unwrap_or(0.5))
+                .aggregation_method(args.aggregation_method.unwrap_or_default()),
+        };
+
+        builder.sparse(args.sparse).lock(true).build(&args.path)?;
 
         let size = args.path.metadata()?.len();
         println!("Created: {} ({} bytes)", &args.path.to_str().unwrap(), size);
@@ -5,7 +5,8 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use whisper::diff;
 use whisper::diff::{
-    DiffArchiveInfo, DiffArchiveShort, DiffArchiveSummary, DiffHeader, DiffSummaryHeader,
+    DiffArchiveInfo, DiffArchiveShort, DiffArchiveSummary, DiffHeader, DiffOptions,
+    DiffStatsHeader, DiffSummaryHeader,
 };
 
 #[derive(Debug, StructOpt)]
@@ -23,6 +24,10 @@ struct Args {
     #[structopt(long = "summary")]
     summary: bool,
 
+    /// Add max and mean deltas and the points over the tolerance to the summary
+    #[structopt(long = "stats")]
+    stats: bool,
+
     /// Skip comparison if either value is undefined
     #[structopt(long = "ignore-empty")]
     ignore_empty: bool,
@@ -35,6 +40,14 @@ struct Args {
     #[structopt(long = "no-headers")]
     no_headers: bool,
 
+    /// Values differing by at most this much are equal
+    #[structopt(long = "abs-tolerance", default_value = "0")]
+    abs_tolerance: f64,
+
+    /// Values differing by at most this fraction of the larger one are equal
+    #[structopt(long = "rel-tolerance", default_value = "0")]
+    rel_tolerance: f64,
+
     /// Unix epoch time of the end of your requested
     #[structopt(long = "until")]
     until: Option<u32>,
@@ -76,12 +89,16 @@ fn print_summary(
     if json {
         println!("{}", serde_json::to_string_pretty(diff)?);
     } else if !columns {
-        if !no_headers {
+        if !no_headers && diff.stats {
+            println!("{:#}", DiffStatsHeader {});
+        } else if !no_headers {
             println!("{:#}", DiffSummaryHeader {});
         }
         print!("{:#}", diff);
     } else {
-        if !no_headers {
+        if !no_headers && diff.stats {
+            println!("{}", DiffStatsHeader {});
+        } else if !no_headers {
             println!("{}", DiffSummaryHeader {});
         }
         print!("{}", diff);
@@ -100,7 +117,11 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
     let until = args.until.unwrap_or(now);
 
-    let diff_raw = diff::diff(&args.path_a, &args.path_b, args.ignore_empty, until, now)?;
+    let options = DiffOptions::new()
+        .ignore_empty(args.ignore_empty)
+        .absolute_tolerance(args.abs_tolerance)
+        .relative_tolerance(args.rel_tolerance);
+    let diff_raw = diff::diff_with(&args.path_a, &args.path_b, until, now, options)?;
 
     if args.summary {
         let short_diff: Vec<DiffArchiveShort> =
@@ -110,6 +131,7 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
             path_a: args.path_a.display().to_string(),
             path_b: args.path_b.display().to_string(),
             archives: short_diff,
+            stats: args.stats,
         };
         print_summary(&diff_rich, args.json, args.columns, args.no_headers)?;
     } else {
@@ -1,8 +1,9 @@
 use chrono::prelude::NaiveDateTime;
-use std::io;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::exit;
 use structopt::StructOpt;
+use whisper::dump::{Dump, DumpFormat};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "whisper-dump")]
@@ -11,13 +12,36 @@ struct Args {
     #[structopt(long = "time-format", short = "t")]
     time_format: Option<String>,
 
+    /// Dump header and raw slots in a format whisper-restore reads: json or csv
+    #[structopt(long = "format")]
+    format: Option<DumpFormat>,
+
+    /// Pretty print json
+    #[structopt(long = "pretty")]
+    pretty: bool,
+
     /// Path to data file
     #[structopt(name = "path", parse(from_os_str))]
     path: PathBuf,
 }
 
 fn run(args: &Args) -> io::Result<()> {
-    let mut file = whisper::WhisperFile::open(&args.path)?;
+    let mut file = whisper::OpenOptions::new().lock(true).open(&args.path)?;
+
+    if let Some(format) = args.format {
+        let dump = Dump::read(&mut file)?;
+        let stdout = io::stdout();
+        let mut out = io::BufWriter::new(stdout.lock());
+        match format {
+            DumpFormat::Json if args.pretty => serde_json::to_writer_pretty(&mut out, &dump)?,
+            DumpFormat::Json => serde_json::to_writer(&mut out, &dump)?,
+            DumpFormat::Csv => dump.write_csv(&mut out)?,
+        }
+        if format == DumpFormat::Json {
+            writeln!(out)?;
+        }
+        return out.flush();
+    }
 
     let meta = file.info().clone();
     println!("Meta data:");
@@ -37,10 +61,9 @@ fn run(args: &Args) -> io::Result<()> {
     }
 
     for (i, archive) in meta.archives.iter().enumerate() {
-        let points = file.dump(archive.seconds_per_point)?;
-
         println!("Archive {} data:", i);
-        for (j, point) in points.iter().enumerate() {
+        for (j, point) in file.iter_slots(archive.seconds_per_point)?.enumerate() {
+            let point = point?;
             match args.time_format {
                 Some(ref time_format) => {
                     let timestr = NaiveDateTime::from_timestamp(i64::from(point.interval), 0)
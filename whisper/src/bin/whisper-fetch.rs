@@ -6,7 +6,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 use whisper::interval::Interval;
-use whisper::WhisperFile;
+use whisper::OpenOptions;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "whisper-fetch")]
@@ -62,7 +62,7 @@ fn run(args: &Args) -> Result<(), Box<dyn Error>> {
     let until = args.until.unwrap_or(now);
 
     let interval = Interval::new(from, until)?;
-    let mut file = WhisperFile::open(&args.path)?;
+    let mut file = OpenOptions::new().lock(true).open(&args.path)?;
 
     let seconds_per_point = file
         .suggest_archive(interval, now)
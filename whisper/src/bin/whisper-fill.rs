@@ -9,7 +9,7 @@ use whisper::fill::fill;
 #[derive(Debug, StructOpt)]
 #[structopt(name = "whisper-fill")]
 struct Args {
-    /// Lock whisper files (is not implemented).
+    /// Lock whisper files.
     #[structopt(long = "lock")]
     lock: bool,
 
@@ -24,7 +24,7 @@ struct Args {
 
 fn run(args: &Args) -> Result<(), Box<dyn Error>> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
-    fill(&args.src, &args.dst, now, now)?;
+    fill(&args.src, &args.dst, now, now, args.lock)?;
     Ok(())
 }
 
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+use whisper::aggregation::AggregationMethod;
+use whisper::import::{import, ImportData, ImportFormat, ImportOptions, ImportTarget};
+use whisper::retention::Retention;
+use whisper::WhisperBuilder;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-import")]
+struct Args {
+    /// Format of the input: csv (metric,timestamp,value), json (output of whisper-fetch --json)
+    /// or line (metric timestamp value)
+    #[structopt(long = "format", default_value = "csv")]
+    format: ImportFormat,
+
+    /// Target is a directory, metric a.b.c goes to a/b/c.wsp under it
+    #[structopt(long = "tree")]
+    tree: bool,
+
+    /// Metric name of the points without one, like the ones of json input
+    #[structopt(long = "metric")]
+    metric: Option<String>,
+
+    /// Create missing files with this retention, see whisper-create; repeat it for more archives
+    #[structopt(long = "retention", number_of_values = 1)]
+    retentions: Vec<Retention>,
+
+    /// XFILESFACTOR of created files
+    #[structopt(long = "xFilesFactor", default_value = "0.5")]
+    x_files_factor: f32,
+
+    /// Function to use when aggregating values in created files
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
+    #[structopt(long = "aggregationMethod", default_value = "average")]
+    aggregation_method: AggregationMethod,
+
+    /// Number of points written at once
+    #[structopt(long = "batch-size", default_value = "10000")]
+    batch_size: usize,
+
+    /// Path to data file, or to the directory with --tree
+    #[structopt(name = "target", parse(from_os_str))]
+    target: PathBuf,
+
+    /// Input files, standard input if none is given
+    #[structopt(name = "input", parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
+    let mut data = ImportData::new();
+    let metric = args.metric.as_deref();
+    if args.inputs.is_empty() {
+        data.read(io::stdin().lock(), args.format, metric)?;
+    } else {
+        for input in &args.inputs {
+            data.read(BufReader::new(File::open(input)?), args.format, metric)?;
+        }
+    }
+
+    let target = if args.tree {
+        ImportTarget::Tree(args.target.clone())
+    } else {
+        ImportTarget::File(args.target.clone())
+    };
+
+    let mut options = ImportOptions::new().batch_size(args.batch_size);
+    if !args.retentions.is_empty() {
+        let schema = WhisperBuilder::default()
+            .add_retentions(&args.retentions)
+            .x_files_factor(args.x_files_factor)
+            .aggregation_method(args.aggregation_method);
+        options = options.schema(schema);
+    }
+
+    let report = import(&data, &target, &options, now)?;
+    println!("{}", report);
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
@@ -28,13 +28,22 @@ fn format_info(meta: &whisper::WhisperMetadata, json: bool) -> Result<(), Box<dy
             "fileSize": &meta.file_size(),
             "archives": &meta.archives
                 .iter()
-                .map(|a| json!({
-                    "retention": a.retention(),
-                    "secondsPerPoint": a.seconds_per_point,
-                    "points": a.points,
-                    "size": a.size(),
-                    "offset": a.offset,
-                    }))
+                .map(|a| {
+                    let mut archive = json!({
+                        "retention": a.retention(),
+                        "secondsPerPoint": a.seconds_per_point,
+                        "points": a.points,
+                        "size": a.size(),
+                        "offset": a.offset,
+                    });
+                    if let Some(method) = a.aggregation_method {
+                        archive["aggregationMethod"] = json!(method.to_string());
+                    }
+                    if let Some(x_files_factor) = a.x_files_factor {
+                        archive["xFilesFactor"] = json!(x_files_factor);
+                    }
+                    archive
+                })
                 .collect::<Vec<_>>()
         });
         println!("{}", serde_json::to_string_pretty(&john)?);
@@ -52,6 +61,12 @@ fn format_info(meta: &whisper::WhisperMetadata, json: bool) -> Result<(), Box<dy
             println!("points: {}", &archive.points);
             println!("size: {}", &archive.size());
             println!("offset: {}", &archive.offset);
+            if let Some(method) = archive.aggregation_method {
+                println!("aggregationMethod: {}", method);
+            }
+            if let Some(x_files_factor) = archive.x_files_factor {
+                println!("xFilesFactor: {}", x_files_factor);
+            }
             println!();
         }
     }
@@ -60,7 +75,7 @@ fn format_info(meta: &whisper::WhisperMetadata, json: bool) -> Result<(), Box<dy
 }
 
 fn run(args: &Args) -> Result<(), Box<dyn Error>> {
-    let file = whisper::WhisperFile::open(&args.path)?;
+    let file = whisper::OpenOptions::new().lock(true).open(&args.path)?;
     let meta = file.info();
 
     match &args.field {
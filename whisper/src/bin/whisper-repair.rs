@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+use whisper::repair::repair;
+
+/// Repair truncated whisper files, misaligned or expired points and a broken maxRetention.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-repair")]
+struct Args {
+    /// Only print what would change
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Output results in JSON form
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Path to data files
+    #[structopt(name = "path", parse(from_os_str), required = true, min_values = 1)]
+    paths: Vec<PathBuf>,
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+
+    for path in &args.paths {
+        let report = repair(path, now, args.dry_run)?;
+        if args.json {
+            println!("{}", serde_json::to_string(&report)?);
+        } else if report.changes.is_empty() {
+            println!("{}: OK", report.path);
+        } else {
+            print!("{}", report);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
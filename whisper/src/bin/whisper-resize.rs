@@ -5,8 +5,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 use whisper::aggregation::AggregationMethod;
-use whisper::error;
-use whisper::resize::resize;
+use whisper::resize::{resize_with_progress, BackupPolicy, Progress, ResizeOptions};
 use whisper::retention::Retention;
 
 #[derive(Debug, StructOpt)]
@@ -17,7 +16,8 @@ struct Args {
     x_files_factor: Option<f32>,
 
     /// Change the aggregation function:
-    /// (average, sum, last, max, min, avg_zero, absmax, absmin)
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
     #[structopt(long = "aggregationMethod")]
     aggregation_method: Option<AggregationMethod>,
 
@@ -56,32 +56,65 @@ struct Args {
     retentions: Vec<Retention>,
 }
 
+fn print_progress(progress: Progress) {
+    match progress {
+        Progress::RemovedTemporary(path) => println!(
+            "Removing previous temporary database file: {}",
+            path.display()
+        ),
+        Progress::Created { path, size } => {
+            println!("Created: {} ({} bytes)", path.display(), size)
+        }
+        Progress::Migrating { aggregate: true } => println!("Migrating data with aggregation..."),
+        Progress::Migrating { aggregate: false } => {
+            println!("Migrating data without aggregation...")
+        }
+        Progress::ArchiveMigrated {
+            seconds_per_point,
+            interval,
+            points,
+        } => println!(
+            "({},{},{}) {} points",
+            interval.from(),
+            interval.until(),
+            seconds_per_point,
+            points
+        ),
+        Progress::Renamed { from, to } => {
+            println!("Renaming {} to: {}", from.display(), to.display())
+        }
+        Progress::RestoredBackup(path) => {
+            println!("Operation failed, restored backup from: {}", path.display())
+        }
+        Progress::RemovedBackup(path) => println!("Unlinking backup: {}", path.display()),
+        Progress::UpdatedMetadata(_) => {
+            println!("Retentions are unchanged, updated metadata in place")
+        }
+    }
+}
+
 fn run(args: &Args) -> Result<(), Box<dyn Error>> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
-    let path = &args.path;
 
-    if !path.is_file() {
-        return Err(error::Error::FileNotExist(path.to_path_buf()).into());
+    let mut options = ResizeOptions::new(&args.retentions)
+        .aggregate(args.aggregate)
+        .backup(if args.nobackup {
+            BackupPolicy::Remove
+        } else {
+            BackupPolicy::Keep
+        });
+    if let Some(x_files_factor) = args.x_files_factor {
+        options = options.x_files_factor(x_files_factor);
+    }
+    if let Some(aggregation_method) = args.aggregation_method {
+        options = options.aggregation_method(aggregation_method);
+    }
+    if let Some(ref newfile) = args.newfile {
+        options = options.new_file(newfile);
     }
-
-    let whisper_file = whisper::WhisperFile::open(path)?;
-    let meta = whisper_file.info();
-
-    let x_files_factor = args.x_files_factor.unwrap_or(meta.x_files_factor);
-    let aggregation_method = args.aggregation_method.unwrap_or(meta.aggregation_method);
 
     println!("Retrieving all data from the archives");
-
-    resize(
-        path,
-        args.newfile.as_deref(),
-        &args.retentions,
-        x_files_factor,
-        aggregation_method,
-        args.aggregate,
-        args.nobackup,
-        now,
-    )?;
+    resize_with_progress(&args.path, &options, now, &mut print_progress)?;
 
     Ok(())
 }
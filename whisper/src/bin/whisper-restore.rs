@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use whisper::dump::{Dump, DumpFormat};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-restore")]
+struct Args {
+    /// Format of the dump written by whisper-dump --format: json or csv
+    #[structopt(long = "format", default_value = "json")]
+    format: DumpFormat,
+
+    /// Overwrite an existing file
+    #[structopt(long = "overwrite")]
+    overwrite: bool,
+
+    /// Path to data file
+    #[structopt(name = "path", parse(from_os_str))]
+    path: PathBuf,
+
+    /// Dump file, standard input if not given
+    #[structopt(name = "input", parse(from_os_str))]
+    input: Option<PathBuf>,
+}
+
+fn read_dump<R: io::BufRead>(reader: R, format: DumpFormat) -> Result<Dump, Box<dyn Error>> {
+    let dump = match format {
+        DumpFormat::Json => serde_json::from_reader(reader)?,
+        DumpFormat::Csv => Dump::read_csv(reader)?,
+    };
+    Ok(dump)
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let dump = match args.input {
+        Some(ref input) => read_dump(BufReader::new(File::open(input)?), args.format)?,
+        None => read_dump(io::stdin().lock(), args.format)?,
+    };
+
+    let mut bytes = io::Cursor::new(Vec::new());
+    dump.write_file(&mut bytes)?;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(args.overwrite)
+        .truncate(args.overwrite)
+        .create_new(!args.overwrite)
+        .open(&args.path)?;
+    file.write_all(bytes.get_ref())?;
+
+    let size = args.path.metadata()?.len();
+    println!(
+        "Restored: {} ({} bytes)",
+        &args.path.to_str().unwrap(),
+        size
+    );
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
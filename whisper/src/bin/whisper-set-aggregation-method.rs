@@ -1,6 +1,7 @@
 use std::io;
 use std::path::PathBuf;
 use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use whisper::aggregation::AggregationMethod;
 
@@ -12,22 +13,29 @@ struct Args {
     path: PathBuf,
 
     /// Function to use when aggregating values
-    /// (average, sum, last, max, min, avg_zero, absmax, absmin)
+    /// (average, sum, last, max, min, avg_zero, absmax, absmin,
+    /// median, count, stddev, p0 to p100)
     #[structopt(name = "aggregationMethod", default_value = "average")]
     aggregation_method: AggregationMethod,
 
     /// XFILESFACTOR
     #[structopt(name = "xFilesFactor", default_value = "0.5")]
     x_files_factor: f32,
+
+    /// Recompute lower archives from the highest precision data with the new settings
+    #[structopt(long = "rebuild")]
+    rebuild: bool,
 }
 
 fn run(args: &Args) -> io::Result<()> {
-    let mut file = whisper::WhisperFile::open(&args.path)?;
+    let mut file = whisper::OpenOptions::new().lock(true).open(&args.path)?;
 
     let old_aggregation_method = file.info().aggregation_method;
 
-    file.set_x_files_factor(args.x_files_factor)?;
-    file.set_aggregation_method(args.aggregation_method)?;
+    file.update_metadata(|metadata| {
+        metadata.set_x_files_factor(args.x_files_factor);
+        metadata.set_aggregation_method(args.aggregation_method);
+    })?;
 
     println!(
         "Updated aggregation method: {} ({} -> {})",
@@ -36,6 +44,15 @@ fn run(args: &Args) -> io::Result<()> {
         &args.aggregation_method
     );
 
+    if args.rebuild {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?
+            .as_secs() as u32;
+        let points = file.rebuild(now)?;
+        println!("Rebuilt {} points of lower archives", points);
+    }
+
     Ok(())
 }
 
@@ -1,6 +1,7 @@
 use std::io;
 use std::path::PathBuf;
 use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -16,10 +17,14 @@ struct Args {
     /// new xFilesFactor, a float between 0 and 1
     #[structopt(name = "xFilesFactor")]
     x_files_factor: f32,
+
+    /// Recompute lower archives from the highest precision data with the new settings
+    #[structopt(long = "rebuild")]
+    rebuild: bool,
 }
 
 fn run(args: &Args) -> io::Result<()> {
-    let mut file = whisper::WhisperFile::open(&args.path)?;
+    let mut file = whisper::OpenOptions::new().lock(true).open(&args.path)?;
 
     let old_x_files_factor = file.info().x_files_factor;
 
@@ -32,6 +37,15 @@ fn run(args: &Args) -> io::Result<()> {
         &args.x_files_factor
     );
 
+    if args.rebuild {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?
+            .as_secs() as u32;
+        let points = file.rebuild(now)?;
+        println!("Rebuilt {} points of lower archives", points);
+    }
+
     Ok(())
 }
 
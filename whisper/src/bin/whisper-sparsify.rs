@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+use whisper::sparsify::{sparsify, sparsify_tree, SparsifyReport};
+
+/// Punch holes over the zeroed blocks of whisper files to reclaim disk space.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "whisper-sparsify")]
+struct Args {
+    /// Whisper files or directories containing them
+    #[structopt(name = "path", parse(from_os_str), required = true, min_values = 1)]
+    paths: Vec<PathBuf>,
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut report = SparsifyReport::default();
+    let mut failed = 0;
+    for path in &args.paths {
+        if path.is_dir() {
+            report += sparsify_tree(path, |path, e| {
+                eprintln!("{}: {}", path.display(), e);
+                failed += 1;
+            })?;
+        } else {
+            match sparsify(path) {
+                Ok(file_report) => report += file_report,
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!("{}", report);
+    if failed > 0 {
+        return Err(format!("Failed to sparsify {} files", failed).into());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
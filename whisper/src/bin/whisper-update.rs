@@ -4,7 +4,7 @@ use std::process::exit;
 use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use whisper::point::Point;
-use whisper::WhisperFile;
+use whisper::OpenOptions;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "whisper-update")]
@@ -21,7 +21,7 @@ struct Args {
 fn run(args: &Args) -> Result<(), Box<dyn Error>> {
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
 
-    let mut file = WhisperFile::open(&args.path)?;
+    let mut file = OpenOptions::new().lock(true).open(&args.path)?;
     file.update_many(&args.points, now)?;
 
     Ok(())
@@ -1,17 +1,22 @@
 use super::*;
 use crate::aggregation::AggregationMethod;
 use crate::retention::Retention;
+use std::collections::HashMap;
 use std::convert::AsRef;
 use std::default;
 use std::fmt::{Display, Formatter};
-use std::io;
+use std::io::{self, Read, Seek, Write};
 use std::path::Path;
 
+#[derive(Clone)]
 pub struct WhisperBuilder {
     aggregation_method: AggregationMethod,
     x_files_factor: f32,
     retentions: Vec<Retention>,
+    archive_aggregation_methods: HashMap<u32, AggregationMethod>,
+    archive_x_files_factors: HashMap<u32, f32>,
     sparse: bool,
+    lock: bool,
 }
 
 impl default::Default for WhisperBuilder {
@@ -20,11 +25,34 @@ impl default::Default for WhisperBuilder {
             aggregation_method: AggregationMethod::Average,
             x_files_factor: 0.5,
             retentions: Vec::new(),
+            archive_aggregation_methods: HashMap::new(),
+            archive_x_files_factors: HashMap::new(),
             sparse: false,
+            lock: false,
         }
     }
 }
 
+/// Builder of a file with the same archives and settings, offsets are computed again.
+impl From<&WhisperMetadata> for WhisperBuilder {
+    fn from(metadata: &WhisperMetadata) -> Self {
+        let mut builder = Self::default()
+            .add_retentions(&metadata.retentions())
+            .aggregation_method(metadata.aggregation_method)
+            .x_files_factor(metadata.x_files_factor);
+        for archive in &metadata.archives {
+            if let Some(aggregation_method) = archive.aggregation_method {
+                builder = builder
+                    .archive_aggregation_method(archive.seconds_per_point, aggregation_method);
+            }
+            if let Some(x_files_factor) = archive.x_files_factor {
+                builder = builder.archive_x_files_factor(archive.seconds_per_point, x_files_factor);
+            }
+        }
+        builder
+    }
+}
+
 impl WhisperBuilder {
     pub fn add_retentions(mut self, retentions: &[Retention]) -> Self {
         self.retentions.extend(retentions);
@@ -46,16 +74,48 @@ impl WhisperBuilder {
         self
     }
 
+    /// Aggregation method of the archive with `seconds_per_point`, stored in an extended header.
+    pub fn archive_aggregation_method(
+        mut self,
+        seconds_per_point: u32,
+        aggregation_method: AggregationMethod,
+    ) -> Self {
+        self.archive_aggregation_methods
+            .insert(seconds_per_point, aggregation_method);
+        self
+    }
+
+    /// xFilesFactor of the archive with `seconds_per_point`, stored in an extended header.
+    pub fn archive_x_files_factor(mut self, seconds_per_point: u32, x_files_factor: f32) -> Self {
+        self.archive_x_files_factors
+            .insert(seconds_per_point, x_files_factor);
+        self
+    }
+
     pub fn sparse(mut self, sparse: bool) -> Self {
         self.sparse = sparse;
         self
     }
 
+    /// Hold an exclusive lock while the file is created and lock it on later operations.
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
     fn into_metadata(mut self) -> Result<WhisperMetadata, BuilderError> {
         if self.x_files_factor < 0.0 || self.x_files_factor > 1.0 {
             return Err(BuilderError::InvalidXFilesFactor(self.x_files_factor));
         }
 
+        if let Some(x_files_factor) = self
+            .archive_x_files_factors
+            .values()
+            .find(|x_files_factor| !(0.0..=1.0).contains(*x_files_factor))
+        {
+            return Err(BuilderError::InvalidXFilesFactor(*x_files_factor));
+        }
+
         if self.retentions.is_empty() {
             return Err(BuilderError::NoRetentions);
         }
@@ -63,13 +123,57 @@ impl WhisperBuilder {
         self.retentions.sort_by_key(|a| a.seconds_per_point);
         validate_archive_list(&self.retentions)?;
 
+        let configured = self
+            .archive_aggregation_methods
+            .keys()
+            .chain(self.archive_x_files_factors.keys());
+        for &seconds_per_point in configured {
+            if !self
+                .retentions
+                .iter()
+                .any(|retention| retention.seconds_per_point == seconds_per_point)
+            {
+                return Err(BuilderError::UnknownArchive(seconds_per_point));
+            }
+        }
+
+        // Every archive of an extended header has its own settings
+        let extended = !self.archive_aggregation_methods.is_empty()
+            || !self.archive_x_files_factors.is_empty();
+        let archive_info_size = if extended {
+            EXTENDED_ARCHIVE_INFO_SIZE
+        } else {
+            ARCHIVE_INFO_SIZE
+        };
+
         let mut archives = Vec::with_capacity(self.retentions.len());
-        let mut offset = METADATA_SIZE + ARCHIVE_INFO_SIZE * self.retentions.len();
+        let mut offset = METADATA_SIZE + archive_info_size * self.retentions.len();
         for retention in &self.retentions {
+            let spp = retention.seconds_per_point;
             archives.push(ArchiveInfo {
                 offset: offset as u32,
-                seconds_per_point: retention.seconds_per_point,
+                seconds_per_point: spp,
                 points: retention.points,
+                aggregation_method: if extended {
+                    Some(
+                        *self
+                            .archive_aggregation_methods
+                            .get(&spp)
+                            .unwrap_or(&self.aggregation_method),
+                    )
+                } else {
+                    None
+                },
+                x_files_factor: if extended {
+                    Some(
+                        *self
+                            .archive_x_files_factors
+                            .get(&spp)
+                            .unwrap_or(&self.x_files_factor),
+                    )
+                } else {
+                    None
+                },
             });
             offset += retention.points as usize * POINT_SIZE;
         }
@@ -92,9 +196,20 @@ impl WhisperBuilder {
 
     pub fn build<P: AsRef<Path>>(self, path: P) -> Result<WhisperFile, BuilderError> {
         let sparse = self.sparse;
+        let lock = self.lock;
+        let metadata = self.into_metadata()?;
+        let file = WhisperFile::create(&metadata, path.as_ref(), sparse, lock)
+            .map_err(BuilderError::Io)?;
+        Ok(file)
+    }
+
+    /// Create the database in an in-memory or custom backend, overwriting its start.
+    pub fn build_in<F: Read + Write + Seek>(
+        self,
+        backend: F,
+    ) -> Result<WhisperFile<F>, BuilderError> {
         let metadata = self.into_metadata()?;
-        let file =
-            WhisperFile::create(&metadata, path.as_ref(), sparse).map_err(BuilderError::Io)?;
+        let file = WhisperFile::create_in(&metadata, backend).map_err(BuilderError::Io)?;
         Ok(file)
     }
 }
@@ -108,7 +223,7 @@ impl WhisperBuilder {
  * 3. Lower precision archives must cover larger time intervals than higher precision archives.
  * 4. Each archive must have at least enough points to consolidate to the next archive
  */
-fn validate_archive_list(archives: &[Retention]) -> Result<(), BuilderError> {
+pub(crate) fn validate_archive_list(archives: &[Retention]) -> Result<(), BuilderError> {
     for (i, pair) in archives.windows(2).enumerate() {
         let archive = &pair[0];
         let next_archive = &pair[1];
@@ -153,6 +268,7 @@ pub enum BuilderError {
     BadRetention(usize, u32, u32),
     NotEnoughPoints(usize, u32, u32),
     InvalidXFilesFactor(f32),
+    UnknownArchive(u32),
     Io(io::Error),
 }
 
@@ -171,6 +287,8 @@ impl Display for BuilderError {
                 write!(f, "Each archive must have at least enough points to consolidate to the next archive (archive at index {} consolidates {} of previous archive's points but it has only {} total points)", index, points, points2),
             Self::InvalidXFilesFactor(factor) =>
                 write!(f, "Invalid xFilesFactor {}, not between 0 and 1", factor),
+            Self::UnknownArchive(seconds_per_point) =>
+                write!(f, "No archive with {} seconds per point", seconds_per_point),
             Self::Io(e) => write!(f, "{}", e),
         }
     }
@@ -189,7 +307,7 @@ impl From<BuilderError> for error::Error {
     fn from(error: BuilderError) -> Self {
         match error {
             BuilderError::Io(e) => error::Error::Io(e),
-            e => error::Error::Kind(e.to_string()),
+            e => error::Error::Builder(e),
         }
     }
 }
@@ -0,0 +1,307 @@
+use crate::WhisperMetadata;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+pub const DEFAULT_CAPACITY: usize = 65536;
+
+lazy_static! {
+    static ref GLOBAL: HeaderCache = HeaderCache::new(DEFAULT_CAPACITY);
+}
+
+/// Identity of a file on disk, used to detect replaced or modified files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Stamp {
+    device: u64,
+    inode: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    pub(crate) fn of(file: &fs::File) -> Result<Self, io::Error> {
+        let meta = file.metadata()?;
+
+        #[cfg(unix)]
+        let (device, inode) = {
+            use std::os::unix::fs::MetadataExt;
+            (meta.dev(), meta.ino())
+        };
+        #[cfg(not(unix))]
+        let (device, inode) = (0, 0);
+
+        Ok(Self {
+            device,
+            inode,
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+struct Entry {
+    stamp: Stamp,
+    metadata: WhisperMetadata,
+    used: u64,
+}
+
+struct Entries {
+    capacity: usize,
+    clock: u64,
+    map: HashMap<PathBuf, Entry>,
+}
+
+impl Entries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Drop the least recently used eighth of entries.
+    fn evict(&mut self) {
+        let mut used: Vec<u64> = self.map.values().map(|entry| entry.used).collect();
+        used.sort_unstable();
+        let threshold = used[used.len() / 8];
+        self.map.retain(|_, entry| entry.used > threshold);
+    }
+}
+
+/**
+ * Bounded cache of whisper headers, the equivalent of `CACHE_HEADERS` in
+ * the Python implementation.
+ *
+ * Entries are keyed by path and are invalidated when the file behind the
+ * path changes its inode or modification time. Writes made through a
+ * `WhisperFile` opened with header caching re-stamp the entry instead, unless
+ * the file was changed by someone else since the entry was stamped.
+ */
+pub struct HeaderCache {
+    entries: Mutex<Entries>,
+}
+
+impl HeaderCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                capacity: usize::max(capacity, 1),
+                clock: 0,
+                map: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Process-wide cache used by `OpenOptions::cache_headers`.
+    pub fn global() -> &'static HeaderCache {
+        &GLOBAL
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut entries = self.entries();
+        entries.capacity = usize::max(capacity, 1);
+        while entries.map.len() > entries.capacity {
+            entries.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries().map.clear();
+    }
+
+    pub fn invalidate(&self, path: &Path) {
+        self.entries().map.remove(path);
+    }
+
+    /// Cached header of `path`, if `file` is still the same file it was read from.
+    pub fn get(&self, path: &Path, file: &fs::File) -> Result<Option<WhisperMetadata>, io::Error> {
+        let stamp = Stamp::of(file)?;
+
+        let mut entries = self.entries();
+        let used = entries.tick();
+        match entries.map.get_mut(path) {
+            Some(entry) if entry.stamp == stamp => {
+                entry.used = used;
+                Ok(Some(entry.metadata.clone()))
+            }
+            Some(_) => {
+                entries.map.remove(path);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * Re-stamp the entry of `path` after `file` was written with `metadata`
+     * as its header. The entry is dropped when its stamp is not `before`, the
+     * stamp observed before the write, as the cached header may be stale.
+     */
+    pub(crate) fn refresh(
+        &self,
+        path: &Path,
+        file: &fs::File,
+        before: Stamp,
+        metadata: &WhisperMetadata,
+    ) -> Result<(), io::Error> {
+        let stamp = Stamp::of(file)?;
+
+        let mut entries = self.entries();
+        match entries.map.get_mut(path) {
+            Some(entry) if entry.stamp == before => {
+                entry.stamp = stamp;
+                entry.metadata = metadata.clone();
+            }
+            Some(_) => {
+                entries.map.remove(path);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn insert(
+        &self,
+        path: &Path,
+        file: &fs::File,
+        metadata: &WhisperMetadata,
+    ) -> Result<(), io::Error> {
+        let stamp = Stamp::of(file)?;
+
+        let mut entries = self.entries();
+        let used = entries.tick();
+        if entries.map.len() >= entries.capacity && !entries.map.contains_key(path) {
+            entries.evict();
+        }
+        entries.map.insert(
+            path.to_path_buf(),
+            Entry {
+                stamp,
+                metadata: metadata.clone(),
+                used,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::AggregationMethod;
+    use crate::archive_info::ArchiveInfo;
+    use std::time::Duration;
+
+    fn metadata(x_files_factor: f32) -> WhisperMetadata {
+        WhisperMetadata {
+            aggregation_method: AggregationMethod::Average,
+            max_retention: 60,
+            x_files_factor,
+            archives: vec![ArchiveInfo {
+                offset: 28,
+                seconds_per_point: 1,
+                points: 60,
+                aggregation_method: None,
+                x_files_factor: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_get_insert() -> Result<(), io::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.wsp");
+        let file = fs::File::create(&path)?;
+
+        let cache = HeaderCache::new(10);
+        assert!(cache.get(&path, &file)?.is_none());
+
+        cache.insert(&path, &file, &metadata(0.5))?;
+        assert_eq!(cache.get(&path, &file)?.unwrap().x_files_factor, 0.5);
+
+        cache.invalidate(&path);
+        assert!(cache.get(&path, &file)?.is_none());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replaced_file() -> Result<(), io::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.wsp");
+        let file = fs::File::create(&path)?;
+
+        let cache = HeaderCache::new(10);
+        cache.insert(&path, &file, &metadata(0.5))?;
+
+        let other = dir.path().join("b.wsp");
+        fs::File::create(&other)?;
+        fs::rename(&other, &path)?;
+
+        let replaced = fs::File::open(&path)?;
+        assert!(cache.get(&path, &replaced)?.is_none());
+        assert!(cache.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh() -> Result<(), io::Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.wsp");
+        let file = fs::File::create(&path)?;
+        let epoch = SystemTime::UNIX_EPOCH;
+
+        let cache = HeaderCache::new(10);
+        file.set_modified(epoch + Duration::from_secs(1))?;
+        cache.insert(&path, &file, &metadata(0.5))?;
+
+        // Written by this handle only
+        let before = Stamp::of(&file)?;
+        file.set_modified(epoch + Duration::from_secs(2))?;
+        cache.refresh(&path, &file, before, &metadata(0.2))?;
+        assert_eq!(cache.get(&path, &file)?.unwrap().x_files_factor, 0.2);
+
+        // Written by someone else before this handle
+        let other = fs::OpenOptions::new().write(true).open(&path)?;
+        other.set_modified(epoch + Duration::from_secs(3))?;
+        let before = Stamp::of(&file)?;
+        file.set_modified(epoch + Duration::from_secs(4))?;
+        cache.refresh(&path, &file, before, &metadata(0.2))?;
+        assert!(cache.get(&path, &file)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity() -> Result<(), io::Error> {
+        let dir = tempfile::tempdir()?;
+        let cache = HeaderCache::new(8);
+
+        let first = dir.path().join("0.wsp");
+        let first_file = fs::File::create(&first)?;
+        cache.insert(&first, &first_file, &metadata(0.5))?;
+
+        for i in 1..20 {
+            let path = dir.path().join(format!("{}.wsp", i));
+            let file = fs::File::create(&path)?;
+            cache.insert(&path, &file, &metadata(0.5))?;
+            assert!(cache.len() <= 8);
+        }
+
+        assert!(cache.get(&first, &first_file)?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+use super::*;
+use crate::builder::validate_archive_list;
+use crate::retention::Retention;
+use std::fmt;
+use std::path::Path;
+
+/// A single problem found in a whisper file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// Header can't be parsed, nothing else is checked.
+    BadHeader { message: String },
+    /// File size differs from the size described by the header.
+    SizeMismatch { expected: u64, actual: u64 },
+    /// `max_retention` differs from the retention of the longest archive.
+    BadMaxRetention { expected: u32, actual: u32 },
+    /// Archive data overlaps the header or the previous archive.
+    OverlappingArchive { archive: usize, offset: u32 },
+    /// Archives violate the rules checked on creation.
+    InvalidArchiveList { message: String },
+    /// Points with timestamps not aligned to the archive step.
+    MisalignedPoints {
+        archive: usize,
+        count: usize,
+        example: u32,
+    },
+    /// Points with timestamps after the time of the check.
+    FuturePoints {
+        archive: usize,
+        count: usize,
+        example: u32,
+    },
+    /// Block of a compressed file doesn't match its checksum or can't be decoded.
+    BadBlock {
+        archive: usize,
+        block: usize,
+        message: String,
+    },
+}
+
+impl Problem {
+    /// The file can't be used as is, other problems leave the rest of the file readable.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Problem::BadHeader { .. } | Problem::SizeMismatch { .. }
+        )
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadHeader { message } => write!(f, "Bad header: {}", message),
+            Problem::SizeMismatch { expected, actual } => write!(
+                f,
+                "File size is {} bytes, expected {} bytes",
+                actual, expected
+            ),
+            Problem::BadMaxRetention { expected, actual } => {
+                write!(f, "maxRetention is {}, expected {}", actual, expected)
+            }
+            Problem::OverlappingArchive { archive, offset } => write!(
+                f,
+                "Archive {} at offset {} overlaps the header or the previous archive",
+                archive, offset
+            ),
+            Problem::InvalidArchiveList { message } => write!(f, "{}", message),
+            Problem::MisalignedPoints {
+                archive,
+                count,
+                example,
+            } => write!(
+                f,
+                "Archive {} has {} misaligned points (e.g. {})",
+                archive, count, example
+            ),
+            Problem::FuturePoints {
+                archive,
+                count,
+                example,
+            } => write!(
+                f,
+                "Archive {} has {} points in the future (e.g. {})",
+                archive, count, example
+            ),
+            Problem::BadBlock {
+                archive,
+                block,
+                message,
+            } => write!(f, "Archive {} block {}: {}", archive, block, message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub path: String,
+    /// Total number of points in all archives, 0 if the header is unreadable.
+    pub points: u32,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.problems.iter().any(Problem::is_fatal)
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            writeln!(f, "{}: {} points", self.path, self.points)?;
+        } else {
+            for problem in &self.problems {
+                writeln!(f, "{}: {}", self.path, problem)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Check the header and all points of a whisper file, or the checksums and
+ * blocks of a compressed one. The file is locked while it is read.
+ */
+pub fn check(path: &Path, now: u32) -> Result<CheckReport, Error> {
+    let mut file = fs::File::open(path)?;
+    let _guard = FileLock::shared(&file)?;
+
+    let mut report = CheckReport {
+        path: path.display().to_string(),
+        points: 0,
+        problems: Vec::new(),
+    };
+
+    if compressed::is_compressed(&mut file)? {
+        match compressed::check_blocks(&mut file) {
+            Ok((points, blocks)) => {
+                report.points = points;
+                report
+                    .problems
+                    .extend(
+                        blocks
+                            .into_iter()
+                            .map(|(archive, block, e)| Problem::BadBlock {
+                                archive,
+                                block,
+                                message: e.to_string(),
+                            }),
+                    );
+            }
+            Err(e) => report.problems.push(Problem::BadHeader {
+                message: e.to_string(),
+            }),
+        }
+        return Ok(report);
+    }
+
+    let metadata = match WhisperMetadata::read(&mut file) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            report.problems.push(Problem::BadHeader {
+                message: e.to_string(),
+            });
+            return Ok(report);
+        }
+    };
+    let points = metadata
+        .archives
+        .iter()
+        .try_fold(0u32, |total, archive| total.checked_add(archive.points));
+    report.points = match points {
+        Some(points) => points,
+        None => {
+            report.problems.push(Problem::BadHeader {
+                message: format!("Header describes more than {} points", u32::MAX),
+            });
+            return Ok(report);
+        }
+    };
+
+    let actual = file.metadata()?.len();
+    let expected = metadata.file_size() as u64;
+    if actual != expected {
+        report
+            .problems
+            .push(Problem::SizeMismatch { expected, actual });
+    }
+
+    let max_retention = metadata
+        .archives
+        .iter()
+        .map(|archive| archive.retention())
+        .max()
+        .unwrap_or(0);
+    if metadata.max_retention != max_retention {
+        report.problems.push(Problem::BadMaxRetention {
+            expected: max_retention,
+            actual: metadata.max_retention,
+        });
+    }
+
+    let mut archives: Vec<(usize, &ArchiveInfo)> = metadata.archives.iter().enumerate().collect();
+    archives.sort_by_key(|(_, archive)| archive.offset);
+    let mut data_start = metadata.header_size() as u64;
+    for (index, archive) in archives {
+        if u64::from(archive.offset) < data_start {
+            report.problems.push(Problem::OverlappingArchive {
+                archive: index,
+                offset: archive.offset,
+            });
+        }
+        data_start = u64::max(data_start, archive.offset as u64 + archive.size() as u64);
+    }
+
+    let retentions: Vec<Retention> = metadata
+        .archives
+        .iter()
+        .map(|archive| Retention {
+            seconds_per_point: archive.seconds_per_point,
+            points: archive.points,
+        })
+        .collect();
+    if let Err(e) = validate_archive_list(&retentions) {
+        report.problems.push(Problem::InvalidArchiveList {
+            message: e.to_string(),
+        });
+    }
+
+    for (index, archive) in metadata.archives.iter().enumerate() {
+        if archive.points == 0
+            || archive.seconds_per_point == 0
+            || archive.offset as u64 + archive.size() as u64 > actual
+        {
+            continue;
+        }
+
+        let points = read_archive(&mut file, archive, 0, archive.points)?;
+        let written = points.iter().filter(|point| point.interval != 0);
+
+        let misaligned: Vec<u32> = written
+            .clone()
+            .filter(|point| point.interval % archive.seconds_per_point != 0)
+            .map(|point| point.interval)
+            .collect();
+        if let Some(&example) = misaligned.iter().min() {
+            report.problems.push(Problem::MisalignedPoints {
+                archive: index,
+                count: misaligned.len(),
+                example,
+            });
+        }
+
+        let future: Vec<u32> = written
+            .filter(|point| point.interval > now)
+            .map(|point| point.interval)
+            .collect();
+        if let Some(&example) = future.iter().max() {
+            report.problems.push(Problem::FuturePoints {
+                archive: index,
+                count: future.len(),
+                example,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u32 = 1_528_240_800;
+
+    fn create(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .build(path)?
+            .update(
+                &Point {
+                    interval: NOW - 60,
+                    value: 1.0,
+                },
+                NOW,
+            )?;
+        Ok(())
+    }
+
+    fn write_at(path: &Path, offset: u64, bytes: &[u8]) -> Result<(), io::Error> {
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+        file.write_all(bytes)
+    }
+
+    #[test]
+    fn test_check_ok() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ok.wsp");
+        create(&path)?;
+
+        let report = check(&path, NOW)?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.points, 20);
+        assert_eq!(
+            format!("{}", report),
+            format!("{}: 20 points\n", path.display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_truncated() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("truncated.wsp");
+        create(&path)?;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(100)?;
+
+        let report = check(&path, NOW)?;
+        assert_eq!(
+            report.problems,
+            vec![Problem::SizeMismatch {
+                expected: 280,
+                actual: 100
+            }]
+        );
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(10)?;
+        let report = check(&path, NOW)?;
+        assert!(matches!(report.problems[..], [Problem::BadHeader { .. }]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_archive_count() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("archive_count.wsp");
+        create(&path)?;
+        write_at(&path, 12, &u32::MAX.to_be_bytes())?;
+
+        let report = check(&path, NOW)?;
+        assert_eq!(
+            report.problems,
+            vec![Problem::BadHeader {
+                message:
+                    "Header of 4294967295 archives takes 51539607556 bytes, the file has 280 bytes"
+                        .to_owned()
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_overflow() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("overflow.wsp");
+        create(&path)?;
+        write_at(&path, 20, &0x10000u32.to_be_bytes())?;
+        write_at(&path, 24, &0x10000u32.to_be_bytes())?;
+
+        let report = check(&path, NOW)?;
+        assert!(report.is_fatal());
+        assert_eq!(
+            report.problems,
+            vec![Problem::BadHeader {
+                message: "Retention of archive 0 overflows: 65536 points of 65536 seconds"
+                    .to_owned()
+            }]
+        );
+
+        // Both archives fit alone, not together
+        let path = dir.path().join("points.wsp");
+        create(&path)?;
+        for offset in &[20, 32] {
+            write_at(&path, *offset, &1u32.to_be_bytes())?;
+            write_at(&path, *offset + 4, &0x8000_0000u32.to_be_bytes())?;
+        }
+
+        let report = check(&path, NOW)?;
+        assert!(report.is_fatal());
+        assert_eq!(
+            report.problems,
+            vec![Problem::BadHeader {
+                message: "Header describes more than 4294967295 points".to_owned()
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_compressed() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("source.wsp");
+        let path = dir.path().join("compressed.wsp");
+        create(&source)?;
+
+        let mut file = OpenOptions::new().open(&source)?;
+        let mut compressed = fs::File::create(&path)?;
+        compressed::compress(
+            &mut file,
+            compressed::DEFAULT_POINTS_PER_BLOCK,
+            &mut compressed,
+        )?;
+        drop(compressed);
+
+        let report = check(&path, NOW)?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.points, 20);
+
+        // Flip a bit of the first point of the first block, its offset follows the 63 bytes of metadata
+        let mut bytes = fs::read(&path)?;
+        let offset = BigEndian::read_u32(&bytes[63..]) as usize;
+        bytes[offset + 11] ^= 1;
+        fs::write(&path, &bytes)?;
+
+        let report = check(&path, NOW)?;
+        assert!(!report.is_fatal());
+        assert!(matches!(
+            report.problems[..],
+            [Problem::BadBlock {
+                archive: 0,
+                block: 0,
+                ..
+            }]
+        ));
+
+        // Flip a bit of the header
+        bytes[30] ^= 1;
+        fs::write(&path, &bytes)?;
+
+        let report = check(&path, NOW)?;
+        assert!(report.is_fatal());
+        assert!(matches!(report.problems[..], [Problem::BadHeader { .. }]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_points() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("points.wsp");
+        create(&path)?;
+
+        let mut bytes = Vec::new();
+        Point {
+            interval: NOW - 59,
+            value: 2.0,
+        }
+        .write(&mut bytes)?;
+        Point {
+            interval: NOW + 60,
+            value: 3.0,
+        }
+        .write(&mut bytes)?;
+        write_at(&path, 40 + 2 * POINT_SIZE as u64, &bytes)?;
+
+        let report = check(&path, NOW)?;
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::MisalignedPoints {
+                    archive: 0,
+                    count: 1,
+                    example: NOW - 59
+                },
+                Problem::FuturePoints {
+                    archive: 0,
+                    count: 1,
+                    example: NOW + 60
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_archives() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("archives.wsp");
+        create(&path)?;
+
+        // Point the second archive at the data of the first one with a bad step
+        let mut bytes = Vec::new();
+        ArchiveInfo {
+            offset: 40,
+            seconds_per_point: 7,
+            points: 10,
+            aggregation_method: None,
+            x_files_factor: None,
+        }
+        .write(&mut bytes)?;
+        write_at(&path, (METADATA_SIZE + ARCHIVE_INFO_SIZE) as u64, &bytes)?;
+
+        let report = check(&path, NOW)?;
+        let kinds: Vec<String> = report
+            .problems
+            .iter()
+            .map(|problem| serde_json::to_value(problem).unwrap()["kind"].to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "\"bad_max_retention\"",
+                "\"overlapping_archive\"",
+                "\"invalid_archive_list\"",
+                "\"misaligned_points\"",
+            ]
+        );
+        Ok(())
+    }
+}
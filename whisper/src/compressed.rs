@@ -0,0 +1,1048 @@
+/**
+ * Compressed whisper format written by go-whisper (go-carbon).
+ *
+ * File = Header,BlockRanges,Data
+ *   Header = Magic,Version,Metadata,ArchiveInfo+
+ *   BlockRanges = BlockRange+ for every archive
+ *   Data = Block+ for every archive
+ *
+ * Every block starts with its first point stored as is, followed by
+ * delta-of-delta encoded timestamps and XOR encoded values (Gorilla).
+ * The header and every block are protected by an IEEE CRC-32.
+ * Compressed files are converted to an in-memory standard database to be read.
+ * Only version 1 of the format, without mixed aggregation, is supported.
+ */
+use super::*;
+use std::convert::TryInto;
+
+pub(crate) const MAGIC: &[u8] = b"whisper_compressed";
+const VERSION: u8 = 1;
+
+/// Magic, version, 7 fields and free space.
+const COMPRESSED_METADATA_SIZE: usize = 18 + 1 + 28 + 16;
+/// Offset of the checksum of the header, the last of the 7 fields.
+const CRC32_OFFSET: usize = 18 + 1 + 28 - 4;
+/**
+ * Archive info, block size and count, average point size, the current block
+ * (index, first and last two points, last byte, count and checksum) and two
+ * statistics, then free space, 92 + 36 bytes like go-whisper reserves.
+ */
+const COMPRESSED_ARCHIVE_INFO_SIZE: usize = 92 + 36;
+const BLOCK_RANGE_SIZE: usize = 16;
+/// Room for the end-of-block marker.
+const END_OF_BLOCK_SIZE: usize = 5;
+
+pub const DEFAULT_POINTS_PER_BLOCK: u32 = 7200;
+const DEFAULT_AVG_COMPRESSED_POINT_SIZE: f32 = 2.0;
+
+/// State of the block points are appended to.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct BlockState {
+    index: u32,
+    p0: Point,
+    pn1: Point,
+    pn2: Point,
+    last_byte_bit_pos: u32,
+    last_byte_offset: u32,
+    count: u32,
+    crc32: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct BlockRange {
+    start: u32,
+    end: u32,
+    count: u32,
+    crc32: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CompressedArchive {
+    info: ArchiveInfo,
+    block_size: u32,
+    block_count: u32,
+    avg_compressed_point_size: f32,
+    cblock: BlockState,
+    ranges: Vec<BlockRange>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CompressedHeader {
+    aggregation_method: AggregationMethod,
+    max_retention: u32,
+    x_files_factor: f32,
+    points_per_block: u32,
+    archives: Vec<CompressedArchive>,
+}
+
+struct BitWriter {
+    buf: Vec<u8>,
+    /// Bits used in the last byte.
+    used: u32,
+}
+
+impl BitWriter {
+    fn new(buf: Vec<u8>) -> Self {
+        Self { buf, used: 8 }
+    }
+
+    fn write(&mut self, bits: u32, value: u64) {
+        for i in (0..bits).rev() {
+            if self.used == 8 {
+                self.buf.push(0);
+                self.used = 0;
+            }
+            if (value >> i) & 1 == 1 {
+                *self.buf.last_mut().unwrap() |= 0x80 >> self.used;
+            }
+            self.used += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    buf: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: u32) -> Result<u64, Error> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self
+                .buf
+                .get(self.position / 8)
+                .ok_or_else(|| Error::Corrupted("Compressed block is truncated".to_owned()))?;
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | u64::from(bit);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// Sign and magnitude of `delta` in `bits` bits.
+fn encode_signed(delta: i64, bits: u32) -> u64 {
+    if delta < 0 {
+        (1 << (bits - 1)) | (-delta) as u64
+    } else {
+        delta as u64
+    }
+}
+
+fn decode_signed(value: u64, bits: u32) -> i64 {
+    let sign = 1 << (bits - 1);
+    if value & sign != 0 {
+        -((value ^ sign) as i64)
+    } else {
+        value as i64
+    }
+}
+
+/*
+ * Timestamps: delta of delta in steps of the archive
+ *   '0'                 the same delta
+ *   '10'   + 7 bits     [-63, 63]
+ *   '110'  + 9 bits     [-255, 255]
+ *   '1110' + 12 bits    [-2047, 2047]
+ *   '1111' + 32 bits    the timestamp itself, 0 marks the end of the block
+ */
+fn write_interval(w: &mut BitWriter, point: &Point, pn1: &Point, pn2: &Point, step: u32) {
+    let delta1 = i64::from(point.interval) - i64::from(pn1.interval);
+    let delta2 = i64::from(pn1.interval) - i64::from(pn2.interval);
+    let step = i64::from(step);
+
+    if (delta1 - delta2) % step != 0 {
+        w.write(4, 0b1111);
+        w.write(32, u64::from(point.interval));
+        return;
+    }
+
+    let delta = (delta1 - delta2) / step;
+    if delta == 0 {
+        w.write(1, 0);
+    } else if delta.abs() < 1 << 6 {
+        w.write(2, 0b10);
+        w.write(7, encode_signed(delta, 7));
+    } else if delta.abs() < 1 << 8 {
+        w.write(3, 0b110);
+        w.write(9, encode_signed(delta, 9));
+    } else if delta.abs() < 1 << 11 {
+        w.write(4, 0b1110);
+        w.write(12, encode_signed(delta, 12));
+    } else {
+        w.write(4, 0b1111);
+        w.write(32, u64::from(point.interval));
+    }
+}
+
+fn read_interval(
+    r: &mut BitReader,
+    pn1: &Point,
+    pn2: &Point,
+    step: u32,
+) -> Result<Option<u32>, Error> {
+    let mut prefix = 0;
+    while prefix < 4 && r.read(1)? == 1 {
+        prefix += 1;
+    }
+
+    let delta = match prefix {
+        0 => 0,
+        1 => decode_signed(r.read(7)?, 7),
+        2 => decode_signed(r.read(9)?, 9),
+        3 => decode_signed(r.read(12)?, 12),
+        _ => {
+            return match r.read(32)? {
+                0 => Ok(None),
+                interval => Ok(Some(interval as u32)),
+            }
+        }
+    };
+
+    let interval = 2 * i64::from(pn1.interval) - i64::from(pn2.interval) + delta * i64::from(step);
+    interval
+        .try_into()
+        .map(Some)
+        .map_err(|_| Error::Corrupted("Bad compressed timestamp".to_owned()))
+}
+
+/*
+ * Values: XOR with the previous value
+ *   '0'                               the same value
+ *   '10' + meaningful bits            within the window of the previous XOR
+ *   '11' + 5 bits of leading zeros
+ *        + 6 bits of length (0 is 64)
+ *        + meaningful bits
+ */
+fn write_value(w: &mut BitWriter, value: f64, pn1: &Point, pn2: &Point) {
+    let xor = pn1.value.to_bits() ^ value.to_bits();
+    if xor == 0 {
+        w.write(1, 0);
+        return;
+    }
+
+    let (leading, trailing) = (xor.leading_zeros(), xor.trailing_zeros());
+    let previous = pn1.value.to_bits() ^ pn2.value.to_bits();
+    if previous != 0 {
+        let (previous_leading, previous_trailing) =
+            (previous.leading_zeros(), previous.trailing_zeros());
+        if leading >= previous_leading && trailing >= previous_trailing {
+            w.write(2, 0b10);
+            w.write(
+                64 - previous_leading - previous_trailing,
+                xor >> previous_trailing,
+            );
+            return;
+        }
+    }
+
+    let leading = leading.min(31);
+    let length = 64 - leading - trailing;
+    w.write(2, 0b11);
+    w.write(5, u64::from(leading));
+    w.write(6, u64::from(length % 64));
+    w.write(length, xor >> trailing);
+}
+
+fn read_value(r: &mut BitReader, pn1: &Point, pn2: &Point) -> Result<f64, Error> {
+    if r.read(1)? == 0 {
+        return Ok(pn1.value);
+    }
+
+    let xor = if r.read(1)? == 0 {
+        let previous = pn1.value.to_bits() ^ pn2.value.to_bits();
+        if previous == 0 {
+            return Err(Error::Corrupted("Bad compressed value".to_owned()));
+        }
+        let (leading, trailing) = (previous.leading_zeros(), previous.trailing_zeros());
+        r.read(64 - leading - trailing)? << trailing
+    } else {
+        let leading = r.read(5)? as u32;
+        let length = match r.read(6)? as u32 {
+            0 => 64,
+            length => length,
+        };
+        if leading + length > 64 {
+            return Err(Error::Corrupted("Bad compressed value".to_owned()));
+        }
+        r.read(length)? << (64 - leading - length)
+    };
+
+    Ok(f64::from_bits(pn1.value.to_bits() ^ xor))
+}
+
+/// Encode chronologically sorted `points`, returning the block with the end marker and its state.
+fn encode_block(points: &[Point], step: u32, index: u32) -> (Vec<u8>, BlockState) {
+    let p0 = points[0];
+    let mut head = Vec::with_capacity(POINT_SIZE);
+    // Writing to a vector can't fail
+    p0.write(&mut head).unwrap();
+
+    let mut w = BitWriter::new(head);
+    let (mut pn1, mut pn2) = (p0, p0);
+    for point in &points[1..] {
+        write_interval(&mut w, point, &pn1, &pn2, step);
+        write_value(&mut w, point.value, &pn1, &pn2);
+        pn2 = pn1;
+        pn1 = *point;
+    }
+
+    let state = BlockState {
+        index,
+        p0,
+        pn1,
+        pn2,
+        last_byte_bit_pos: w.used % 8,
+        last_byte_offset: (w.buf.len() - if w.used == 8 { 0 } else { 1 }) as u32,
+        count: points.len() as u32,
+        crc32: 0,
+    };
+
+    w.write(4, 0b1111);
+    w.write(32, 0);
+    (w.buf, state)
+}
+
+fn decode_block(bytes: &[u8], count: u32, step: u32) -> Result<Vec<Point>, Error> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if bytes.len() < POINT_SIZE {
+        return Err(Error::Corrupted("Compressed block is truncated".to_owned()));
+    }
+
+    // Every point after the first takes at least 2 bits
+    let max_count = 1 + (bytes.len() - POINT_SIZE) * 4;
+    let mut points = Vec::with_capacity(usize::min(count as usize, max_count));
+
+    let p0 = Point::read(&mut &bytes[..])?;
+    points.push(p0);
+
+    let mut r = BitReader {
+        buf: &bytes[POINT_SIZE..],
+        position: 0,
+    };
+    let (mut pn1, mut pn2) = (p0, p0);
+    while points.len() < count as usize {
+        let interval = match read_interval(&mut r, &pn1, &pn2, step)? {
+            Some(interval) => interval,
+            None => break,
+        };
+        let value = read_value(&mut r, &pn1, &pn2)?;
+
+        let point = Point { interval, value };
+        points.push(point);
+        pn2 = pn1;
+        pn1 = point;
+    }
+
+    Ok(points)
+}
+
+fn read_point_state<R: Read>(r: &mut R) -> Result<Point, io::Error> {
+    let interval = r.read_u32::<BigEndian>()?;
+    let value = r.read_f64::<BigEndian>()?;
+    Ok(Point { interval, value })
+}
+
+fn read_header<R: Read + Seek>(r: &mut R) -> Result<CompressedHeader, Error> {
+    let size = r.seek(io::SeekFrom::End(0))?;
+    r.seek(io::SeekFrom::Start(0))?;
+
+    let mut magic = [0; MAGIC.len()];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(Error::NotCompressedFile);
+    }
+    let version = r.read_u8()?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let aggregation_type = r.read_u32::<BigEndian>()?;
+    let max_retention = r.read_u32::<BigEndian>()?;
+    let x_files_factor = r.read_f32::<BigEndian>()?;
+    let points_per_block = r.read_u32::<BigEndian>()?;
+    let archive_count = r.read_u32::<BigEndian>()?;
+    let _avg_compressed_point_size = r.read_f32::<BigEndian>()?;
+    let header_crc32 = r.read_u32::<BigEndian>()?;
+
+    let aggregation_method = AggregationMethod::from_type(aggregation_type)
+        .ok_or(Error::BadAggregationMethod(aggregation_type))?;
+
+    if !(0.0..=1.0).contains(&x_files_factor) {
+        return Err(Error::BadXFilesFactor(x_files_factor));
+    }
+
+    // Corrupted counts must not allocate more than the file could hold
+    let infos_size = COMPRESSED_METADATA_SIZE as u64
+        + u64::from(archive_count) * COMPRESSED_ARCHIVE_INFO_SIZE as u64;
+    if infos_size > size {
+        return Err(Error::Corrupted(format!(
+            "Header of {} archives takes {} bytes, the file has {} bytes",
+            archive_count, infos_size, size
+        )));
+    }
+
+    let mut archives = Vec::with_capacity(archive_count as usize);
+    for index in 0..archive_count {
+        let position = COMPRESSED_METADATA_SIZE + index as usize * COMPRESSED_ARCHIVE_INFO_SIZE;
+        r.seek(io::SeekFrom::Start(position as u64))?;
+
+        let info = ArchiveInfo::read(r)?;
+        let block_size = r.read_u32::<BigEndian>()?;
+        let block_count = r.read_u32::<BigEndian>()?;
+        let avg_compressed_point_size = r.read_f32::<BigEndian>()?;
+
+        let block_index = r.read_u32::<BigEndian>()?;
+        let p0 = read_point_state(r)?;
+        let pn1 = read_point_state(r)?;
+        let pn2 = read_point_state(r)?;
+        let cblock = BlockState {
+            index: block_index,
+            p0,
+            pn1,
+            pn2,
+            last_byte_bit_pos: r.read_u32::<BigEndian>()?,
+            last_byte_offset: r.read_u32::<BigEndian>()?,
+            count: r.read_u32::<BigEndian>()?,
+            crc32: r.read_u32::<BigEndian>()?,
+        };
+        // Discarded points and extensions counted by go-whisper aren't used
+        let _discarded = r.read_u32::<BigEndian>()?;
+        let _extended = r.read_u32::<BigEndian>()?;
+
+        let data_size = u64::from(block_count) * u64::from(block_size);
+        let data_end = u64::from(info.offset) + data_size;
+        if info.seconds_per_point == 0
+            || info.points == 0
+            || info.seconds_per_point.checked_mul(info.points).is_none()
+            || data_end > size
+        {
+            return Err(Error::Corrupted(format!("Bad archive {}", index)));
+        }
+        // Every point after the first of a block takes at least 2 bits
+        let capacity = data_size * 4 + u64::from(block_count);
+        if u64::from(info.points) > capacity {
+            return Err(Error::Corrupted(format!(
+                "Archive {} has {} points, its blocks hold at most {}",
+                index, info.points, capacity
+            )));
+        }
+
+        archives.push(CompressedArchive {
+            info,
+            block_size,
+            block_count,
+            avg_compressed_point_size,
+            cblock,
+            ranges: Vec::new(),
+        });
+    }
+
+    let header_size = infos_size
+        + archives
+            .iter()
+            .map(|archive| u64::from(archive.block_count))
+            .sum::<u64>()
+            * BLOCK_RANGE_SIZE as u64;
+    if header_size > size {
+        return Err(Error::Corrupted(format!(
+            "Block ranges take {} bytes, the file has {} bytes",
+            header_size, size
+        )));
+    }
+
+    r.seek(io::SeekFrom::Start(infos_size))?;
+    for archive in &mut archives {
+        for _ in 0..archive.block_count {
+            let start = r.read_u32::<BigEndian>()?;
+            let end = r.read_u32::<BigEndian>()?;
+            let count = r.read_u32::<BigEndian>()?;
+            let block_crc32 = r.read_u32::<BigEndian>()?;
+            archive.ranges.push(BlockRange {
+                start,
+                end,
+                count,
+                crc32: block_crc32,
+            });
+        }
+    }
+
+    // The checksum covers the whole header, with the checksum itself zeroed
+    let mut bytes = vec![0; header_size as usize];
+    r.seek(io::SeekFrom::Start(0))?;
+    r.read_exact(&mut bytes)?;
+    bytes[CRC32_OFFSET..CRC32_OFFSET + 4].copy_from_slice(&[0; 4]);
+    if crc32(&bytes) != header_crc32 {
+        return Err(Error::Corrupted(
+            "Checksum of the compressed header doesn't match".to_owned(),
+        ));
+    }
+
+    Ok(CompressedHeader {
+        aggregation_method,
+        max_retention,
+        x_files_factor,
+        points_per_block,
+        archives,
+    })
+}
+
+fn write_header<W: Write>(w: &mut W, header: &CompressedHeader) -> Result<(), io::Error> {
+    let mut bytes = Vec::new();
+    bytes.write_all(MAGIC)?;
+    bytes.write_u8(VERSION)?;
+    bytes.write_u32::<BigEndian>(header.aggregation_method.to_type())?;
+    bytes.write_u32::<BigEndian>(header.max_retention)?;
+    bytes.write_f32::<BigEndian>(header.x_files_factor)?;
+    bytes.write_u32::<BigEndian>(header.points_per_block)?;
+    bytes.write_u32::<BigEndian>(header.archives.len() as u32)?;
+    bytes.write_f32::<BigEndian>(DEFAULT_AVG_COMPRESSED_POINT_SIZE)?;
+    bytes.write_u32::<BigEndian>(0)?;
+    bytes.resize(COMPRESSED_METADATA_SIZE, 0);
+
+    for archive in &header.archives {
+        let mut info = Vec::with_capacity(COMPRESSED_ARCHIVE_INFO_SIZE);
+        archive.info.write(&mut info)?;
+        info.write_u32::<BigEndian>(archive.block_size)?;
+        info.write_u32::<BigEndian>(archive.block_count)?;
+        info.write_f32::<BigEndian>(archive.avg_compressed_point_size)?;
+
+        let cblock = &archive.cblock;
+        info.write_u32::<BigEndian>(cblock.index)?;
+        cblock.p0.write(&mut info)?;
+        cblock.pn1.write(&mut info)?;
+        cblock.pn2.write(&mut info)?;
+        info.write_u32::<BigEndian>(cblock.last_byte_bit_pos)?;
+        info.write_u32::<BigEndian>(cblock.last_byte_offset)?;
+        info.write_u32::<BigEndian>(cblock.count)?;
+        info.write_u32::<BigEndian>(cblock.crc32)?;
+        // No discarded points nor extensions
+        info.write_u32::<BigEndian>(0)?;
+        info.write_u32::<BigEndian>(0)?;
+        info.resize(COMPRESSED_ARCHIVE_INFO_SIZE, 0);
+        bytes.write_all(&info)?;
+    }
+
+    for archive in &header.archives {
+        for range in &archive.ranges {
+            bytes.write_u32::<BigEndian>(range.start)?;
+            bytes.write_u32::<BigEndian>(range.end)?;
+            bytes.write_u32::<BigEndian>(range.count)?;
+            bytes.write_u32::<BigEndian>(range.crc32)?;
+        }
+    }
+
+    let header_crc32 = crc32(&bytes);
+    bytes[CRC32_OFFSET..CRC32_OFFSET + 4].copy_from_slice(&header_crc32.to_be_bytes());
+    w.write_all(&bytes)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+/// Checksum of the block at `index`, only the bytes completely written so far count for the current block.
+fn block_crc32(block: &[u8], archive: &CompressedArchive, index: usize) -> u32 {
+    let end = if index as u32 == archive.cblock.index {
+        let offset = u64::from(archive.info.offset) + index as u64 * u64::from(archive.block_size);
+        let written = u64::from(archive.cblock.last_byte_offset).saturating_sub(offset);
+        usize::min(written as usize, block.len())
+    } else {
+        block.len()
+    };
+    crc32(&block[..end])
+}
+
+/// Read the block at `index` of `archive`, check its checksum and decode it.
+fn read_block<R: Read + Seek>(
+    fh: &mut R,
+    archive: &CompressedArchive,
+    index: usize,
+) -> Result<Vec<Point>, Error> {
+    let range = archive.ranges[index];
+    let offset = u64::from(archive.info.offset) + index as u64 * u64::from(archive.block_size);
+
+    let mut block = vec![0; archive.block_size as usize];
+    fh.seek(io::SeekFrom::Start(offset))?;
+    fh.read_exact(&mut block)?;
+
+    if block_crc32(&block, archive, index) != range.crc32 {
+        return Err(Error::Corrupted(format!(
+            "Checksum of the compressed block at offset {} doesn't match",
+            offset
+        )));
+    }
+    decode_block(&block, range.count, archive.info.seconds_per_point)
+}
+
+/// Points of an archive in chronological order, within the retention of the latest one.
+fn archive_points(mut slots: Vec<Point>, archive: &ArchiveInfo) -> Vec<Point> {
+    slots.retain(|point| point.interval != 0);
+    slots.sort_by_key(|point| point.interval);
+    slots.dedup_by_key(|point| point.interval);
+
+    if let Some(latest) = slots.last().map(|point| point.interval) {
+        let retention = archive.retention();
+        slots.retain(|point| latest - point.interval < retention);
+    }
+    slots
+}
+
+/// Whether `fh` holds a compressed whisper database.
+pub fn is_compressed<R: Read + Seek>(fh: &mut R) -> Result<bool, Error> {
+    fh.seek(io::SeekFrom::Start(0))?;
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    fh.take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    Ok(magic == MAGIC)
+}
+
+/**
+ * Decompress a compressed whisper database into an in-memory standard one.
+ *
+ * The whole database is allocated at once, 12 bytes for every point of the
+ * archives. As a point takes at least 2 bits in a block, that is at most
+ * 48 times the size of the blocks; larger headers are rejected as corrupted.
+ */
+pub fn decompress<R: Read + Seek>(fh: &mut R) -> Result<WhisperFile<io::Cursor<Vec<u8>>>, Error> {
+    let header = read_header(fh)?;
+
+    let mut offset = (METADATA_SIZE + ARCHIVE_INFO_SIZE * header.archives.len()) as u64;
+    let mut archives = Vec::with_capacity(header.archives.len());
+    for archive in &header.archives {
+        let info = ArchiveInfo {
+            offset: offset.try_into().map_err(|_| {
+                Error::Corrupted(format!(
+                    "Decompressed archive would start at offset {}",
+                    offset
+                ))
+            })?,
+            ..archive.info
+        };
+        offset += u64::from(info.points) * POINT_SIZE as u64;
+        archives.push(info);
+    }
+    let metadata = WhisperMetadata {
+        aggregation_method: header.aggregation_method,
+        max_retention: header.max_retention,
+        x_files_factor: header.x_files_factor,
+        archives,
+    };
+
+    let mut file = WhisperFile::create_in(&metadata, io::Cursor::new(Vec::new()))?;
+    for (archive, info) in header.archives.iter().zip(&metadata.archives) {
+        let mut points = Vec::new();
+        for (index, range) in archive.ranges.iter().enumerate() {
+            if range.count != 0 {
+                points.extend(read_block(fh, archive, index)?);
+            }
+        }
+
+        let points = archive_points(points, info);
+        if points.is_empty() {
+            continue;
+        }
+
+        let base_interval = points[0].interval;
+        for chunk in pack_points(&points, info.seconds_per_point) {
+            write_archive(&mut file.file, info, &chunk, base_interval)?;
+        }
+    }
+
+    Ok(file)
+}
+
+/// Archive index, block index and error of blocks failing `check_blocks`.
+pub(crate) type BadBlocks = Vec<(usize, usize, Error)>;
+
+/**
+ * Check the header and every block of a compressed whisper database.
+ *
+ * Returns the number of points of its archives and the blocks, as archive
+ * and block indexes, which don't match their checksum or can't be decoded.
+ * Fails if the header can't be read.
+ */
+pub(crate) fn check_blocks<R: Read + Seek>(fh: &mut R) -> Result<(u32, BadBlocks), Error> {
+    let header = read_header(fh)?;
+
+    let mut problems = Vec::new();
+    for (archive_index, archive) in header.archives.iter().enumerate() {
+        for (index, range) in archive.ranges.iter().enumerate() {
+            if range.count != 0 {
+                if let Err(e) = read_block(fh, archive, index) {
+                    problems.push((archive_index, index, e));
+                }
+            }
+        }
+    }
+
+    let points = header
+        .archives
+        .iter()
+        .try_fold(0u32, |total, archive| {
+            total.checked_add(archive.info.points)
+        })
+        .ok_or_else(|| {
+            Error::Corrupted(format!("Header describes more than {} points", u32::MAX))
+        })?;
+    Ok((points, problems))
+}
+
+/// Open a compressed whisper file as an in-memory standard database.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<WhisperFile<io::Cursor<Vec<u8>>>, Error> {
+    let mut file = fs::File::open(path)?;
+    let _guard = FileLock::shared(&file)?;
+    decompress(&mut file)
+}
+
+/**
+ * Write `file` in the compressed format.
+ *
+ * Archives are split into blocks of `points_per_block` points, with one spare
+ * block for rotation like go-whisper does.
+ */
+pub fn compress<F: Read + Write + Seek, W: Write>(
+    file: &mut WhisperFile<F>,
+    points_per_block: u32,
+    w: &mut W,
+) -> Result<(), Error> {
+    let metadata = file.info().clone();
+    let mut archives = Vec::with_capacity(metadata.archives.len());
+    let mut data = Vec::with_capacity(metadata.archives.len());
+
+    for info in &metadata.archives {
+        let points = archive_points(file.dump(info.seconds_per_point)?, info);
+        let block_points = points_per_block.min(info.points).max(1);
+
+        let mut blocks = Vec::new();
+        let mut ranges = Vec::new();
+        let mut cblock = BlockState::default();
+        for (index, chunk) in points.chunks(block_points as usize).enumerate() {
+            let (block, state) = encode_block(chunk, info.seconds_per_point, index as u32);
+            blocks.push(block);
+            ranges.push(BlockRange {
+                start: chunk[0].interval,
+                end: chunk[chunk.len() - 1].interval,
+                count: chunk.len() as u32,
+                crc32: 0,
+            });
+            cblock = state;
+        }
+
+        let block_count = info.points.div_ceil(block_points) + 1;
+        ranges.resize(block_count as usize, BlockRange::default());
+
+        let nominal_size = (block_points as f32 * DEFAULT_AVG_COMPRESSED_POINT_SIZE).ceil()
+            as usize
+            + END_OF_BLOCK_SIZE;
+        let block_size = blocks
+            .iter()
+            .map(|block| block.len())
+            .fold(nominal_size, usize::max);
+
+        archives.push(CompressedArchive {
+            info: *info,
+            block_size: block_size as u32,
+            block_count,
+            avg_compressed_point_size: (block_size - END_OF_BLOCK_SIZE) as f32
+                / block_points as f32,
+            cblock,
+            ranges,
+        });
+        data.push(blocks);
+    }
+
+    let mut offset = (COMPRESSED_METADATA_SIZE
+        + COMPRESSED_ARCHIVE_INFO_SIZE * archives.len()
+        + BLOCK_RANGE_SIZE
+            * archives
+                .iter()
+                .map(|archive| archive.block_count as usize)
+                .sum::<usize>()) as u32;
+    for (archive, blocks) in archives.iter_mut().zip(&mut data) {
+        archive.info.offset = offset;
+        offset += archive.block_size * archive.block_count;
+
+        // Offset of the last byte is absolute, like go-whisper appends to it
+        archive.cblock.last_byte_offset +=
+            archive.info.offset + archive.cblock.index * archive.block_size;
+        for (index, block) in blocks.iter_mut().enumerate() {
+            block.resize(archive.block_size as usize, 0);
+            archive.ranges[index].crc32 = block_crc32(block, archive, index);
+        }
+        archive.cblock.crc32 = archive.ranges[archive.cblock.index as usize].crc32;
+    }
+
+    let header = CompressedHeader {
+        aggregation_method: metadata.aggregation_method,
+        max_retention: metadata.max_retention,
+        x_files_factor: metadata.x_files_factor,
+        points_per_block,
+        archives,
+    };
+    write_header(w, &header)?;
+
+    for (archive, blocks) in header.archives.iter().zip(data) {
+        for block in &blocks {
+            w.write_all(block)?;
+        }
+        let empty = archive.block_count as usize - blocks.len();
+        io::copy(
+            &mut io::repeat(0).take((empty * archive.block_size as usize) as u64),
+            w,
+        )?;
+    }
+
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    fn standard(
+        points: &[Point],
+    ) -> Result<WhisperFile<io::Cursor<Vec<u8>>>, Box<dyn std::error::Error>> {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 100,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 100,
+            })
+            .aggregation_method(AggregationMethod::Max)
+            .x_files_factor(0.2)
+            .build_in(io::Cursor::new(Vec::new()))?;
+        if !points.is_empty() {
+            file.update_many(points, NOW)?;
+        }
+        Ok(file)
+    }
+
+    #[test]
+    fn test_block_round_trip() -> Result<(), Error> {
+        let mut points: Vec<Point> = (0..50)
+            .map(|i| Point {
+                interval: NOW - 6000 + 60 * i,
+                value: f64::from(i % 7) * 1.5 - 3.0,
+            })
+            .collect();
+        // Gaps, a huge jump and special values
+        points.retain(|point| point.interval % 420 != 0);
+        points.push(Point {
+            interval: NOW + 60 * 5000,
+            value: f64::NAN,
+        });
+        points.push(Point {
+            interval: NOW + 60 * 5001,
+            value: f64::MAX,
+        });
+        points.push(Point {
+            interval: NOW + 60 * 5003,
+            value: -0.0,
+        });
+
+        let (block, state) = encode_block(&points, 60, 3);
+        assert_eq!(state.count, points.len() as u32);
+        assert_eq!(state.pn1, points[points.len() - 1]);
+
+        let mut padded = block.clone();
+        padded.resize(block.len() + 10, 0);
+        let decoded = decode_block(&padded, points.len() as u32 + 5, 60)?;
+
+        assert_eq!(decoded.len(), points.len());
+        for (decoded, point) in decoded.iter().zip(&points) {
+            assert_eq!(decoded.interval, point.interval);
+            assert_eq!(decoded.value.to_bits(), point.value.to_bits());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let points: Vec<Point> = (1..150)
+            .filter(|i| i % 11 != 0)
+            .map(|i| Point {
+                interval: NOW - 60 * i,
+                value: (f64::from(i) * 0.37).sin() * 100.0,
+            })
+            .collect();
+        let mut file = standard(&points)?;
+
+        let mut compressed = io::Cursor::new(Vec::new());
+        compress(&mut file, 30, &mut compressed)?;
+        assert!(is_compressed(&mut compressed)?);
+        assert!(!is_compressed(&mut file.get_ref().clone())?);
+        assert!(WhisperMetadata::read(&mut compressed).is_err());
+
+        let mut restored = decompress(&mut compressed)?;
+        assert_eq!(restored.info().archives, file.info().archives);
+        assert_eq!(restored.info().aggregation_method, AggregationMethod::Max);
+        assert_eq!(restored.info().x_files_factor, 0.2);
+        for archive in file.info().archives.clone() {
+            let interval = Interval::past(NOW, archive.retention());
+            assert_eq!(
+                restored.fetch(archive.seconds_per_point, interval, NOW)?,
+                file.fetch(archive.seconds_per_point, interval, NOW)?
+            );
+        }
+
+        let mut again = io::Cursor::new(Vec::new());
+        compress(&mut restored, 30, &mut again)?;
+        assert_eq!(again.into_inner(), compressed.into_inner());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksums() -> Result<(), Box<dyn std::error::Error>> {
+        let points: Vec<Point> = (1..50)
+            .map(|i| Point {
+                interval: NOW - 60 * i,
+                value: f64::from(i),
+            })
+            .collect();
+        let mut file = standard(&points)?;
+
+        let mut compressed = io::Cursor::new(Vec::new());
+        compress(&mut file, 30, &mut compressed)?;
+        let bytes = compressed.into_inner();
+
+        let header = read_header(&mut io::Cursor::new(&bytes))?;
+        let archive = &header.archives[0];
+        assert_eq!(archive.cblock.index, 1);
+        assert!(archive.cblock.last_byte_offset > archive.info.offset + archive.block_size);
+        assert!(archive.ranges[..2].iter().all(|range| range.crc32 != 0));
+        let (points, bad_blocks) = check_blocks(&mut io::Cursor::new(&bytes))?;
+        assert_eq!(points, 200);
+        assert!(bad_blocks.is_empty());
+
+        let mut corrupted = bytes.clone();
+        corrupted[COMPRESSED_METADATA_SIZE] ^= 1;
+        match decompress(&mut io::Cursor::new(corrupted)) {
+            Err(Error::Corrupted(_)) => {}
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+
+        let mut corrupted = bytes;
+        corrupted[archive.info.offset as usize + 3] ^= 1;
+        match decompress(&mut io::Cursor::new(&corrupted)) {
+            Err(Error::Corrupted(_)) => {}
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+        let (_, bad_blocks) = check_blocks(&mut io::Cursor::new(&corrupted))?;
+        assert_eq!(bad_blocks.len(), 1);
+        assert_eq!((bad_blocks[0].0, bad_blocks[0].1), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_layout() -> Result<(), Box<dyn std::error::Error>> {
+        let points: Vec<Point> = (1..50)
+            .map(|i| Point {
+                interval: NOW - 60 * i,
+                value: f64::from(i),
+            })
+            .collect();
+        let mut file = standard(&points)?;
+
+        let mut compressed = io::Cursor::new(Vec::new());
+        compress(&mut file, 30, &mut compressed)?;
+        let bytes = compressed.into_inner();
+        let header = read_header(&mut io::Cursor::new(&bytes))?;
+        let archive = &header.archives[0];
+
+        // Fields of the first archive at the offsets go-whisper uses
+        let info = &bytes[COMPRESSED_METADATA_SIZE..][..COMPRESSED_ARCHIVE_INFO_SIZE];
+        let field =
+            |offset: usize| u32::from_be_bytes(info[offset..offset + 4].try_into().unwrap());
+        let value =
+            |offset: usize| f64::from_be_bytes(info[offset..offset + 8].try_into().unwrap());
+        assert_eq!(field(0), archive.info.offset);
+        assert_eq!((field(4), field(8)), (60, 100));
+        assert_eq!((field(12), field(16)), (archive.block_size, 5));
+        // The second block holds the 19 latest points, from 19 minutes ago
+        assert_eq!(field(24), 1);
+        assert_eq!((field(28), value(32)), (NOW - 60 * 19, 19.0));
+        assert_eq!((field(40), value(44)), (NOW - 60, 1.0));
+        assert_eq!((field(52), value(56)), (NOW - 120, 2.0));
+        assert_eq!(field(68), archive.cblock.last_byte_offset);
+        assert_eq!(field(72), 19);
+        assert_eq!(field(76), archive.ranges[1].crc32);
+        assert!(info[80..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(
+            archive.cblock.p0,
+            Point {
+                interval: NOW - 60 * 19,
+                value: 19.0
+            }
+        );
+        assert_eq!(archive.cblock.crc32, archive.ranges[1].crc32);
+
+        let mut unsupported = bytes;
+        unsupported[MAGIC.len()] = 2;
+        match read_header(&mut io::Cursor::new(&unsupported)) {
+            Err(Error::UnsupportedVersion(2)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_counts() -> Result<(), Box<dyn std::error::Error>> {
+        // A huge count of points doesn't allocate beyond the size of the block
+        assert!(decode_block(&[0; 4], u32::MAX, 60).is_err());
+        assert!(decode_block(&[0; 20], u32::MAX, 60).is_err());
+
+        let mut file = standard(&[])?;
+        let mut compressed = io::Cursor::new(Vec::new());
+        compress(&mut file, 30, &mut compressed)?;
+        let mut bytes = compressed.into_inner();
+        let original = bytes.clone();
+
+        // Archive count, then block size of the first archive
+        bytes[35..39].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_header(&mut io::Cursor::new(&bytes)).is_err());
+        bytes[35..39].copy_from_slice(&2u32.to_be_bytes());
+        let block_size = COMPRESSED_METADATA_SIZE + ARCHIVE_INFO_SIZE;
+        bytes[block_size..block_size + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_header(&mut io::Cursor::new(&bytes)).is_err());
+
+        // More points than the blocks can hold, with a valid checksum
+        let mut header = read_header(&mut io::Cursor::new(&original))?;
+        header.archives[1].info.points = u32::MAX / 300;
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &header)?;
+        bytes.extend_from_slice(&original[bytes.len()..]);
+        match read_header(&mut io::Cursor::new(&bytes)) {
+            Err(Error::Corrupted(message)) => assert!(message.starts_with("Archive 1 has")),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = standard(&[])?;
+
+        let mut compressed = io::Cursor::new(Vec::new());
+        compress(&mut file, DEFAULT_POINTS_PER_BLOCK, &mut compressed)?;
+
+        let restored = decompress(&mut compressed)?;
+        assert_eq!(
+            restored.into_inner().into_inner(),
+            file.into_inner().into_inner()
+        );
+        Ok(())
+    }
+}
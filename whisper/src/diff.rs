@@ -1,7 +1,6 @@
 use super::*;
 use crate::interval::Interval;
 use std::fmt;
-use std::io;
 use std::path::Path;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -14,6 +13,17 @@ pub struct DiffPoint {
     pub value2: Option<f64>,
 }
 
+/// Deltas of the points of an archive known in both files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffStats {
+    /// Largest absolute difference.
+    pub max_delta: f64,
+    /// Mean of `value_b - value_a`.
+    pub mean_delta: f64,
+    /// Number of points differing by more than the tolerance.
+    pub over_threshold: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiffArchive {
     #[serde(rename = "archive")]
@@ -22,6 +32,7 @@ pub struct DiffArchive {
     pub diffs: Vec<DiffPoint>,
     pub points: usize,
     pub total: usize,
+    pub stats: DiffStats,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -103,6 +114,7 @@ pub struct DiffArchiveShort {
     pub index: usize,
     pub total: usize,
     pub points: usize,
+    pub stats: DiffStats,
 }
 
 impl From<DiffArchive> for DiffArchiveShort {
@@ -111,6 +123,7 @@ impl From<DiffArchive> for DiffArchiveShort {
             index: w.index,
             total: w.total,
             points: w.points,
+            stats: w.stats,
         }
     }
 }
@@ -120,20 +133,42 @@ pub struct DiffArchiveSummary {
     pub archives: Vec<DiffArchiveShort>,
     pub path_a: String,
     pub path_b: String,
+    /// Print the columns of `DiffStatsHeader` after the Graphite ones.
+    #[serde(skip)]
+    pub stats: bool,
 }
 
 impl fmt::Display for DiffArchiveSummary {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for archive in &self.archives {
             if f.alternate() {
-                writeln!(
+                write!(
                     f,
                     "{:>7} {:>9} {:>9}",
                     archive.index, archive.total, archive.points
                 )?;
+                if self.stats {
+                    write!(
+                        f,
+                        " {:>13} {:>13} {:>14}",
+                        format!("{:.6}", archive.stats.max_delta),
+                        format!("{:.6}", archive.stats.mean_delta),
+                        archive.stats.over_threshold
+                    )?;
+                }
             } else {
-                writeln!(f, "{} {} {}", archive.index, archive.total, archive.points)?;
+                write!(f, "{} {} {}", archive.index, archive.total, archive.points)?;
+                if self.stats {
+                    write!(
+                        f,
+                        " {} {} {}",
+                        archive.stats.max_delta,
+                        archive.stats.mean_delta,
+                        archive.stats.over_threshold
+                    )?;
+                }
             }
+            writeln!(f)?;
         }
 
         Ok(())
@@ -153,71 +188,142 @@ impl fmt::Display for DiffSummaryHeader {
     }
 }
 
-/** Compare two whisper databases. Each file must have the same archive configuration */
+/// Header of a `DiffArchiveSummary` with stats.
+pub struct DiffStatsHeader();
+
+impl fmt::Display for DiffStatsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(
+                f,
+                "{:#} {:>13} {:>13} {:>14}",
+                DiffSummaryHeader(),
+                "max_delta",
+                "mean_delta",
+                "over_threshold"
+            )?;
+        } else {
+            write!(
+                f,
+                "{} max_delta mean_delta over_threshold",
+                DiffSummaryHeader()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Options of `diff_with`.
+ *
+ * Two values are equal when they differ by at most the absolute tolerance
+ * or the relative tolerance times the larger magnitude, like Python's
+ * `math.isclose`. NaN equals NaN.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+    ignore_empty: bool,
+    absolute_tolerance: f64,
+    relative_tolerance: f64,
+}
+
+impl DiffOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip comparison if either value is undefined.
+    pub fn ignore_empty(mut self, ignore_empty: bool) -> Self {
+        self.ignore_empty = ignore_empty;
+        self
+    }
+
+    pub fn absolute_tolerance(mut self, absolute_tolerance: f64) -> Self {
+        self.absolute_tolerance = absolute_tolerance;
+        self
+    }
+
+    pub fn relative_tolerance(mut self, relative_tolerance: f64) -> Self {
+        self.relative_tolerance = relative_tolerance;
+        self
+    }
+
+    fn is_close(&self, value1: f64, value2: f64) -> bool {
+        if value1 == value2 || (value1.is_nan() && value2.is_nan()) {
+            return true;
+        }
+        let tolerance = f64::max(
+            self.absolute_tolerance,
+            self.relative_tolerance * f64::max(value1.abs(), value2.abs()),
+        );
+        (value1 - value2).abs() <= tolerance
+    }
+}
+
+/// Compare two whisper databases, see `diff_with`.
 pub fn diff(
     path1: &Path,
     path2: &Path,
     ignore_empty: bool,
-    mut until_time: u32,
+    until_time: u32,
     now: u32,
-) -> Result<Vec<DiffArchive>, io::Error> {
-    let mut file1 = WhisperFile::open(path1)?;
-    let mut file2 = WhisperFile::open(path2)?;
-
-    if file1.info().archives != file2.info().archives {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Archive configurations are unalike. Resize the input before diffing",
-        ));
-    }
+) -> Result<Vec<DiffArchive>, Error> {
+    let options = DiffOptions::new().ignore_empty(ignore_empty);
+    diff_with(path1, path2, until_time, now, options)
+}
 
-    let mut archives = file1.info().archives.clone();
+/**
+ * Compare two whisper databases. Both files are locked while they are read.
+ *
+ * When the archive configurations differ, the first file is resampled to the
+ * resolution of each archive of the second one with its aggregation method
+ * and xFilesFactor.
+ */
+pub fn diff_with(
+    path1: &Path,
+    path2: &Path,
+    mut until_time: u32,
+    now: u32,
+    options: DiffOptions,
+) -> Result<Vec<DiffArchive>, Error> {
+    let open_options = OpenOptions::new().lock(true);
+    let mut file1 = open_options.open(path1)?;
+    let mut file2 = open_options.open(path2)?;
+
+    let same_archives = file1.info().archives == file2.info().archives;
+    let metadata = file2.info().clone();
+    let mut archives = metadata.archives.clone();
     archives.sort_by_key(|a| a.retention());
 
     let mut archive_diffs = Vec::new();
 
     for (index, archive) in archives.iter().enumerate() {
         let start_time = now - archive.retention();
-        let interval = Interval::new(start_time, until_time).unwrap();
-
-        let data1 = file1.fetch(archive.seconds_per_point, interval, now)?;
-        let data2 = file2.fetch(archive.seconds_per_point, interval, now)?;
-
-        let start = u32::min(data1.from_interval, data2.from_interval);
-        let end = u32::max(data1.until_interval, data2.until_interval);
-        let archive_step = u32::min(data1.step, data2.step);
-
-        let points: Vec<DiffPoint> = (start..end)
-            .step_by(archive_step as usize)
-            .enumerate()
-            .map(|(index, interval)| DiffPoint {
-                interval,
-                value1: data1.values[index],
-                value2: data2.values[index],
-            })
-            .filter(|p| {
-                if ignore_empty {
-                    p.value1.is_some() && p.value2.is_some()
-                } else {
-                    p.value1.is_some() || p.value2.is_some()
-                }
-            })
-            .collect();
-
-        let total = points.len();
-
-        let diffs: Vec<DiffPoint> = points
-            .into_iter()
-            .filter(|p| p.value1 != p.value2)
-            .collect();
-        let points = diffs.len();
-
-        archive_diffs.push(DiffArchive {
-            index,
-            diffs,
-            points,
-            total,
-        });
+        let interval = Interval::new(start_time, u32::min(until_time, now)).unwrap();
+
+        let values2 = file2.iter_archive(archive.seconds_per_point, interval)?;
+        let archive_diff = if same_archives {
+            let values1 = file1
+                .iter_archive(archive.seconds_per_point, interval)?
+                .values();
+            diff_values(index, values1, values2.values(), options)?
+        } else {
+            let values1 = resample::resample(
+                &mut file1,
+                values2.interval(),
+                archive.seconds_per_point,
+                metadata.archive_aggregation_method(archive),
+                metadata.archive_x_files_factor(archive),
+                now,
+            )?;
+            diff_values(
+                index,
+                values1.into_iter().map(Ok),
+                values2.values(),
+                options,
+            )?
+        };
+        archive_diffs.push(archive_diff);
 
         until_time = u32::min(start_time, until_time);
     }
@@ -225,6 +331,73 @@ pub fn diff(
     Ok(archive_diffs)
 }
 
+fn diff_values<I1, I2>(
+    index: usize,
+    values1: I1,
+    values2: I2,
+    options: DiffOptions,
+) -> Result<DiffArchive, Error>
+where
+    I1: Iterator<Item = Result<(u32, Option<f64>), Error>>,
+    I2: Iterator<Item = Result<(u32, Option<f64>), Error>>,
+{
+    let mut total = 0;
+    let mut diffs = Vec::new();
+    let mut stats = DiffStats::default();
+    let mut delta_sum = 0.0;
+    let mut delta_count = 0;
+    for (value1, value2) in values1.zip(values2) {
+        let (interval, value1) = value1?;
+        let (_, value2) = value2?;
+
+        let compared = if options.ignore_empty {
+            value1.is_some() && value2.is_some()
+        } else {
+            value1.is_some() || value2.is_some()
+        };
+        if !compared {
+            continue;
+        }
+
+        total += 1;
+        let equal = match (value1, value2) {
+            (Some(a), Some(b)) => {
+                let delta = b - a;
+                if !delta.is_nan() {
+                    stats.max_delta = f64::max(stats.max_delta, delta.abs());
+                    delta_sum += delta;
+                    delta_count += 1;
+                }
+                let close = options.is_close(a, b);
+                if !close {
+                    stats.over_threshold += 1;
+                }
+                close
+            }
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if !equal {
+            diffs.push(DiffPoint {
+                interval,
+                value1,
+                value2,
+            });
+        }
+    }
+    if delta_count > 0 {
+        stats.mean_delta = delta_sum / f64::from(delta_count);
+    }
+    let points = diffs.len();
+
+    Ok(DiffArchive {
+        index,
+        diffs,
+        points,
+        total,
+        stats,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,6 +410,24 @@ mod tests {
         assert_eq!(format_none(None), "None");
     }
 
+    #[test]
+    fn diff_options_is_close() {
+        let exact = DiffOptions::new();
+        assert!(exact.is_close(1.0, 1.0));
+        assert!(exact.is_close(f64::NAN, f64::NAN));
+        assert!(exact.is_close(f64::INFINITY, f64::INFINITY));
+        assert!(!exact.is_close(1.0, 1.0 + 1e-12));
+        assert!(!exact.is_close(f64::NAN, 1.0));
+
+        let absolute = DiffOptions::new().absolute_tolerance(0.5);
+        assert!(absolute.is_close(1.0, 1.5));
+        assert!(!absolute.is_close(1.0, 1.6));
+
+        let relative = DiffOptions::new().relative_tolerance(0.01);
+        assert!(relative.is_close(1000.0, 1010.0));
+        assert!(!relative.is_close(1.0, 1.1));
+    }
+
     #[test]
     fn from_archive_to_short() {
         let diff = DiffArchive {
@@ -248,6 +439,7 @@ mod tests {
             }],
             points: 1,
             total: 7,
+            stats: DiffStats::default(),
         };
 
         let diff_short: DiffArchiveShort = diff.into();
@@ -256,7 +448,8 @@ mod tests {
             DiffArchiveShort {
                 index: 2,
                 total: 7,
-                points: 1
+                points: 1,
+                stats: DiffStats::default(),
             }
         );
     }
@@ -273,6 +466,7 @@ mod tests {
                 }],
                 points: 1,
                 total: 7,
+                stats: DiffStats::default(),
             }],
             path_a: "path_a".to_owned(),
             path_b: "path_b".to_owned(),
@@ -296,9 +490,15 @@ mod tests {
                 index: 0,
                 total: 7,
                 points: 1,
+                stats: DiffStats {
+                    max_delta: 1.5,
+                    mean_delta: 0.25,
+                    over_threshold: 1,
+                },
             }],
             path_a: "path_a".to_owned(),
             path_b: "path_b".to_owned(),
+            stats: false,
         };
 
         let diff_info_str1 = format!("{}", diff_info);
@@ -306,6 +506,20 @@ mod tests {
 
         let diff_info_str2 = format!("{:#}", diff_info);
         assert_eq!(diff_info_str2, "      0         7         1\n");
+
+        let diff_info = DiffArchiveSummary {
+            stats: true,
+            ..diff_info
+        };
+
+        let diff_info_str1 = format!("{}", diff_info);
+        assert_eq!(diff_info_str1, "0 7 1 1.5 0.25 1\n");
+
+        let diff_info_str2 = format!("{:#}", diff_info);
+        assert_eq!(
+            diff_info_str2,
+            "      0         7         1      1.500000      0.250000              1\n"
+        );
     }
 
     #[test]
@@ -328,12 +542,34 @@ mod tests {
         let header = DiffSummaryHeader();
 
         let header_str1 = format!("{}", header);
+        assert_eq!(header_str1, "archive total differing");
+
+        let header_str2 = format!("{:#}", header);
         for word in &["archive", "total", "differing"] {
-            assert!(header_str1.contains(word), "should contains {}", word);
+            assert!(header_str2.contains(word), "should contains {}", word);
         }
+        assert!(!header_str2.contains("max_delta"));
+    }
+
+    #[test]
+    fn stats_header_fmt() {
+        let header = DiffStatsHeader();
+
+        let header_str1 = format!("{}", header);
+        assert_eq!(
+            header_str1,
+            "archive total differing max_delta mean_delta over_threshold"
+        );
 
         let header_str2 = format!("{:#}", header);
-        for word in &["archive", "total", "differing"] {
+        for word in &[
+            "archive",
+            "total",
+            "differing",
+            "max_delta",
+            "mean_delta",
+            "over_threshold",
+        ] {
             assert!(header_str2.contains(word), "should contains {}", word);
         }
     }
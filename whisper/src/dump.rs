@@ -0,0 +1,415 @@
+use super::*;
+use serde::{de, Deserializer, Serializer};
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// Machine readable format of a dump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Json,
+    /// Header and archive lines, each followed by `interval,value` lines of its slots.
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(DumpFormat::Json),
+            "csv" => Ok(DumpFormat::Csv),
+            _ => Err(format!("Unsupported dump format '{}'.", s)),
+        }
+    }
+}
+
+/**
+ * Header and raw slots of a whisper file.
+ *
+ * Slots are kept in the order they are stored, including empty ones, so
+ * `Dump::write_file` recreates the file byte for byte.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    pub aggregation_method: AggregationMethod,
+    pub max_retention: u32,
+    pub x_files_factor: f32,
+    pub archives: Vec<DumpArchive>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DumpArchive {
+    pub offset: u32,
+    pub seconds_per_point: u32,
+    pub points: u32,
+    /// Aggregation method of an extended header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation_method: Option<AggregationMethod>,
+    /// xFilesFactor of an extended header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_files_factor: Option<f32>,
+    /// Slot of the newest point, `None` for an empty archive. Ignored by restore.
+    #[serde(default)]
+    pub head: Option<u32>,
+    pub slots: Vec<Slot>,
+}
+
+/// Raw point of a slot, non-finite values are written as their bits, like `0x7ff8000000000000`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Slot {
+    pub interval: u32,
+    #[serde(with = "raw_value")]
+    pub value: f64,
+}
+
+mod raw_value {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if value.is_finite() {
+            serializer.serialize_f64(*value)
+        } else {
+            serializer.collect_str(&format_value(*value))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(f64),
+            Bits(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(value),
+            Raw::Bits(bits) => parse_value(&bits).map_err(de::Error::custom),
+        }
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        format!("{:#018x}", value.to_bits())
+    }
+}
+
+fn parse_value(s: &str) -> Result<f64, String> {
+    match s.strip_prefix("0x") {
+        Some(bits) => u64::from_str_radix(bits, 16)
+            .map(f64::from_bits)
+            .map_err(|_| format!("Bad value bits '{}'", s)),
+        None => s.parse().map_err(|_| format!("Bad value '{}'", s)),
+    }
+}
+
+fn parse_field<T: FromStr>(field: Option<&str>, name: &str) -> Result<T, String> {
+    let field = field.ok_or_else(|| format!("Missing {}", name))?;
+    field
+        .parse()
+        .map_err(|_| format!("Bad {} '{}'", name, field))
+}
+
+fn parse_optional<T: FromStr>(field: Option<&str>, name: &str) -> Result<Option<T>, String> {
+    match field {
+        None | Some("") => Ok(None),
+        field => parse_field(field, name).map(Some),
+    }
+}
+
+fn format_optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+impl Dump {
+    /// Read the header and every slot of `file`.
+    pub fn read<F: Read + Write + Seek>(file: &mut WhisperFile<F>) -> Result<Self, Error> {
+        let meta = file.info().clone();
+        let mut archives = Vec::with_capacity(meta.archives.len());
+        for archive in &meta.archives {
+            let slots = file
+                .iter_slots(archive.seconds_per_point)?
+                .map(|point| {
+                    point.map(|point| Slot {
+                        interval: point.interval,
+                        value: point.value,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            let head = slots
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| slot.interval != 0)
+                .max_by_key(|(_, slot)| slot.interval)
+                .map(|(index, _)| index as u32);
+
+            archives.push(DumpArchive {
+                offset: archive.offset,
+                seconds_per_point: archive.seconds_per_point,
+                points: archive.points,
+                aggregation_method: archive.aggregation_method,
+                x_files_factor: archive.x_files_factor,
+                head,
+                slots,
+            });
+        }
+
+        Ok(Self {
+            aggregation_method: meta.aggregation_method,
+            max_retention: meta.max_retention,
+            x_files_factor: meta.x_files_factor,
+            archives,
+        })
+    }
+
+    fn metadata(&self) -> Result<WhisperMetadata, Error> {
+        let metadata = WhisperMetadata {
+            aggregation_method: self.aggregation_method,
+            max_retention: self.max_retention,
+            x_files_factor: self.x_files_factor,
+            archives: self
+                .archives
+                .iter()
+                .map(|archive| ArchiveInfo {
+                    offset: archive.offset,
+                    seconds_per_point: archive.seconds_per_point,
+                    points: archive.points,
+                    aggregation_method: archive.aggregation_method,
+                    x_files_factor: archive.x_files_factor,
+                })
+                .collect(),
+        };
+
+        if metadata.is_extended()
+            && self.archives.iter().any(|archive| {
+                archive.aggregation_method.is_none() || archive.x_files_factor.is_none()
+            })
+        {
+            return Err(Error::InvalidDump(
+                "Every archive of an extended header needs an aggregation method and xFilesFactor"
+                    .to_owned(),
+            ));
+        }
+
+        let mut end = metadata.header_size() as u64;
+        for (index, archive) in self.archives.iter().enumerate() {
+            if archive.slots.len() != archive.points as usize {
+                return Err(Error::InvalidDump(format!(
+                    "Archive {} has {} slots instead of {}",
+                    index,
+                    archive.slots.len(),
+                    archive.points
+                )));
+            }
+            if u64::from(archive.offset) < end {
+                return Err(Error::InvalidDump(format!(
+                    "Archive {} at offset {} overlaps the header or the previous archive",
+                    index, archive.offset
+                )));
+            }
+            end = u64::from(archive.offset) + archive.points as u64 * POINT_SIZE as u64;
+        }
+
+        Ok(metadata)
+    }
+
+    /// Write the file of the dump to `w`, the header at its start and every archive at its offset.
+    pub fn write_file<W: Write + Seek>(&self, w: &mut W) -> Result<(), Error> {
+        let metadata = self.metadata()?;
+
+        w.seek(io::SeekFrom::Start(0))?;
+        metadata.write(w)?;
+        for archive in &self.archives {
+            let mut bytes = Vec::with_capacity(archive.slots.len() * POINT_SIZE);
+            for slot in &archive.slots {
+                Point {
+                    interval: slot.interval,
+                    value: slot.value,
+                }
+                .write(&mut bytes)?;
+            }
+            w.seek(io::SeekFrom::Start(archive.offset.into()))?;
+            w.write_all(&bytes)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        writeln!(
+            w,
+            "whisper,{},{},{}",
+            self.aggregation_method, self.max_retention, self.x_files_factor
+        )?;
+        for archive in &self.archives {
+            writeln!(
+                w,
+                "archive,{},{},{},{},{},{}",
+                archive.offset,
+                archive.seconds_per_point,
+                archive.points,
+                format_optional(archive.aggregation_method),
+                format_optional(archive.x_files_factor),
+                format_optional(archive.head)
+            )?;
+            for slot in &archive.slots {
+                writeln!(w, "{},{}", slot.interval, format_value(slot.value))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_csv<R: BufRead>(r: R) -> Result<Self, Error> {
+        let mut dump: Option<Dump> = None;
+        for (index, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            Self::read_csv_line(&mut dump, &line)
+                .map_err(|e| Error::InvalidDump(format!("Line {}: {}", index + 1, e)))?;
+        }
+        dump.ok_or_else(|| Error::InvalidDump("Missing whisper header".to_owned()))
+    }
+
+    fn read_csv_line(dump: &mut Option<Dump>, line: &str) -> Result<(), String> {
+        let mut fields = line.split(',').map(str::trim);
+        match (dump, fields.next()) {
+            (dump @ None, Some("whisper")) => {
+                *dump = Some(Dump {
+                    aggregation_method: parse_field(fields.next(), "aggregation method")?,
+                    max_retention: parse_field(fields.next(), "max retention")?,
+                    x_files_factor: parse_field(fields.next(), "xFilesFactor")?,
+                    archives: Vec::new(),
+                });
+            }
+            (None, _) => return Err("Expected whisper header".to_owned()),
+            (Some(dump), Some("archive")) => {
+                dump.archives.push(DumpArchive {
+                    offset: parse_field(fields.next(), "offset")?,
+                    seconds_per_point: parse_field(fields.next(), "seconds per point")?,
+                    points: parse_field(fields.next(), "points")?,
+                    aggregation_method: parse_optional(fields.next(), "aggregation method")?,
+                    x_files_factor: parse_optional(fields.next(), "xFilesFactor")?,
+                    head: parse_optional(fields.next(), "head")?,
+                    slots: Vec::new(),
+                });
+            }
+            (Some(dump), interval) => {
+                let archive = dump
+                    .archives
+                    .last_mut()
+                    .ok_or_else(|| "Slot before the first archive".to_owned())?;
+                archive.slots.push(Slot {
+                    interval: parse_field(interval, "interval")?,
+                    value: parse_value(fields.next().ok_or_else(|| "Missing value".to_owned())?)?,
+                });
+            }
+        }
+        if fields.next().is_some() {
+            return Err("Too many fields".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    fn file() -> Result<WhisperFile<io::Cursor<Vec<u8>>>, Box<dyn std::error::Error>> {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 5,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 3,
+            })
+            .archive_x_files_factor(300, 0.0)
+            .build_in(io::Cursor::new(Vec::new()))?;
+        file.update_many(
+            &[
+                Point {
+                    interval: NOW - 180,
+                    value: 23_076_150.157_894_738,
+                },
+                Point {
+                    interval: NOW - 120,
+                    value: -0.0,
+                },
+                Point {
+                    interval: NOW - 60,
+                    value: 1e-300,
+                },
+                Point {
+                    interval: NOW,
+                    value: f64::NAN,
+                },
+            ],
+            NOW,
+        )?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_json_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = file()?;
+        let dump = Dump::read(&mut file)?;
+        assert_eq!(dump.archives[0].head, Some(3));
+        assert_eq!(dump.archives[1].slots[0].interval, NOW - NOW % 300 - 300);
+
+        let json = serde_json::to_string(&dump)?;
+        assert!(json.contains("\"0x7ff8000000000000\""));
+        assert!(json.contains("23076150.157894738"));
+        let restored: Dump = serde_json::from_str(&json)?;
+
+        let mut bytes = io::Cursor::new(Vec::new());
+        restored.write_file(&mut bytes)?;
+        assert_eq!(bytes.into_inner(), file.into_inner().into_inner());
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = file()?;
+        let dump = Dump::read(&mut file)?;
+
+        let mut csv = Vec::new();
+        dump.write_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert!(csv.starts_with("whisper,average,900,0.5\narchive,56,60,5,average,0.5,3\n"));
+        let restored = Dump::read_csv(csv.as_bytes())?;
+
+        let mut bytes = io::Cursor::new(Vec::new());
+        restored.write_file(&mut bytes)?;
+        assert_eq!(bytes.into_inner(), file.into_inner().into_inner());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_dump() -> Result<(), Box<dyn std::error::Error>> {
+        let mut dump = Dump::read(&mut file()?)?;
+        dump.archives[1].slots.pop();
+        let error = dump
+            .write_file(&mut io::Cursor::new(Vec::new()))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid dump: Archive 1 has 2 slots instead of 3"
+        );
+
+        let error = Dump::read_csv("archive,40,60,5,,,\n".as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid dump: Line 1: Expected whisper header"
+        );
+        Ok(())
+    }
+}
@@ -1,13 +1,47 @@
+use crate::aggregation::AggregationMethod;
+use crate::builder::BuilderError;
 use std::fmt::{Display, Formatter, Result};
 use std::io;
 use std::num::{ParseFloatError, ParseIntError};
 use std::path::PathBuf;
 
+/// Errors of whisper file operations.
 #[derive(Debug)]
 pub enum Error {
+    /// Reading or writing the file failed.
     Io(io::Error),
     FileNotExist(PathBuf),
-    Kind(String),
+    /// Header has an unknown aggregation type.
+    BadAggregationMethod(u32),
+    /// Header has an xFilesFactor outside of `0.0..=1.0`.
+    BadXFilesFactor(f32),
+    /// File is compressed and must be opened with `whisper::compressed`.
+    CompressedFile,
+    /// File is not compressed, but was opened with `whisper::compressed`.
+    NotCompressedFile,
+    /// Compressed file has a format version which can't be read.
+    UnsupportedVersion(u8),
+    /// Header or data can't be decoded.
+    Corrupted(String),
+    /// Timestamp is in the future or older than the max retention.
+    TimestampNotCovered(u32),
+    /// No archive covers the requested time range.
+    NoData,
+    /// Time range is empty or reversed.
+    InvalidInterval(String),
+    /// No archive has the requested seconds per point.
+    ArchiveNotFound(u32),
+    /// A metadata update tried to change the archives.
+    ArchivesChanged,
+    /// Values can't be aggregated.
+    Aggregation(&'static str),
+    /// Header has per-archive settings Graphite whisper can't read.
+    ExtendedHeaderNotCompatible,
+    /// Aggregation method is unknown to Graphite whisper.
+    AggregationNotCompatible(AggregationMethod),
+    Builder(BuilderError),
+    /// Dump can't be read or doesn't describe a valid file.
+    InvalidDump(String),
 }
 
 impl Display for Error {
@@ -15,7 +49,38 @@ impl Display for Error {
         match self {
             Error::Io(e) => write!(f, "{}", e),
             Error::FileNotExist(e) => write!(f, "[ERROR] File {:#?} does not exist!", e),
-            Error::Kind(e) => write!(f, "{}", e),
+            Error::BadAggregationMethod(t) => write!(f, "Bad aggregation method {}", t),
+            Error::BadXFilesFactor(x) => write!(f, "Bad x_files_factor {}", x),
+            Error::CompressedFile => write!(
+                f,
+                "Compressed whisper file, open it with whisper::compressed"
+            ),
+            Error::NotCompressedFile => write!(f, "Not a compressed whisper file"),
+            Error::UnsupportedVersion(v) => {
+                write!(f, "Unsupported compressed whisper version {}", v)
+            }
+            Error::Corrupted(e) => write!(f, "{}", e),
+            Error::TimestampNotCovered(_) => {
+                write!(f, "Timestamp not covered by any archives in this database.")
+            }
+            Error::NoData => write!(f, "No data in selected timerange"),
+            Error::InvalidInterval(e) => write!(f, "{}", e),
+            Error::ArchiveNotFound(s) => write!(f, "Archive not found: {} seconds per point", s),
+            Error::ArchivesChanged => {
+                write!(f, "Archives can't be changed by a metadata update")
+            }
+            Error::Aggregation(e) => write!(f, "{}", e),
+            Error::ExtendedHeaderNotCompatible => write!(
+                f,
+                "Extended header with per-archive settings is not supported by Graphite whisper"
+            ),
+            Error::AggregationNotCompatible(m) => write!(
+                f,
+                "Aggregation method {} is not supported by Graphite whisper",
+                m
+            ),
+            Error::Builder(e) => write!(f, "{}", e),
+            Error::InvalidDump(e) => write!(f, "Invalid dump: {}", e),
         }
     }
 }
@@ -24,6 +89,7 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::Io(e) => Some(e),
+            Self::Builder(e) => Some(e),
             _ => None,
         }
     }
@@ -34,6 +100,16 @@ impl From<io::Error> for Error {
         Error::Io(error)
     }
 }
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParseError {
     ParsePointError(String),
@@ -60,3 +136,29 @@ impl std::error::Error for ParseError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_io_error() {
+        let error = io::Error::from(Error::Io(io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let error = io::Error::from(Error::TimestampNotCovered(60));
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+        let inner = error.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*inner, Error::TimestampNotCovered(60)));
+    }
+
+    #[test]
+    fn test_source() {
+        use std::error::Error as _;
+
+        let error = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(error, Error::Io(_)));
+        assert!(error.source().is_some());
+        assert!(Error::ArchiveNotFound(60).source().is_none());
+    }
+}
@@ -1,18 +1,24 @@
 use std::fs::File;
-use std::io::Result;
+use std::io::{Error, Result};
 
-#[cfg(target_os = "unix")]
+#[cfg(target_os = "linux")]
 pub fn fallocate(fd: &mut File, offset: usize, len: usize) -> Result<()> {
-    use std::os::ext::io::AsRawFd;
+    use std::os::unix::io::AsRawFd;
 
-    libc::posix_fallocate(
-        fd.as_raw_fd(),
-        offset as ::libc::off_t,
-        len as ::libc::off_t,
-    );
+    // posix_fallocate returns the error instead of setting errno
+    match unsafe {
+        libc::posix_fallocate(
+            fd.as_raw_fd(),
+            offset as ::libc::off_t,
+            len as ::libc::off_t,
+        )
+    } {
+        0 => Ok(()),
+        errno => Err(Error::from_raw_os_error(errno)),
+    }
 }
 
-#[cfg(not(target_os = "unix"))]
+#[cfg(not(target_os = "linux"))]
 pub fn fallocate(fd: &mut File, offset: usize, len: usize) -> Result<()> {
     use std::io::{Seek, SeekFrom, Write};
 
@@ -26,3 +32,58 @@ pub fn fallocate(fd: &mut File, offset: usize, len: usize) -> Result<()> {
     fd.write_all(&zeroes[0..remaining])?;
     Ok(())
 }
+
+/// Deallocate a range of the file, which reads back as zeroes. The file size doesn't change.
+#[cfg(target_os = "linux")]
+pub fn punch_hole(fd: &File, offset: u64, len: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe {
+        libc::fallocate(
+            fd.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as ::libc::off_t,
+            len as ::libc::off_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn punch_hole(_fd: &File, _offset: u64, _len: u64) -> Result<()> {
+    Err(Error::new(
+        std::io::ErrorKind::Other,
+        "Punching holes is not supported on this platform",
+    ))
+}
+
+/// Size of the blocks the file system allocates.
+#[cfg(unix)]
+pub fn block_size(fd: &File) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fd.metadata()?.blksize())
+}
+
+#[cfg(not(unix))]
+pub fn block_size(_fd: &File) -> Result<u64> {
+    Ok(4096)
+}
+
+/// Disk space allocated to the file, less than its size for sparse files.
+#[cfg(unix)]
+pub fn allocated_size(fd: &File) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    // st_blocks is in 512-byte units regardless of the block size
+    Ok(fd.metadata()?.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+pub fn allocated_size(fd: &File) -> Result<u64> {
+    Ok(fd.metadata()?.len())
+}
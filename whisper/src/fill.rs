@@ -1,6 +1,5 @@
 use super::*;
 use crate::interval::Interval;
-use std::io;
 use std::path::Path;
 
 fn fill_interval(
@@ -9,11 +8,12 @@ fn fill_interval(
     tstart: u32,
     tsuntil: u32,
     now: u32,
-) -> Result<(), io::Error> {
+    options: OpenOptions,
+) -> Result<(), Error> {
     let mut tstop = tsuntil;
 
-    let mut file_src = WhisperFile::open(src)?;
-    let mut file_dst = WhisperFile::open(dst)?;
+    let mut file_src = options.open(src)?;
+    let mut file_dst = options.open(dst)?;
 
     let mut archives = file_src.info().archives.clone();
     archives.sort_by_key(|a| a.retention());
@@ -68,9 +68,14 @@ fn fill_interval(
     Ok(())
 }
 
-pub fn fill(src: &Path, dst: &Path, from: u32, now: u32) -> Result<(), io::Error> {
+/**
+ * Copies data from src to dst, if missing. When `lock` is set, both files
+ * are locked while they are read or updated.
+ */
+pub fn fill(src: &Path, dst: &Path, from: u32, now: u32, lock: bool) -> Result<(), Error> {
+    let options = OpenOptions::new().lock(lock);
     let mut start_from = from;
-    let mut file_dst = WhisperFile::open(dst)?;
+    let mut file_dst = options.open(dst)?;
 
     let mut archives = file_dst.info().archives.clone();
     archives.sort_by_key(|a| a.retention());
@@ -97,11 +102,11 @@ pub fn fill(src: &Path, dst: &Path, from: u32, now: u32) -> Result<(), io::Error
             } else if let Some(gapstart_unwrap) = gapstart {
                 if v.is_some() {
                     if (start - gapstart_unwrap) > archive.seconds_per_point {
-                        fill_interval(src, dst, gapstart_unwrap, start, now)?;
+                        fill_interval(src, dst, gapstart_unwrap, start, now, options)?;
                     }
                     gapstart = None;
                 } else if start == (end - step) {
-                    fill_interval(src, dst, gapstart_unwrap, start, now)?;
+                    fill_interval(src, dst, gapstart_unwrap, start, now, options)?;
                 }
             }
             start += step;
@@ -0,0 +1,370 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Format of imported points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// `metric,timestamp,value` lines, a header line is skipped.
+    Csv,
+    /// Output of `whisper-fetch --json`, without a metric name.
+    Json,
+    /// `metric timestamp value` lines, like diamond-pipe reads them.
+    Line,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ImportFormat::Csv),
+            "json" => Ok(ImportFormat::Json),
+            "line" => Ok(ImportFormat::Line),
+            _ => Err(format!("Unsupported import format '{}'.", s)),
+        }
+    }
+}
+
+/// Where imported points are written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportTarget {
+    /// Every point goes to one file, metric names are ignored.
+    File(PathBuf),
+    /// Metric `a.b.c` goes to `a/b/c.wsp` under the directory.
+    Tree(PathBuf),
+}
+
+/// Points read from the inputs, grouped by metric.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportData {
+    /// Points of every metric, `None` for points without a metric name.
+    pub metrics: BTreeMap<Option<String>, Vec<Point>>,
+    /// Number of lines that couldn't be parsed.
+    pub malformed: usize,
+}
+
+impl ImportData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read all points of `reader`. Points without a metric name get `metric`.
+    pub fn read<R: BufRead>(
+        &mut self,
+        reader: R,
+        format: ImportFormat,
+        metric: Option<&str>,
+    ) -> Result<(), Error> {
+        match format {
+            ImportFormat::Json => {
+                let data: ArchiveData = serde_json::from_reader(reader).map_err(io::Error::from)?;
+                let points = self.points_of(metric);
+                for (index, value) in data.values.iter().enumerate() {
+                    if let Some(value) = value {
+                        points.push(Point {
+                            interval: data.from_interval + index as u32 * data.step,
+                            value: *value,
+                        });
+                    }
+                }
+            }
+            ImportFormat::Csv | ImportFormat::Line => {
+                for (index, line) in reader.lines().enumerate() {
+                    let line = line?;
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    match parse_line(line, format) {
+                        Some((name, point)) => self.points_of(Some(name)).push(point),
+                        // Header of the CSV export
+                        None if index == 0 && format == ImportFormat::Csv => {}
+                        None => self.malformed += 1,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn points_of(&mut self, metric: Option<&str>) -> &mut Vec<Point> {
+        self.metrics.entry(metric.map(str::to_owned)).or_default()
+    }
+
+    /// Number of points read.
+    pub fn len(&self) -> usize {
+        self.metrics.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn parse_line(line: &str, format: ImportFormat) -> Option<(&str, Point)> {
+    let fields: Vec<&str> = match format {
+        ImportFormat::Csv => line.split(',').map(str::trim).collect(),
+        _ => line.split_whitespace().collect(),
+    };
+    match fields[..] {
+        [name, interval, value] if !name.is_empty() => Some((
+            name,
+            Point {
+                interval: interval.parse().ok()?,
+                value: value.parse().ok()?,
+            },
+        )),
+        _ => None,
+    }
+}
+
+/**
+ * Options of `import`.
+ *
+ * Without a schema points of missing files are skipped.
+ */
+#[derive(Clone)]
+pub struct ImportOptions {
+    schema: Option<WhisperBuilder>,
+    batch_size: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            batch_size: 10_000,
+        }
+    }
+}
+
+impl ImportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create missing files with `schema`.
+    pub fn schema(mut self, schema: WhisperBuilder) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Number of points written by a single `update_many`.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = usize::max(batch_size, 1);
+        self
+    }
+}
+
+/// Totals of `import`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImportReport {
+    /// Number of files written.
+    pub files: usize,
+    /// Number of files created from the schema.
+    pub created: usize,
+    /// Number of points read.
+    pub points: usize,
+    /// Number of points written to the highest precision archive they fit.
+    pub written: usize,
+    /// Number of points older than the retention of every archive.
+    pub too_old: usize,
+    /// Number of points newer than now, written like Graphite does.
+    pub future_written: usize,
+    /// Number of points overwritten by a later point with the same timestamp.
+    pub duplicates: usize,
+    /// Number of lines that couldn't be parsed.
+    pub malformed: usize,
+    /// Number of points of missing files or metrics with an invalid name.
+    pub skipped: usize,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "files {}, created {}, points {}, written {}, too old {}, future written {}, duplicates {}, malformed {}, skipped {}",
+            self.files,
+            self.created,
+            self.points,
+            self.written,
+            self.too_old,
+            self.future_written,
+            self.duplicates,
+            self.malformed,
+            self.skipped
+        )
+    }
+}
+
+/// Path of `metric` in the tree under `root`, `None` if the name is not valid.
+fn metric_path(root: &Path, metric: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in metric.split('.') {
+        if segment.is_empty()
+            || !segment
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path.with_extension("wsp"))
+}
+
+/**
+ * Write the points of `data` to the files of `target`.
+ *
+ * Points of every metric are sorted and written in batches, the files are
+ * locked while they are updated.
+ */
+pub fn import(
+    data: &ImportData,
+    target: &ImportTarget,
+    options: &ImportOptions,
+    now: u32,
+) -> Result<ImportReport, Error> {
+    let mut report = ImportReport {
+        points: data.len(),
+        malformed: data.malformed,
+        ..ImportReport::default()
+    };
+
+    let mut files: BTreeMap<PathBuf, Vec<Point>> = BTreeMap::new();
+    for (metric, points) in &data.metrics {
+        let path = match (target, metric) {
+            (ImportTarget::File(path), _) => Some(path.clone()),
+            (ImportTarget::Tree(root), Some(metric)) => metric_path(root, metric),
+            (ImportTarget::Tree(_), None) => None,
+        };
+        match path {
+            Some(path) => files.entry(path).or_default().extend(points),
+            None => report.skipped += points.len(),
+        }
+    }
+
+    for (path, mut points) in files {
+        if !path.exists() {
+            match options.schema {
+                Some(ref schema) => {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    schema.clone().build(&path)?;
+                    report.created += 1;
+                }
+                None => {
+                    report.skipped += points.len();
+                    continue;
+                }
+            }
+        }
+
+        let mut file = OpenOptions::new().lock(true).open(&path)?;
+        points.sort_by_key(|point| point.interval);
+        for batch in points.chunks(options.batch_size) {
+            let update = file.update_many(batch, now)?;
+            report.written += update.written_total();
+            report.too_old += update.too_old;
+            report.future_written += update.future_written;
+            report.duplicates += update.duplicates;
+        }
+        report.files += 1;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    #[test]
+    fn test_read_formats() -> Result<(), Error> {
+        let mut data = ImportData::new();
+        let csv = "metric,timestamp,value\na.b,100,1.5\na.b,160,2\nbroken line\nc,100,3\n";
+        data.read(csv.as_bytes(), ImportFormat::Csv, None)?;
+        data.read("a.b 220 4\nc 160\n".as_bytes(), ImportFormat::Line, None)?;
+        let json = r#"{"start": 100, "end": 280, "step": 60, "values": [7.0, null, 8.0]}"#;
+        data.read(json.as_bytes(), ImportFormat::Json, Some("d"))?;
+
+        assert_eq!(data.malformed, 2);
+        assert_eq!(data.len(), 6);
+        let intervals = |metric: &str| -> Vec<u32> {
+            data.metrics[&Some(metric.to_owned())]
+                .iter()
+                .map(|point| point.interval)
+                .collect()
+        };
+        assert_eq!(intervals("a.b"), vec![100, 160, 220]);
+        assert_eq!(intervals("c"), vec![100]);
+        assert_eq!(intervals("d"), vec![100, 220]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_path() {
+        let root = Path::new("root");
+        assert_eq!(
+            metric_path(root, "a.b-c.d_e"),
+            Some(root.join("a").join("b-c").join("d_e.wsp"))
+        );
+        assert_eq!(metric_path(root, "a..b"), None);
+        assert_eq!(metric_path(root, "a/../b"), None);
+    }
+
+    #[test]
+    fn test_import_tree() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let mut data = ImportData::new();
+        let lines = format!(
+            "a.b {} 1\na.b {} 2\na.b {} 3\nc {} 4\na.b {} 5\n",
+            NOW - 120,
+            NOW - 60,
+            NOW - 86400,
+            NOW - 60,
+            NOW + 60
+        );
+        data.read(lines.as_bytes(), ImportFormat::Line, None)?;
+        data.read(
+            "{\"start\": 0, \"end\": 60, \"step\": 60, \"values\": [1.0]}".as_bytes(),
+            ImportFormat::Json,
+            None,
+        )?;
+
+        let target = ImportTarget::Tree(dir.path().to_path_buf());
+        let report = import(&data, &target, &ImportOptions::new(), NOW)?;
+        assert_eq!(report.files, 0);
+        assert_eq!(report.skipped, 6);
+
+        let schema = WhisperBuilder::default().add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        });
+        let options = ImportOptions::new().schema(schema).batch_size(1);
+        let report = import(&data, &target, &options, NOW)?;
+        assert_eq!(report.files, 2);
+        assert_eq!(report.created, 2);
+        assert_eq!(report.written, 3);
+        assert_eq!(report.too_old, 1);
+        assert_eq!(report.future_written, 1);
+        assert_eq!(report.skipped, 1);
+
+        let mut file = OpenOptions::new().open(dir.path().join("a").join("b.wsp"))?;
+        let values = file.fetch(60, Interval::new(NOW - 180, NOW)?, NOW)?.values;
+        assert_eq!(values, vec![None, Some(1.0), Some(2.0)]);
+        let values = file
+            .fetch(60, Interval::new(NOW, NOW + 120)?, NOW + 120)?
+            .values;
+        assert_eq!(values, vec![None, Some(5.0)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use super::*;
+
+/// Number of points read from the file at once.
+pub(crate) const CHUNK_POINTS: u32 = 4096;
+
+pub(crate) enum Source<'a, F> {
+    File(&'a mut F),
+    Mapped(&'a [u8]),
+}
+
+/**
+ * Iterator over the points of an archive, reading them in chunks.
+ *
+ * Created with `WhisperFile::iter_archive` or `WhisperFile::iter_slots`.
+ * The file stays locked until the iterator is dropped.
+ */
+pub struct ArchiveIter<'a, F> {
+    source: Source<'a, F>,
+    archive: ArchiveInfo,
+    interval: Interval,
+    next_index: u32,
+    remaining: u32,
+    chunk: std::vec::IntoIter<Point>,
+    _guard: Option<FileLock>,
+}
+
+impl<'a, F: Read + Seek> ArchiveIter<'a, F> {
+    pub(crate) fn new(
+        source: Source<'a, F>,
+        archive: ArchiveInfo,
+        guard: Option<FileLock>,
+    ) -> Self {
+        Self {
+            source,
+            archive,
+            interval: Interval::past(archive.retention(), archive.retention()),
+            next_index: 0,
+            remaining: 0,
+            chunk: Vec::new().into_iter(),
+            _guard: guard,
+        }
+    }
+
+    /// Iterate from the slot of `interval.from()`, nothing if the archive is empty.
+    pub(crate) fn chronological(mut self, interval: Interval) -> Result<Self, Error> {
+        let base = self.read_points(0, 1)?[0];
+        self.interval = interval;
+        if base.interval != 0 {
+            let steps = (interval.until() - interval.from()) / self.archive.seconds_per_point;
+            self.next_index = instant_offset(&self.archive, base.interval, interval.from());
+            self.remaining = u32::min(steps, self.archive.points);
+        }
+        Ok(self)
+    }
+
+    /// Iterate every slot in the order they are stored, starting with the base point.
+    pub(crate) fn slots(mut self) -> Result<Self, Error> {
+        let base = self.read_points(0, 1)?[0];
+        self.interval = Interval::past(
+            base.interval + self.archive.retention(),
+            self.archive.retention(),
+        );
+        self.next_index = 0;
+        self.remaining = self.archive.points;
+        Ok(self)
+    }
+
+    /// Interval of the iterated points, aligned to the archive precision.
+    pub fn interval(&self) -> Interval {
+        self.interval
+    }
+
+    /**
+     * Value of every interval of `interval()`, `None` where the archive
+     * has no point for it, the same as `fetch` returns.
+     */
+    pub fn values(self) -> impl Iterator<Item = Result<(u32, Option<f64>), Error>> + 'a
+    where
+        F: 'a,
+    {
+        let step = self.archive.seconds_per_point;
+        let from = self.interval.from();
+        let count = (self.interval.until() - from) / step;
+        let mut points = self;
+        (0..count).map(move |i| {
+            let interval = from + i * step;
+            match points.next() {
+                Some(Ok(point)) if point.interval == interval => Ok((interval, Some(point.value))),
+                Some(Err(e)) => Err(e),
+                _ => Ok((interval, None)),
+            }
+        })
+    }
+
+    fn read_points(&mut self, from_index: u32, until_index: u32) -> Result<Vec<Point>, io::Error> {
+        match self.source {
+            Source::File(ref mut file) => {
+                read_archive(&mut **file, &self.archive, from_index, until_index)
+            }
+            Source::Mapped(data) => {
+                read_mapped_archive(data, &self.archive, from_index, until_index)
+            }
+        }
+    }
+}
+
+impl<'a, F: Read + Seek> Iterator for ArchiveIter<'a, F> {
+    type Item = Result<Point, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(point) = self.chunk.next() {
+                return Some(Ok(point));
+            }
+            if self.remaining == 0 {
+                return None;
+            }
+
+            // Chunks end at the end of the archive, so they never wrap around
+            let count = u32::min(
+                u32::min(self.remaining, CHUNK_POINTS),
+                self.archive.points - self.next_index,
+            );
+            let until_index = self.next_index + count;
+            match self.read_points(self.next_index, until_index) {
+                Ok(points) => {
+                    self.chunk = points.into_iter();
+                    self.next_index = until_index % self.archive.points;
+                    self.remaining -= count;
+                }
+                Err(e) => {
+                    self.remaining = 0;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    fn file(points: u32) -> Result<WhisperFile<io::Cursor<Vec<u8>>>, Box<dyn std::error::Error>> {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 1,
+                points,
+            })
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        // Twice the retention, so the archive wraps around
+        let points: Vec<Point> = (1..=points * 2)
+            .map(|i| Point {
+                interval: NOW - i,
+                value: f64::from(i),
+            })
+            .collect();
+        file.update_many(&points, NOW)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_iter_archive_matches_fetch() -> Result<(), Box<dyn std::error::Error>> {
+        let points = CHUNK_POINTS * 2 + 100;
+        let mut file = file(points)?;
+
+        for &(from, until) in &[
+            (NOW - points, NOW),
+            (NOW - 10, NOW),
+            (NOW - 5000, NOW - 3000),
+        ] {
+            let interval = Interval::new(from, until)?;
+            let data = file.fetch(1, interval, NOW)?;
+            let values = file
+                .iter_archive(1, interval)?
+                .values()
+                .map(|value| value.map(|(_, value)| value))
+                .collect::<Result<Vec<_>, Error>>()?;
+            assert_eq!(values, data.values);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_archive_chronological() -> Result<(), Box<dyn std::error::Error>> {
+        let points = CHUNK_POINTS + 10;
+        let mut file = file(points)?;
+
+        let interval = Interval::new(NOW - points, NOW)?;
+        let intervals = file
+            .iter_archive(1, interval)?
+            .map(|point| point.map(|point| point.interval))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let expected: Vec<u32> = (NOW - points..NOW).collect();
+        assert_eq!(intervals, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_slots_matches_dump() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = file(CHUNK_POINTS + 10)?;
+
+        let dump = read_archive(&mut file.file, &file.metadata.archives[0], 0, 0)?;
+        let slots = file.iter_slots(1)?.collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(slots, dump);
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_empty_archive() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let interval = Interval::new(NOW - 600, NOW)?;
+        assert_eq!(file.iter_archive(60, interval)?.count(), 0);
+
+        let values = file
+            .iter_archive(60, interval)?
+            .values()
+            .collect::<Result<Vec<_>, Error>>()?;
+        assert_eq!(values.len(), 10);
+        assert!(values.iter().all(|(_, value)| value.is_none()));
+
+        assert_eq!(file.iter_slots(60)?.count(), 10);
+        Ok(())
+    }
+}
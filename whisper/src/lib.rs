@@ -1,9 +1,10 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Read, Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /*
 # This module is an implementation of the Whisper database API
@@ -13,6 +14,8 @@ use std::path::Path;
 #   Header = Metadata,ArchiveInfo+
 #       Metadata = aggregationType,maxRetention,xFilesFactor,archiveCount
 #       ArchiveInfo = Offset,SecondsPerPoint,Points
+#   Extended header sets EXTENDED_HEADER_FLAG in aggregationType and stores
+#       ArchiveInfo = Offset,SecondsPerPoint,Points,aggregationType,xFilesFactor
 #   Data = Archive+
 #       Archive = Point+
 #           Point = timestamp,value
@@ -36,40 +39,71 @@ BUFFERING = 0
 __headerCache = {}
 */
 
+pub mod aggregate;
 pub mod aggregation;
 pub mod archive_info;
+#[cfg(feature = "async")]
+pub mod r#async;
 pub mod builder;
+pub mod cache;
+pub mod check;
+pub mod compressed;
 pub mod diff;
+pub mod dump;
 pub mod error;
 mod fallocate;
 pub mod fill;
+pub mod import;
 pub mod interval;
+pub mod iter;
+mod lock;
 pub mod merge;
+pub mod options;
 pub mod point;
+pub mod repair;
+mod resample;
 pub mod resize;
 pub mod retention;
+pub mod sparsify;
 
 use crate::aggregation::*;
 use crate::archive_info::*;
+use crate::cache::{HeaderCache, Stamp};
+use crate::error::Error;
 use crate::interval::*;
+use crate::iter::{ArchiveIter, Source};
+use crate::lock::FileLock;
 use crate::point::*;
+use crate::retention::Retention;
 
 pub use crate::builder::WhisperBuilder;
+pub use crate::options::{OpenOptions, ReadMode};
 
 pub const METADATA_SIZE: usize = 16;
 pub const ARCHIVE_INFO_SIZE: usize = 12;
+pub const EXTENDED_ARCHIVE_INFO_SIZE: usize = 20;
+/// Set in the aggregation type of files with per-archive aggregation method and xFilesFactor.
+pub const EXTENDED_HEADER_FLAG: u32 = 0x8000_0000;
 pub const POINT_SIZE: usize = 12;
 
-#[derive(Debug, Clone)]
+/**
+ * Header of a whisper file.
+ *
+ * Serialized metadata can be used as a schema of new files, see
+ * `WhisperBuilder::from`. Offsets and max retention are computed then, so
+ * they can be left out.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WhisperMetadata {
     pub aggregation_method: AggregationMethod,
+    #[serde(default)]
     pub max_retention: u32,
     pub x_files_factor: f32,
     pub archives: Vec<ArchiveInfo>,
 }
 
 impl WhisperMetadata {
-    pub fn read<R: Read + Seek>(fh: &mut R) -> Result<Self, io::Error> {
+    pub fn read<R: Read + Seek>(fh: &mut R) -> Result<Self, Error> {
         fh.seek(io::SeekFrom::Start(0))?;
 
         let aggregation_type = fh.read_u32::<BigEndian>()?;
@@ -77,24 +111,57 @@ impl WhisperMetadata {
         let x_files_factor = fh.read_f32::<BigEndian>()?;
         let archive_count = fh.read_u32::<BigEndian>()?;
 
-        let aggregation_method =
-            AggregationMethod::from_type(aggregation_type).ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Bad aggregation method {}", aggregation_type),
-                )
-            })?;
+        let extended = aggregation_type & EXTENDED_HEADER_FLAG != 0;
+        let aggregation_method = AggregationMethod::from_type(
+            aggregation_type & !EXTENDED_HEADER_FLAG,
+        )
+        .ok_or_else(|| {
+            if aggregation_type == BigEndian::read_u32(compressed::MAGIC) {
+                Error::CompressedFile
+            } else {
+                Error::BadAggregationMethod(aggregation_type)
+            }
+        })?;
 
         if x_files_factor < 0.0 || x_files_factor > 1.0 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Bad x_files_factor {}", x_files_factor),
-            ));
+            return Err(Error::BadXFilesFactor(x_files_factor));
         }
 
+        // A corrupted count must not allocate more than the file could hold
+        let archive_info_size = if extended {
+            EXTENDED_ARCHIVE_INFO_SIZE
+        } else {
+            ARCHIVE_INFO_SIZE
+        };
+        let header_size = METADATA_SIZE as u64 + archive_count as u64 * archive_info_size as u64;
+        let size = fh.seek(io::SeekFrom::End(0))?;
+        if header_size > size {
+            return Err(Error::Corrupted(format!(
+                "Header of {} archives takes {} bytes, the file has {} bytes",
+                archive_count, header_size, size
+            )));
+        }
+        fh.seek(io::SeekFrom::Start(METADATA_SIZE as u64))?;
+
         let mut archives = Vec::with_capacity(archive_count as usize);
         for _ in 0..archive_count {
-            let archive_info = ArchiveInfo::read(fh)?;
+            let archive_info = if extended {
+                ArchiveInfo::read_extended(fh)?
+            } else {
+                ArchiveInfo::read(fh)?
+            };
+            if archive_info
+                .seconds_per_point
+                .checked_mul(archive_info.points)
+                .is_none()
+            {
+                return Err(Error::Corrupted(format!(
+                    "Retention of archive {} overflows: {} points of {} seconds",
+                    archives.len(),
+                    archive_info.points,
+                    archive_info.seconds_per_point
+                )));
+            }
             archives.push(archive_info);
         }
 
@@ -106,8 +173,76 @@ impl WhisperMetadata {
         })
     }
 
+    pub fn retentions(&self) -> Vec<Retention> {
+        self.archives
+            .iter()
+            .map(|archive| Retention {
+                seconds_per_point: archive.seconds_per_point,
+                points: archive.points,
+            })
+            .collect()
+    }
+
+    /// Some archive has its own aggregation method or xFilesFactor, so the header is extended.
+    pub fn is_extended(&self) -> bool {
+        self.archives
+            .iter()
+            .any(|archive| archive.aggregation_method.is_some() || archive.x_files_factor.is_some())
+    }
+
+    pub fn archive_info_size(&self) -> usize {
+        if self.is_extended() {
+            EXTENDED_ARCHIVE_INFO_SIZE
+        } else {
+            ARCHIVE_INFO_SIZE
+        }
+    }
+
+    /// Aggregation method used to propagate values into the archive.
+    pub fn archive_aggregation_method(&self, archive: &ArchiveInfo) -> AggregationMethod {
+        archive
+            .aggregation_method
+            .unwrap_or(self.aggregation_method)
+    }
+
+    /// xFilesFactor used to propagate values into the archive.
+    pub fn archive_x_files_factor(&self, archive: &ArchiveInfo) -> f32 {
+        archive.x_files_factor.unwrap_or(self.x_files_factor)
+    }
+
+    /// Set aggregation method of the file, including every archive of an extended header.
+    pub fn set_aggregation_method(&mut self, aggregation_method: AggregationMethod) {
+        self.aggregation_method = aggregation_method;
+        for archive in &mut self.archives {
+            if archive.aggregation_method.is_some() {
+                archive.aggregation_method = Some(aggregation_method);
+            }
+        }
+    }
+
+    /// Set xFilesFactor of the file, including every archive of an extended header.
+    pub fn set_x_files_factor(&mut self, x_files_factor: f32) {
+        self.x_files_factor = x_files_factor;
+        for archive in &mut self.archives {
+            if archive.x_files_factor.is_some() {
+                archive.x_files_factor = Some(x_files_factor);
+            }
+        }
+    }
+
+    /// Fail if Graphite whisper can't read the file.
+    pub fn check_graphite_compat(&self) -> Result<(), Error> {
+        if self.is_extended() {
+            return Err(Error::ExtendedHeaderNotCompatible);
+        }
+        if !self.aggregation_method.is_graphite_compatible() {
+            return Err(Error::AggregationNotCompatible(self.aggregation_method));
+        }
+        Ok(())
+    }
+
     fn header_size(&self) -> usize {
-        METADATA_SIZE + ARCHIVE_INFO_SIZE * self.archives.len()
+        METADATA_SIZE + self.archive_info_size() * self.archives.len()
     }
 
     pub fn file_size(&self) -> usize {
@@ -120,7 +255,12 @@ impl WhisperMetadata {
     }
 
     fn write_metadata<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
-        w.write_u32::<BigEndian>(self.aggregation_method.to_type())?;
+        let flag = if self.is_extended() {
+            EXTENDED_HEADER_FLAG
+        } else {
+            0
+        };
+        w.write_u32::<BigEndian>(self.aggregation_method.to_type() | flag)?;
         w.write_u32::<BigEndian>(self.max_retention)?;
         w.write_f32::<BigEndian>(self.x_files_factor)?;
         w.write_u32::<BigEndian>(self.archives.len() as u32)?;
@@ -129,16 +269,42 @@ impl WhisperMetadata {
 
     fn write<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
         self.write_metadata(w)?;
+        let extended = self.is_extended();
         for archive in &self.archives {
-            archive.write(w)?;
+            if extended {
+                archive.write_extended(w, self.aggregation_method, self.x_files_factor)?;
+            } else {
+                archive.write(w)?;
+            }
         }
         Ok(())
     }
 }
 
-pub struct WhisperFile {
-    metadata: WhisperMetadata,
+/// State of a whisper database backed by a file on disk.
+struct Disk<F> {
+    /// Duplicate handle of the backend, used for locking and syncing.
     file: fs::File,
+    path: PathBuf,
+    lock: bool,
+    cache_headers: bool,
+    mmap: Option<Mmap>,
+    /// Turns a reopened file into the backend.
+    from_file: fn(fs::File) -> F,
+}
+
+/**
+ * Whisper database stored in `F`, a file on disk by default.
+ *
+ * Any `Read + Write + Seek` backend, such as `io::Cursor<Vec<u8>>`, can be
+ * used with `WhisperFile::from_backend` or `WhisperBuilder::build_in`.
+ * Locking, header caching and memory mapping are only available for files
+ * opened by path.
+ */
+pub struct WhisperFile<F = fs::File> {
+    metadata: WhisperMetadata,
+    file: F,
+    disk: Option<Disk<F>>,
 }
 
 impl WhisperFile {
@@ -146,6 +312,7 @@ impl WhisperFile {
         header: &WhisperMetadata,
         path: P,
         sparse: bool,
+        lock: bool,
     ) -> Result<Self, io::Error> {
         let mut metainfo_bytes = Vec::<u8>::new();
         header.write(&mut metainfo_bytes)?;
@@ -154,11 +321,13 @@ impl WhisperFile {
             .read(true)
             .write(true)
             .create_new(true)
-            .open(path)?;
+            .open(path.as_ref())?;
 
-        // if LOCK {
-        //     fcntl.flock(fh.fileno(), fcntl.LOCK_EX)
-        // }
+        let guard = if lock {
+            Some(FileLock::exclusive(&fh)?)
+        } else {
+            None
+        };
 
         fh.write_all(&metainfo_bytes)?;
         if sparse {
@@ -173,86 +342,449 @@ impl WhisperFile {
         }
 
         fh.sync_all()?;
+        drop(guard);
 
         Ok(Self {
             metadata: header.clone(),
+            disk: Some(Disk {
+                file: fh.try_clone()?,
+                path: path.as_ref().to_path_buf(),
+                lock,
+                cache_headers: false,
+                mmap: None,
+                from_file: std::convert::identity,
+            }),
             file: fh,
         })
     }
 
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
-        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
-        let metadata = WhisperMetadata::read(&mut file)?;
-        Ok(Self { metadata, file })
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open_with(path.as_ref(), OpenOptions::default())
+    }
+
+    fn open_with(path: &Path, options: OpenOptions) -> Result<Self, Error> {
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let metadata = {
+            let _guard = if options.lock {
+                Some(FileLock::shared(&file)?)
+            } else {
+                None
+            };
+            read_header(path, &file, options.cache_headers)?
+        };
+        if options.strict_compat {
+            metadata.check_graphite_compat()?;
+        }
+        let mmap = match options.read_mode {
+            ReadMode::Buffered => None,
+            ReadMode::Mmap => Some(map_file(&file)?),
+        };
+        Ok(Self {
+            metadata,
+            disk: Some(Disk {
+                file: file.try_clone()?,
+                path: path.to_path_buf(),
+                lock: options.lock,
+                cache_headers: options.cache_headers,
+                mmap,
+                from_file: std::convert::identity,
+            }),
+            file,
+        })
+    }
+}
+
+fn read_header(
+    path: &Path,
+    mut file: &fs::File,
+    cache_headers: bool,
+) -> Result<WhisperMetadata, Error> {
+    if cache_headers {
+        let cache = HeaderCache::global();
+        match cache.get(path, file)? {
+            Some(metadata) => Ok(metadata),
+            None => {
+                let metadata = WhisperMetadata::read(&mut file)?;
+                cache.insert(path, file, &metadata)?;
+                Ok(metadata)
+            }
+        }
+    } else {
+        WhisperMetadata::read(&mut file)
+    }
+}
+
+fn map_file(file: &fs::File) -> Result<Mmap, io::Error> {
+    // The mapping is only read from, and whisper files are never truncated.
+    unsafe { Mmap::map(file) }
+}
+
+/// Whether `path` no longer refers to `file`, e.g. after `update_metadata` or `resize` in another process.
+#[cfg(unix)]
+fn is_replaced(path: &Path, file: &fs::File) -> Result<bool, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let current = match fs::metadata(path) {
+        Ok(current) => current,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let opened = file.metadata()?;
+    Ok(current.dev() != opened.dev() || current.ino() != opened.ino())
+}
+
+#[cfg(not(unix))]
+fn is_replaced(_path: &Path, _file: &fs::File) -> Result<bool, io::Error> {
+    Ok(false)
+}
+
+/**
+ * Write a copy of `original` with a new metadata block and rename it over
+ * `path`, so the header is never observed half-written, neither after a crash
+ * nor by readers which don't lock the file.
+ *
+ * Permissions, holes and, when the process may change it, the owner of the
+ * file are kept. Other hard links of the file keep the old header.
+ */
+#[cfg(unix)]
+fn replace_header(
+    path: &Path,
+    original: &fs::File,
+    metadata_bytes: &[u8],
+) -> Result<fs::File, io::Error> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path is not a file"))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| -> Result<fs::File, io::Error> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        let attributes = original.metadata()?;
+        file.set_permissions(attributes.permissions())?;
+        match fchown(&file, Some(attributes.uid()), Some(attributes.gid())) {
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            result => result?,
+        }
+        copy_sparse(original, &file, attributes.len())?;
+        file.seek(io::SeekFrom::Start(0))?;
+        file.write_all(metadata_bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(file)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    let file = result?;
+
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(file)
+}
+
+/// Copy the first `len` bytes of `from`, leaving holes for blocks which only hold zeroes.
+#[cfg(unix)]
+fn copy_sparse(mut from: &fs::File, mut to: &fs::File, len: u64) -> Result<(), io::Error> {
+    let block = fallocate::block_size(from)? as usize;
+    let mut buffer = vec![0; block * 16];
+    let mut offset = 0;
+    from.seek(io::SeekFrom::Start(0))?;
+    while offset < len {
+        let count = u64::min(len - offset, buffer.len() as u64) as usize;
+        from.read_exact(&mut buffer[..count])?;
+        for chunk in buffer[..count].chunks(block) {
+            if chunk.iter().any(|&byte| byte != 0) {
+                to.seek(io::SeekFrom::Start(offset))?;
+                to.write_all(chunk)?;
+            }
+            offset += chunk.len() as u64;
+        }
+    }
+    to.set_len(len)
+}
+
+impl<F: Read + Write + Seek> WhisperFile<F> {
+    /// Open a whisper database stored in `backend`.
+    pub fn from_backend(mut backend: F) -> Result<Self, Error> {
+        let metadata = WhisperMetadata::read(&mut backend)?;
+        Ok(Self {
+            metadata,
+            file: backend,
+            disk: None,
+        })
+    }
+
+    fn create_in(header: &WhisperMetadata, mut backend: F) -> Result<Self, io::Error> {
+        backend.seek(io::SeekFrom::Start(0))?;
+        header.write(&mut backend)?;
+        let data_size = header.file_size() - header.header_size();
+        io::copy(&mut io::repeat(0).take(data_size as u64), &mut backend)?;
+        backend.flush()?;
+
+        Ok(Self {
+            metadata: header.clone(),
+            file: backend,
+            disk: None,
+        })
+    }
+
+    pub fn get_ref(&self) -> &F {
+        &self.file
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+
+    /// Stamp of the file before a write, if its header is cached.
+    fn cache_stamp(&self) -> Result<Option<Stamp>, io::Error> {
+        match self.disk {
+            Some(ref disk) if disk.cache_headers => Stamp::of(&disk.file).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Keep the cached header valid after this handle has modified the file.
+    fn refresh_cache(&self, before: Option<Stamp>) -> Result<(), io::Error> {
+        match (&self.disk, before) {
+            (Some(ref disk), Some(before)) => {
+                HeaderCache::global().refresh(&disk.path, &disk.file, before, &self.metadata)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Switch to `file`, which has replaced the one this handle was opened with.
+    fn use_file(&mut self, file: fs::File) -> Result<(), io::Error> {
+        if let Some(ref mut disk) = self.disk {
+            self.metadata = read_header(&disk.path, &file, disk.cache_headers)?;
+            if disk.mmap.is_some() {
+                disk.mmap = Some(map_file(&file)?);
+            }
+            self.file = (disk.from_file)(file.try_clone()?);
+            disk.file = file;
+        }
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<(), io::Error> {
+        let file = match self.disk {
+            Some(ref disk) => fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&disk.path)?,
+            None => return Ok(()),
+        };
+        self.use_file(file)
+    }
+
+    fn lock_with(
+        &mut self,
+        acquire: fn(&fs::File) -> Result<FileLock, io::Error>,
+    ) -> Result<Option<FileLock>, io::Error> {
+        loop {
+            let guard = match self.disk {
+                Some(ref disk) if disk.lock => {
+                    let guard = acquire(&disk.file)?;
+                    if !is_replaced(&disk.path, &disk.file)? {
+                        // The header may have been written in place, e.g. by `repair`
+                        self.metadata = read_header(&disk.path, &disk.file, disk.cache_headers)?;
+                        return Ok(Some(guard));
+                    }
+                    guard
+                }
+                _ => return Ok(None),
+            };
+            // Release the old file before its handle is closed
+            drop(guard);
+            self.reopen()?;
+        }
+    }
+
+    fn lock_exclusive(&mut self) -> Result<Option<FileLock>, io::Error> {
+        self.lock_with(FileLock::exclusive)
+    }
+
+    fn lock_shared(&mut self) -> Result<Option<FileLock>, io::Error> {
+        self.lock_with(FileLock::shared)
+    }
+
+    fn sync_data(&mut self) -> Result<(), io::Error> {
+        self.file.flush()?;
+        match self.disk {
+            Some(ref disk) => disk.file.sync_data(),
+            None => Ok(()),
+        }
     }
 
     pub fn info(&self) -> &WhisperMetadata {
         &self.metadata
     }
 
-    pub fn set_x_files_factor(&mut self, x_files_factor: f32) -> Result<(), io::Error> {
-        if x_files_factor < 0.0 || x_files_factor > 1.0 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Bad x_files_factor {}", x_files_factor),
-            ));
+    /**
+     * Change the aggregation method and xFilesFactor of the file in one step.
+     *
+     * Files on disk are copied to a temporary file with the new header, which
+     * is then renamed over the original while the original is locked
+     * exclusively, so readers and crashes never observe a partially written
+     * header. See `replace_header` for the attributes kept. Other handles
+     * switch to the new file when they lock it, handles which don't lock it
+     * keep using the file they have opened, and writes made through them
+     * without a lock meanwhile are lost. Other backends are written in place.
+     * Archives can't be changed this way, see `resize` instead, but
+     * per-archive aggregation method and xFilesFactor of a file with an
+     * extended header can.
+     */
+    pub fn update_metadata<U>(&mut self, update: U) -> Result<(), Error>
+    where
+        U: FnOnce(&mut WhisperMetadata),
+    {
+        let guard = self.lock_exclusive()?;
+
+        let mut metadata = self.metadata.clone();
+        update(&mut metadata);
+
+        let x_files_factors = metadata
+            .archives
+            .iter()
+            .filter_map(|archive| archive.x_files_factor);
+        for x_files_factor in std::iter::once(metadata.x_files_factor).chain(x_files_factors) {
+            if !(0.0..=1.0).contains(&x_files_factor) {
+                return Err(Error::BadXFilesFactor(x_files_factor));
+            }
         }
 
-        // if LOCK:
-        //     fcntl.flock(fh.fileno(), fcntl.LOCK_EX)
+        if metadata.max_retention != self.metadata.max_retention
+            || metadata.is_extended() != self.metadata.is_extended()
+            || metadata.archives.len() != self.metadata.archives.len()
+            || metadata
+                .archives
+                .iter()
+                .zip(&self.metadata.archives)
+                .any(|(a, b)| !a.same_layout(b))
+        {
+            return Err(Error::ArchivesChanged);
+        }
 
-        self.file.seek(io::SeekFrom::Start(0))?;
-        self.metadata.x_files_factor = x_files_factor; // TODO: transactional update
-        self.metadata.write_metadata(&mut self.file)?;
-        self.file.sync_data()?;
+        let mut metadata_bytes = Vec::with_capacity(metadata.header_size());
+        metadata.write(&mut metadata_bytes)?;
+
+        match self.disk {
+            #[cfg(unix)]
+            Some(ref disk) => {
+                self.file.flush()?;
+                let file = replace_header(&disk.path, &disk.file, &metadata_bytes)?;
+                HeaderCache::global().invalidate(&disk.path);
+                drop(guard);
+                self.use_file(file)?;
+            }
+            _ => {
+                let before = self.cache_stamp()?;
+                self.file.seek(io::SeekFrom::Start(0))?;
+                self.file.write_all(&metadata_bytes)?;
+                self.sync_data()?;
+                self.metadata = metadata;
+                self.refresh_cache(before)?;
+                drop(guard);
+            }
+        }
 
         Ok(())
     }
 
+    /// Set xFilesFactor of the file, including every archive of an extended header.
+    pub fn set_x_files_factor(&mut self, x_files_factor: f32) -> Result<(), Error> {
+        self.update_metadata(|metadata| metadata.set_x_files_factor(x_files_factor))
+    }
+
+    /// Set aggregation method of the file, including every archive of an extended header.
     pub fn set_aggregation_method(
         &mut self,
         aggregation_method: AggregationMethod,
-    ) -> Result<(), io::Error> {
-        // if LOCK:
-        //     fcntl.flock(fh.fileno(), fcntl.LOCK_EX)
-
-        self.file.seek(io::SeekFrom::Start(0))?;
-        self.metadata.aggregation_method = aggregation_method; // TODO: transactional update
-        self.metadata.write_metadata(&mut self.file)?;
-        self.file.sync_data()?;
+    ) -> Result<(), Error> {
+        self.update_metadata(|metadata| metadata.set_aggregation_method(aggregation_method))
+    }
 
-        Ok(())
+    /**
+     * Recompute lower archives with the current aggregation methods and xFilesFactors,
+     * for example after `set_aggregation_method`.
+     *
+     * Every lower archive is re-derived from the highest precision data still
+     * available, rollups older than the retention of the highest precision
+     * archive are left untouched. Returns the number of rewritten points.
+     */
+    pub fn rebuild(&mut self, now: u32) -> Result<usize, Error> {
+        let _guard = self.lock_exclusive()?;
+        file_rebuild(&mut self.file, &self.metadata, now)
     }
 
-    pub fn update(&mut self, point: &Point, now: u32) -> Result<(), io::Error> {
-        // if LOCK:
-        //     fcntl.flock(fh.fileno(), fcntl.LOCK_EX)
-        file_update(&mut self.file, &self.metadata, point, now)
+    /// Write a point, failing if it's in the future or beyond the retention of every archive.
+    pub fn update(&mut self, point: &Point, now: u32) -> Result<UpdateReport, Error> {
+        let _guard = self.lock_exclusive()?;
+        let before = self.cache_stamp()?;
+        let report = file_update(&mut self.file, &self.metadata, point, now)?;
+        self.refresh_cache(before)?;
+        Ok(report)
     }
 
-    pub fn update_many(&mut self, points: &[Point], now: u32) -> Result<(), io::Error> {
+    /// Write points, skipping the ones beyond the retention of every archive.
+    pub fn update_many(&mut self, points: &[Point], now: u32) -> Result<UpdateReport, Error> {
         if points.is_empty() {
-            return Ok(());
+            return Ok(UpdateReport::new(self.metadata.archives.len()));
         }
 
-        // if LOCK:
-        //     fcntl.flock(fh.fileno(), fcntl.LOCK_EX)
+        let _guard = self.lock_exclusive()?;
 
         // if CAN_FADVISE and FADVISE_RANDOM:
         //     posix_fadvise(fh.fileno(), 0, 0, POSIX_FADV_RANDOM)
 
         let mut points_vec = points.to_vec();
         points_vec.sort_by_key(|p| std::u32::MAX - p.interval); // Order points by timestamp, newest first
-        file_update_many(&mut self.file, &self.metadata, &points_vec, now)
+        let before = self.cache_stamp()?;
+        let report = file_update_many(&mut self.file, &self.metadata, &points_vec, now)?;
+        self.refresh_cache(before)?;
+        Ok(report)
     }
 
-    fn find_archive(&self, seconds_per_point: u32) -> Result<ArchiveInfo, io::Error> {
+    /// Write chronologically sorted points into the archive at `index` and propagate them.
+    pub(crate) fn update_archive(
+        &mut self,
+        index: usize,
+        points: &[Point],
+    ) -> Result<UpdateReport, Error> {
+        let mut report = UpdateReport::new(self.metadata.archives.len());
+        if points.is_empty() {
+            return Ok(report);
+        }
+
+        let _guard = self.lock_exclusive()?;
+        let before = self.cache_stamp()?;
+        __archive_update_many(&mut self.file, &self.metadata, index, points, &mut report)?;
+        self.refresh_cache(before)?;
+        Ok(report)
+    }
+
+    fn find_archive(&self, seconds_per_point: u32) -> Result<ArchiveInfo, Error> {
         self.metadata
             .archives
             .iter()
             .find(|archive| archive.seconds_per_point == seconds_per_point)
             .map(|a| a.to_owned())
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Archive not found"))
+            .ok_or(Error::ArchiveNotFound(seconds_per_point))
     }
 
     pub fn suggest_archive(&self, interval: Interval, now: u32) -> Option<u32> {
@@ -274,7 +806,7 @@ impl WhisperFile {
         seconds_per_point: u32,
         interval: Interval,
         now: u32,
-    ) -> Result<(Interval, Option<Vec<Point>>), io::Error> {
+    ) -> Result<(Interval, Option<Vec<Point>>), Error> {
         let archive = self.find_archive(seconds_per_point)?;
         let available = Interval::past(now, self.metadata.max_retention);
 
@@ -285,12 +817,19 @@ impl WhisperFile {
 
         let interval = available
             .intersection(interval)
-            .map_err(|s| io::Error::new(io::ErrorKind::Other, s))?;
-
-        let adjusted_interval = adjust_interval(interval, archive.seconds_per_point)
-            .map_err(|s| io::Error::new(io::ErrorKind::Other, s))?;
-
-        let points = archive_fetch_interval(&mut self.file, &archive, adjusted_interval)?;
+            .map_err(Error::InvalidInterval)?;
+
+        let adjusted_interval =
+            adjust_interval(interval, archive.seconds_per_point).map_err(Error::InvalidInterval)?;
+
+        let _guard = self.lock_shared()?;
+        let points = match self.disk {
+            Some(Disk {
+                mmap: Some(ref mmap),
+                ..
+            }) => mapped_archive_fetch_interval(mmap, &archive, adjusted_interval)?,
+            _ => archive_fetch_interval(&mut self.file, &archive, adjusted_interval)?,
+        };
 
         Ok((adjusted_interval, points))
     }
@@ -299,10 +838,8 @@ impl WhisperFile {
         &mut self,
         interval: Interval,
         now: u32,
-    ) -> Result<ArchiveData, io::Error> {
-        let seconds_per_point = self
-            .suggest_archive(interval, now)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No data in selected timerange"))?;
+    ) -> Result<ArchiveData, Error> {
+        let seconds_per_point = self.suggest_archive(interval, now).ok_or(Error::NoData)?;
 
         let (adjusted_interval, points) = self.fetch_points(seconds_per_point, interval, now)?;
         let data = points_to_data(&points, adjusted_interval, seconds_per_point);
@@ -314,15 +851,103 @@ impl WhisperFile {
         seconds_per_point: u32,
         interval: Interval,
         now: u32,
-    ) -> Result<ArchiveData, io::Error> {
+    ) -> Result<ArchiveData, Error> {
         let (adjusted_interval, points) = self.fetch_points(seconds_per_point, interval, now)?;
         let data = points_to_data(&points, adjusted_interval, seconds_per_point);
         Ok(data)
     }
 
-    pub fn dump(&mut self, seconds_per_point: u32) -> Result<Vec<Point>, io::Error> {
+    /**
+     * Fetch `interval` from every archive that covers a part of it, using the
+     * highest resolution available for each part.
+     *
+     * Segments are returned in chronological order, each with its own step,
+     * and lower archives start where higher ones end.
+     */
+    pub fn fetch_stitched(
+        &mut self,
+        interval: Interval,
+        now: u32,
+    ) -> Result<Vec<ArchiveData>, Error> {
+        let interval = match Interval::past(now, self.metadata.max_retention).intersection(interval)
+        {
+            Ok(interval) => interval,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let archives = self.metadata.archives.clone();
+        let mut segments = Vec::new();
+        let mut until = interval.until();
+        for (index, archive) in archives.iter().enumerate() {
+            let archive_from = now.saturating_sub(archive.retention());
+            let from = match archives.get(index + 1) {
+                // Switch to the lower archive on its step boundary
+                Some(lower) if archive_from > interval.from() => {
+                    adjust_instant_up(archive_from, lower.seconds_per_point)
+                }
+                _ => interval.from(),
+            };
+
+            if from < until {
+                let segment = Interval::new(from, until).map_err(Error::InvalidInterval)?;
+                let (adjusted_interval, points) =
+                    self.fetch_points(archive.seconds_per_point, segment, now)?;
+                segments.push(points_to_data(
+                    &points,
+                    adjusted_interval,
+                    archive.seconds_per_point,
+                ));
+                until = adjusted_interval.from();
+            }
+
+            if from <= interval.from() {
+                break;
+            }
+        }
+
+        segments.reverse();
+        Ok(segments)
+    }
+
+    pub fn dump(&mut self, seconds_per_point: u32) -> Result<Vec<Point>, Error> {
+        self.iter_slots(seconds_per_point)?.collect()
+    }
+
+    /**
+     * Iterate over the points of an archive in chronological order, starting
+     * at the slot of `interval.from()` and wrapping around the end of the archive.
+     *
+     * Points are read in chunks instead of loading the whole archive. They
+     * are returned as stored, `ArchiveIter::values` checks their intervals
+     * like `fetch` does. Nothing is returned for an empty archive.
+     */
+    pub fn iter_archive(
+        &mut self,
+        seconds_per_point: u32,
+        interval: Interval,
+    ) -> Result<ArchiveIter<'_, F>, Error> {
+        let interval =
+            adjust_interval(interval, seconds_per_point).map_err(Error::InvalidInterval)?;
+        self.archive_iter(seconds_per_point)?
+            .chronological(interval)
+    }
+
+    /// Iterate over every slot of an archive in the order they are stored, like `dump`.
+    pub fn iter_slots(&mut self, seconds_per_point: u32) -> Result<ArchiveIter<'_, F>, Error> {
+        self.archive_iter(seconds_per_point)?.slots()
+    }
+
+    fn archive_iter(&mut self, seconds_per_point: u32) -> Result<ArchiveIter<'_, F>, Error> {
         let archive = self.find_archive(seconds_per_point)?;
-        read_archive(&mut self.file, &archive, 0, archive.points)
+        let guard = self.lock_shared()?;
+        let source = match self.disk {
+            Some(Disk {
+                mmap: Some(ref mmap),
+                ..
+            }) => Source::Mapped(mmap),
+            _ => Source::File(&mut self.file),
+        };
+        Ok(ArchiveIter::new(source, archive, guard))
     }
 }
 
@@ -379,6 +1004,53 @@ fn read_archive<R: Read + Seek>(
     Ok(series)
 }
 
+fn mapped_slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], io::Error> {
+    data.get(offset..offset + len).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Archive is beyond the end of file",
+        )
+    })
+}
+
+fn decode_points(bytes: &[u8], series: &mut Vec<Point>) {
+    series.extend(bytes.chunks_exact(POINT_SIZE).map(|chunk| Point {
+        interval: BigEndian::read_u32(&chunk[0..4]),
+        value: BigEndian::read_f64(&chunk[4..]),
+    }));
+}
+
+/// Same as `read_archive`, but decodes points straight from a mapped file.
+fn read_mapped_archive(
+    data: &[u8],
+    archive: &ArchiveInfo,
+    from_index: u32,
+    until_index: u32,
+) -> Result<Vec<Point>, io::Error> {
+    let from_index = from_index % archive.points;
+    let until_index = until_index % archive.points;
+
+    let mut series =
+        Vec::with_capacity(((archive.points + until_index - from_index) % archive.points) as usize);
+
+    let offset = archive.offset as usize;
+    let from_offset = offset + from_index as usize * POINT_SIZE;
+
+    if from_index < until_index {
+        // If we don't wrap around the archive
+        let len = (until_index - from_index) as usize * POINT_SIZE;
+        decode_points(mapped_slice(data, from_offset, len)?, &mut series);
+    } else {
+        // We do wrap around the archive, so we need two slices
+        let tail_len = (archive.points - from_index) as usize * POINT_SIZE;
+        decode_points(mapped_slice(data, from_offset, tail_len)?, &mut series);
+        let head_len = until_index as usize * POINT_SIZE;
+        decode_points(mapped_slice(data, offset, head_len)?, &mut series);
+    }
+
+    Ok(series)
+}
+
 fn write_archive_point<F: Read + Write + Seek>(
     fh: &mut F,
     archive: &ArchiveInfo,
@@ -450,7 +1122,7 @@ fn __propagate<F: Read + Write + Seek>(
     timestamp: u32,
     higher: &ArchiveInfo,
     lower: &ArchiveInfo,
-) -> Result<bool, io::Error> {
+) -> Result<bool, Error> {
     let lower_interval_start = timestamp - (timestamp % lower.seconds_per_point);
 
     fh.seek(io::SeekFrom::Start(higher.offset.into()))?;
@@ -475,12 +1147,12 @@ fn __propagate<F: Read + Write + Seek>(
     }
 
     let known_percent = known_values as f32 / neighbor_values.len() as f32;
-    if known_percent >= header.x_files_factor {
+    if known_percent >= header.archive_x_files_factor(lower) {
         // We have enough data to propagate a value!
         let aggregate_value = header
-            .aggregation_method
+            .archive_aggregation_method(lower)
             .aggregate(&neighbor_values)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(Error::Aggregation)?;
 
         let my_point = Point {
             interval: lower_interval_start,
@@ -495,19 +1167,189 @@ fn __propagate<F: Read + Write + Seek>(
     }
 }
 
-fn file_update(
-    fh: &mut fs::File,
+/**
+ * Same as calling `__propagate` for every interval in chronological order,
+ * but reads the affected range of the higher archive once and writes
+ * the aggregated points in contiguous runs.
+ *
+ * It's expected that lower intervals are aligned, unique and sorted in chronological order
+ */
+fn __propagate_many<F: Read + Write + Seek>(
+    fh: &mut F,
+    header: &WhisperMetadata,
+    lower_intervals: &[u32],
+    higher: &ArchiveInfo,
+    lower: &ArchiveInfo,
+) -> Result<bool, Error> {
+    let (first_interval, last_interval) = match (lower_intervals.first(), lower_intervals.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(false),
+    };
+
+    let mut lower_points: Vec<Point> =
+        aggregate_intervals(fh, header, lower_intervals, higher, lower)?
+            .into_iter()
+            .filter_map(|(interval, value)| value.map(|value| Point { interval, value }))
+            .collect();
+
+    if lower_points.is_empty() {
+        return Ok(false);
+    }
+
+    let lower_base = lower.read_base(fh)?;
+    let base_interval = if lower_base.interval == 0 {
+        lower_points[0].interval
+    } else {
+        lower_base.interval
+    };
+
+    if last_interval - first_interval >= lower.retention() {
+        // Points sharing a slot overwrite each other, keep the latest one
+        let mut slots = HashSet::new();
+        lower_points.reverse();
+        lower_points
+            .retain(|point| slots.insert(instant_offset(lower, base_interval, point.interval)));
+        lower_points.reverse();
+    }
+
+    for chunk in pack_points(&lower_points, lower.seconds_per_point) {
+        write_archive(fh, lower, &chunk, base_interval)?;
+    }
+
+    Ok(true)
+}
+
+/**
+ * Aggregate the points of the higher archive for every lower interval with
+ * the aggregation method and xFilesFactor of the lower archive, like `__propagate`.
+ *
+ * Values are `None` where there isn't enough known data.
+ */
+fn aggregate_intervals<F: Read + Seek>(
+    fh: &mut F,
+    header: &WhisperMetadata,
+    lower_intervals: &[u32],
+    higher: &ArchiveInfo,
+    lower: &ArchiveInfo,
+) -> Result<Vec<(u32, Option<f64>)>, Error> {
+    let (first_interval, last_interval) = match (lower_intervals.first(), lower_intervals.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(Vec::new()),
+    };
+
+    let higher_base = higher.read_base(fh)?;
+    let higher_points = lower.seconds_per_point / higher.seconds_per_point;
+
+    let span = (last_interval - first_interval) / higher.seconds_per_point + higher_points;
+    let (start_index, series) = if higher_base.interval == 0 || span >= higher.points {
+        // The whole archive is affected, read it from the start
+        (0, read_archive(fh, higher, 0, 0)?)
+    } else {
+        let start_index = instant_offset(higher, higher_base.interval, first_interval);
+        let series = read_archive(fh, higher, start_index, start_index + span)?;
+        (start_index, series)
+    };
+
+    let mut lower_values = Vec::with_capacity(lower_intervals.len());
+    for &lower_interval_start in lower_intervals {
+        let higher_first_index = instant_offset(higher, higher_base.interval, lower_interval_start);
+        let position = (higher.points + higher_first_index - start_index) % higher.points;
+        let neighbors: Vec<Point> = (position..position + higher_points)
+            .map(|i| series[i as usize % series.len()])
+            .collect();
+
+        let neighbor_values =
+            points_to_values(&neighbors, lower_interval_start, higher.seconds_per_point);
+
+        let known_values = neighbor_values.iter().filter(|v| v.is_some()).count();
+        let known_percent = known_values as f32 / neighbor_values.len() as f32;
+        let value = if known_values > 0 && known_percent >= header.archive_x_files_factor(lower) {
+            let aggregate_value = header
+                .archive_aggregation_method(lower)
+                .aggregate(&neighbor_values)
+                .map_err(Error::Aggregation)?;
+            Some(aggregate_value)
+        } else {
+            None
+        };
+
+        lower_values.push((lower_interval_start, value));
+    }
+
+    Ok(lower_values)
+}
+
+/**
+ * Re-derive lower archives from the highest precision archive, one after another.
+ *
+ * Only lower intervals entirely within the retention of the highest precision
+ * archive are rewritten. Intervals without enough known data are cleared.
+ */
+fn file_rebuild<F: Read + Write + Seek>(
+    fh: &mut F,
+    header: &WhisperMetadata,
+    now: u32,
+) -> Result<usize, Error> {
+    let since = match header.archives.first() {
+        Some(archive) => now.saturating_sub(archive.retention()),
+        None => return Ok(0),
+    };
+
+    let mut rebuilt = 0;
+    for pair in header.archives.windows(2) {
+        let (higher, lower) = (&pair[0], &pair[1]);
+        let step = lower.seconds_per_point;
+        let first_interval = since + (step - since % step) % step;
+        let last_interval = now - now % step;
+        if first_interval > last_interval {
+            continue;
+        }
+
+        let lower_intervals: Vec<u32> = (first_interval..=last_interval)
+            .step_by(step as usize)
+            .collect();
+        let values = aggregate_intervals(fh, header, &lower_intervals, higher, lower)?;
+
+        let lower_base = lower.read_base(fh)?;
+        let base_interval = if lower_base.interval != 0 {
+            lower_base.interval
+        } else if let Some(&(interval, _)) = values.iter().find(|(_, value)| value.is_some()) {
+            interval
+        } else {
+            // Nothing to clear in an empty archive
+            continue;
+        };
+
+        // A point of a previous cycle of the archive reads as unknown but keeps
+        // the base of the archive if it lands in the first slot
+        let lower_points: Vec<Point> = values
+            .into_iter()
+            .map(|(interval, value)| match value {
+                Some(value) => Point { interval, value },
+                None => Point {
+                    interval: interval.saturating_sub(lower.retention()),
+                    value: 0.0,
+                },
+            })
+            .collect();
+
+        rebuilt += lower_points.len();
+        write_archive(fh, lower, &lower_points, base_interval)?;
+    }
+
+    Ok(rebuilt)
+}
+
+fn file_update<F: Read + Write + Seek>(
+    fh: &mut F,
     header: &WhisperMetadata,
     point: &Point,
     now: u32,
-) -> Result<(), io::Error> {
+) -> Result<UpdateReport, Error> {
     let timestamp = point.interval;
 
     if now >= timestamp + header.max_retention || now < timestamp {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Timestamp not covered by any archives in this database.",
-        ));
+        return Err(Error::TimestampNotCovered(timestamp));
     }
 
     // Find the highest-precision archive that covers timestamp
@@ -515,12 +1357,7 @@ fn file_update(
         .archives
         .iter()
         .position(|a| timestamp + a.retention() >= now)
-        .ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                "Timestamp not covered by any archives in this database.",
-            )
-        })?;
+        .ok_or(Error::TimestampNotCovered(timestamp))?;
 
     let archive = &header.archives[archive_index];
 
@@ -533,34 +1370,42 @@ fn file_update(
 
     write_archive_point(fh, archive, &adjusted_point)?;
 
+    let mut report = UpdateReport::new(header.archives.len());
+    report.written[archive_index] = 1;
+
     // Now we propagate the update to lower-precision archives
-    for pair in header.archives[archive_index..].windows(2) {
+    for (i, pair) in header.archives[archive_index..].windows(2).enumerate() {
         let higher = &pair[0];
         let lower = &pair[1];
         if !__propagate(fh, &header, interval, higher, lower)? {
             break;
         }
+        report.propagated.push(archive_index + i + 1);
     }
 
-    Ok(())
+    Ok(report)
 }
 
-fn file_update_many(
-    fh: &mut fs::File,
+fn file_update_many<F: Read + Write + Seek>(
+    fh: &mut F,
     header: &WhisperMetadata,
     points: &[Point],
     now: u32,
-) -> Result<(), io::Error> {
+) -> Result<UpdateReport, Error> {
+    let mut report = UpdateReport::new(header.archives.len());
     let mut archive_index = 0;
     let mut current_points = vec![];
 
-    for point in points {
+    // Points are sorted newest first
+    report.future_written = points.iter().take_while(|p| p.interval > now).count();
+
+    for (i, point) in points.iter().enumerate() {
         while point.interval + header.archives[archive_index].retention() < now {
             // We can't fit any more points in this archive
             if !current_points.is_empty() {
                 // Commit all the points we've found that it can fit
                 current_points.reverse(); // Put points in chronological order
-                __archive_update_many(fh, &header, archive_index, &current_points)?;
+                __archive_update_many(fh, header, archive_index, &current_points, &mut report)?;
                 current_points.clear();
             }
             archive_index += 1;
@@ -570,7 +1415,9 @@ fn file_update_many(
         }
 
         if archive_index >= header.archives.len() {
-            break; // Drop remaining points that don't fit in the database
+            // Drop remaining points that don't fit in the database
+            report.too_old = points.len() - i;
+            break;
         }
 
         current_points.push(*point);
@@ -579,10 +1426,32 @@ fn file_update_many(
     // Don't forget to commit after we've checked all the archives
     if archive_index < header.archives.len() && !current_points.is_empty() {
         current_points.reverse();
-        __archive_update_many(fh, &header, archive_index, &current_points)?;
+        __archive_update_many(fh, header, archive_index, &current_points, &mut report)?;
     }
 
-    Ok(())
+    // Future points were written to the highest-precision archive, only count them in `future_written`
+    if report.future_written > 0 {
+        let archive = &header.archives[0];
+        let mut intervals: Vec<u32> = points
+            .iter()
+            .take_while(|p| p.interval + archive.retention() >= now)
+            .map(|p| p.align(archive.seconds_per_point).interval)
+            .collect();
+        let mut past = intervals.split_off(report.future_written);
+        intervals.extend_from_slice(&past);
+        intervals.sort_unstable();
+        intervals.dedup();
+        past.sort_unstable();
+        past.dedup();
+
+        let future_slots = intervals.len() - past.len();
+        report.written[0] -= future_slots;
+        report.duplicates -= report.future_written - future_slots;
+    }
+
+    report.propagated.sort_unstable();
+    report.propagated.dedup();
+    Ok(report)
 }
 
 /**
@@ -624,7 +1493,8 @@ fn __archive_update_many<F: Read + Write + Seek>(
     header: &WhisperMetadata,
     archive_index: usize,
     points: &[Point],
-) -> Result<(), io::Error> {
+    report: &mut UpdateReport,
+) -> Result<(), Error> {
     let archive = &header.archives[archive_index];
 
     let aligned_points: Vec<Point> = points
@@ -634,6 +1504,10 @@ fn __archive_update_many<F: Read + Write + Seek>(
 
     let chunks: Vec<Vec<Point>> = pack_points(&aligned_points, archive.seconds_per_point);
 
+    let written: usize = chunks.iter().map(Vec::len).sum();
+    report.written[archive_index] += written;
+    report.duplicates += aligned_points.len() - written;
+
     // Read base point and determine where our writes will start
     let base = archive.read_base(fh)?;
 
@@ -650,29 +1524,55 @@ fn __archive_update_many<F: Read + Write + Seek>(
     }
 
     // Now we propagate the updates to lower-precision archives
-    for pair in header.archives[archive_index..].windows(2) {
+    for (i, pair) in header.archives[archive_index..].windows(2).enumerate() {
         let higher = &pair[0];
         let lower = &pair[1];
 
-        let unique_lower_intervals: HashSet<u32> = aligned_points
+        let mut lower_intervals: Vec<u32> = aligned_points
             .iter()
             .map(|p| p.align(lower.seconds_per_point).interval)
             .collect();
-        let mut propagate_further = false;
-        for interval in unique_lower_intervals {
-            if __propagate(fh, header, interval, higher, lower)? {
-                propagate_further = true;
-            }
-        }
+        lower_intervals.dedup();
 
-        if !propagate_further {
+        if !__propagate_many(fh, header, &lower_intervals, higher, lower)? {
             break;
         }
+        report.propagated.push(archive_index + i + 1);
     }
 
     Ok(())
 }
 
+/// What `WhisperFile::update` and `WhisperFile::update_many` did with the points.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UpdateReport {
+    /// Points written to every archive, by archive index.
+    pub written: Vec<usize>,
+    /// Points older than the retention of every archive, which were dropped.
+    pub too_old: usize,
+    /// Points newer than `now`, which `update_many` writes to the
+    /// highest-precision archive like Graphite does. They are counted neither
+    /// in `written` nor in `duplicates`, `update` rejects them instead.
+    pub future_written: usize,
+    /// Points replaced by a later point for the same interval of their archive.
+    pub duplicates: usize,
+    /// Indexes of the lower-precision archives values were propagated to.
+    pub propagated: Vec<usize>,
+}
+
+impl UpdateReport {
+    fn new(archives: usize) -> Self {
+        Self {
+            written: vec![0; archives],
+            ..Self::default()
+        }
+    }
+
+    pub fn written_total(&self) -> usize {
+        self.written.iter().sum()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct ArchiveData {
     #[serde(rename = "start")]
@@ -736,6 +1636,26 @@ fn archive_fetch_interval<R: Read + Seek>(
     }
 }
 
+fn mapped_archive_fetch_interval(
+    data: &[u8],
+    archive: &ArchiveInfo,
+    interval: Interval,
+) -> Result<Option<Vec<Point>>, io::Error> {
+    let mut base = Vec::with_capacity(1);
+    decode_points(
+        mapped_slice(data, archive.offset as usize, POINT_SIZE)?,
+        &mut base,
+    );
+    if base[0].interval == 0 {
+        Ok(None)
+    } else {
+        let from_index = instant_offset(archive, base[0].interval, interval.from());
+        let until_index = instant_offset(archive, base[0].interval, interval.until());
+        let points = read_mapped_archive(data, archive, from_index, until_index)?;
+        Ok(Some(points))
+    }
+}
+
 fn points_to_data(
     points: &Option<Vec<Point>>,
     interval: Interval,
@@ -767,6 +1687,8 @@ mod tests {
             offset: 100_000,
             seconds_per_point: 1,
             points: 60,
+            aggregation_method: None,
+            x_files_factor: None,
         };
 
         assert_eq!(instant_offset(&archive, 0, 0), 0);
@@ -787,6 +1709,448 @@ mod tests {
         assert_eq!(instant_offset(&archive, 10, 120 + 70), 0);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_sparse() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let from = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir.path().join("from"))?;
+        (&from).write_all(b"header")?;
+        from.set_len(1 << 20)?;
+        let to = fs::File::create(dir.path().join("to"))?;
+
+        copy_sparse(&from, &to, 1 << 20)?;
+        to.sync_all()?;
+        assert_eq!(
+            fs::read(dir.path().join("to"))?,
+            fs::read(dir.path().join("from"))?
+        );
+        assert!(fallocate::allocated_size(&to)? < 1 << 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_mapped_archive() -> Result<(), io::Error> {
+        let archive = ArchiveInfo {
+            offset: 4,
+            seconds_per_point: 1,
+            points: 5,
+            aggregation_method: None,
+            x_files_factor: None,
+        };
+
+        let mut data = vec![0u8; 4];
+        for i in 0..archive.points {
+            Point {
+                interval: 100 + i,
+                value: f64::from(i),
+            }
+            .write(&mut data)?;
+        }
+        let mut cursor = io::Cursor::new(data.clone());
+
+        for &(from, until) in &[(0, 5), (1, 3), (3, 1), (4, 4), (2, 0)] {
+            assert_eq!(
+                read_mapped_archive(&data, &archive, from, until)?,
+                read_archive(&mut cursor, &archive, from, until)?
+            );
+        }
+
+        assert!(read_mapped_archive(&data[..30], &archive, 0, 4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_backend() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let now = 1528240800;
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .aggregation_method(AggregationMethod::Sum)
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let points: Vec<Point> = (1..=5)
+            .map(|i| Point {
+                interval: now - i * 60,
+                value: f64::from(i),
+            })
+            .collect();
+        file.update_many(&points, now)?;
+
+        let bytes = file.into_inner().into_inner();
+        assert_eq!(
+            bytes.len(),
+            METADATA_SIZE + 2 * ARCHIVE_INFO_SIZE + 20 * POINT_SIZE
+        );
+
+        let mut file = WhisperFile::from_backend(io::Cursor::new(bytes))?;
+        assert_eq!(file.info().aggregation_method, AggregationMethod::Sum);
+
+        let data = file.fetch(60, Interval::new(now - 300, now)?, now)?;
+        assert_eq!(
+            data.values,
+            vec![Some(5.0), Some(4.0), Some(3.0), Some(2.0), Some(1.0)]
+        );
+
+        let data = file.fetch(300, Interval::new(now - 600, now)?, now)?;
+        assert_eq!(data.values, vec![None, Some(15.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_errors() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let now = 1528240800;
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let point = Point {
+            interval: now - 3600,
+            value: 1.0,
+        };
+        match file.update(&point, now) {
+            Err(Error::TimestampNotCovered(interval)) => assert_eq!(interval, now - 3600),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        let interval = Interval::new(now - 600, now)?;
+        match file.fetch(7, interval, now) {
+            Err(Error::ArchiveNotFound(7)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        match file.set_x_files_factor(2.0) {
+            Err(Error::BadXFilesFactor(x_files_factor)) => assert_eq!(x_files_factor, 2.0),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        match file.update_metadata(|metadata| metadata.archives[0].points = 20) {
+            Err(Error::ArchivesChanged) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        let mut bytes = file.into_inner().into_inner();
+        bytes[..4].copy_from_slice(&99u32.to_be_bytes());
+        match WhisperFile::from_backend(io::Cursor::new(bytes)) {
+            Err(Error::BadAggregationMethod(99)) => {}
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+
+        let error: io::Error = Error::NoData.into();
+        assert_eq!(error.to_string(), "No data in selected timerange");
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_report() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let now = 1528240800;
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .x_files_factor(0.0)
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let points: Vec<Point> = [
+            now + 60,
+            now - 60,
+            now - 60,
+            now - 120,
+            now - 1200,
+            now - 10000,
+        ]
+        .iter()
+        .map(|&interval| Point {
+            interval,
+            value: 1.0,
+        })
+        .collect();
+        let report = file.update_many(&points, now)?;
+        assert_eq!(
+            report,
+            UpdateReport {
+                written: vec![2, 1],
+                too_old: 1,
+                future_written: 1,
+                duplicates: 1,
+                propagated: vec![1],
+            }
+        );
+        assert_eq!(report.written_total(), 3);
+
+        // Future points are written, but not counted twice
+        let future: Vec<Point> = [now + 60, now + 60, now + 120, now - 60]
+            .iter()
+            .map(|&interval| Point {
+                interval,
+                value: 2.0,
+            })
+            .collect();
+        let report = file.update_many(&future, now)?;
+        assert_eq!(report.written, vec![1, 0]);
+        assert_eq!((report.future_written, report.duplicates), (3, 0));
+        let data = file.fetch(60, Interval::new(now, now + 180)?, now + 180)?;
+        assert_eq!(data.values, vec![None, Some(2.0), Some(2.0)]);
+
+        let report = file.update(&points[1], now)?;
+        assert_eq!(report.written, vec![1, 0]);
+        assert_eq!(report.propagated, vec![1]);
+
+        assert!(file.update(&points[0], now).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_header() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let now = 1528240800;
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 600,
+                points: 10,
+            })
+            .archive_aggregation_method(300, AggregationMethod::Max)
+            .archive_x_files_factor(600, 1.0)
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let points: Vec<Point> = (1..=5)
+            .map(|i| Point {
+                interval: now - i * 60,
+                value: f64::from(i),
+            })
+            .collect();
+        file.update_many(&points, now)?;
+
+        let bytes = file.into_inner().into_inner();
+        assert_eq!(
+            bytes.len(),
+            METADATA_SIZE + 3 * EXTENDED_ARCHIVE_INFO_SIZE + 30 * POINT_SIZE
+        );
+        assert_ne!(BigEndian::read_u32(&bytes) & EXTENDED_HEADER_FLAG, 0);
+
+        let mut file = WhisperFile::from_backend(io::Cursor::new(bytes))?;
+        let info = file.info().clone();
+        assert!(info.is_extended());
+        assert_eq!(info.aggregation_method, AggregationMethod::Average);
+        assert_eq!(
+            info.archive_aggregation_method(&info.archives[1]),
+            AggregationMethod::Max
+        );
+        assert_eq!(info.archive_x_files_factor(&info.archives[1]), 0.5);
+        assert_eq!(info.archive_x_files_factor(&info.archives[2]), 1.0);
+
+        let data = file.fetch(300, Interval::new(now - 600, now)?, now)?;
+        assert_eq!(data.values, vec![None, Some(5.0)]);
+
+        // Only one of the two 5 minute points is known, below the archive xFilesFactor
+        let data = file.fetch(600, Interval::new(now - 1200, now)?, now)?;
+        assert_eq!(data.values, vec![None, None]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_from_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .aggregation_method(AggregationMethod::Sum)
+            .archive_x_files_factor(300, 0.0)
+            .build_in(io::Cursor::new(Vec::new()))?;
+        let bytes = file.get_ref().get_ref().clone();
+
+        let copy = WhisperBuilder::from(file.info()).build_in(io::Cursor::new(Vec::new()))?;
+        assert_eq!(copy.into_inner().into_inner(), bytes);
+
+        let json = serde_json::to_value(file.info())?;
+        assert_eq!(json["aggregation_method"], "sum");
+        assert_eq!(json["archives"][1]["x_files_factor"], 0.0);
+
+        // Offsets and max retention are computed from the archives
+        let schema: WhisperMetadata = serde_json::from_str(
+            r#"{
+                "aggregation_method": "sum",
+                "x_files_factor": 0.5,
+                "archives": [
+                    {"seconds_per_point": 60, "points": 10, "aggregation_method": "sum", "x_files_factor": 0.5},
+                    {"seconds_per_point": 300, "points": 10, "aggregation_method": "sum", "x_files_factor": 0.0}
+                ]
+            }"#,
+        )?;
+        let copy = WhisperBuilder::from(&schema).build_in(io::Cursor::new(Vec::new()))?;
+        assert_eq!(copy.into_inner().into_inner(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_compat() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let dir = tempfile::tempdir()?;
+        let retention = Retention {
+            seconds_per_point: 60,
+            points: 10,
+        };
+
+        let path = dir.path().join("p95.wsp");
+        WhisperBuilder::default()
+            .add_retention(retention)
+            .aggregation_method(AggregationMethod::Percentile(95))
+            .build(&path)?;
+
+        let file = OpenOptions::new().open(&path)?;
+        assert_eq!(
+            file.info().aggregation_method,
+            AggregationMethod::Percentile(95)
+        );
+
+        let error = OpenOptions::new()
+            .strict_compat(true)
+            .open(&path)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            Error::AggregationNotCompatible(AggregationMethod::Percentile(95))
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Aggregation method p95 is not supported by Graphite whisper"
+        );
+
+        let path = dir.path().join("average.wsp");
+        WhisperBuilder::default()
+            .add_retention(retention)
+            .build(&path)?;
+        OpenOptions::new().strict_compat(true).open(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_header_is_not_extended() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .build_in(io::Cursor::new(Vec::new()))?;
+
+        let bytes = file.into_inner().into_inner();
+        assert_eq!(BigEndian::read_u32(&bytes), 1);
+
+        let file = WhisperFile::from_backend(io::Cursor::new(bytes))?;
+        assert!(!file.info().is_extended());
+        assert_eq!(file.info().archives[0].aggregation_method, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagate_many() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::retention::Retention;
+
+        let methods = [
+            AggregationMethod::Average,
+            AggregationMethod::Sum,
+            AggregationMethod::Last,
+            AggregationMethod::Min,
+        ];
+
+        for &method in &methods {
+            for &lower_points in &[7, 20] {
+                let file = WhisperBuilder::default()
+                    .add_retention(Retention {
+                        seconds_per_point: 1,
+                        points: 30,
+                    })
+                    .add_retention(Retention {
+                        seconds_per_point: 5,
+                        points: lower_points,
+                    })
+                    .aggregation_method(method)
+                    .x_files_factor(0.5)
+                    .build_in(io::Cursor::new(Vec::new()))?;
+                let header = file.info().clone();
+                let higher = &header.archives[0];
+                let lower = &header.archives[1];
+
+                // Wrap around the higher archive and leave some gaps
+                let t0 = 1_528_240_800 + 3;
+                let points: Vec<Point> = (0..40)
+                    .filter(|i| i % 4 != 1 && i % 7 != 2)
+                    .map(|i| Point {
+                        interval: t0 + i,
+                        value: f64::from(i) * 0.3 - 2.0,
+                    })
+                    .collect();
+                let mut fh = file.into_inner();
+                let (head, tail) = points.split_at(points.len() / 2);
+                write_archive(&mut fh, higher, head, t0)?;
+                write_archive(&mut fh, higher, tail, t0)?;
+
+                for range in &[0..9, 5..8] {
+                    let intervals: Vec<u32> = range.clone().map(|i| t0 - 3 + i * 5).collect();
+
+                    let mut actual = fh.clone();
+                    let mut expected = fh.clone();
+                    for &interval in &intervals {
+                        __propagate(&mut expected, &header, interval, higher, lower)?;
+                    }
+
+                    assert!(__propagate_many(
+                        &mut actual,
+                        &header,
+                        &intervals,
+                        higher,
+                        lower
+                    )?);
+                    assert_eq!(actual.into_inner(), expected.into_inner());
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_adjust_interval() {
         assert_eq!(
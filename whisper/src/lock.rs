@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io;
+
+/**
+ * Advisory lock on a whisper file, released on drop.
+ *
+ * Locks are taken with `flock(2)`, so they cooperate with other processes
+ * (including the Python implementation) that lock the same files.
+ * On platforms without `flock` locking is a no-op.
+ */
+pub struct FileLock {
+    #[cfg(unix)]
+    fd: std::os::unix::io::RawFd,
+}
+
+#[cfg(unix)]
+impl FileLock {
+    fn acquire(file: &File, operation: libc::c_int) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let fd = file.as_raw_fd();
+        loop {
+            if unsafe { libc::flock(fd, operation) } == 0 {
+                return Ok(Self { fd });
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    pub fn exclusive(file: &File) -> io::Result<Self> {
+        Self::acquire(file, libc::LOCK_EX)
+    }
+
+    pub fn shared(file: &File) -> io::Result<Self> {
+        Self::acquire(file, libc::LOCK_SH)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.fd, libc::LOCK_UN);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl FileLock {
+    pub fn exclusive(_file: &File) -> io::Result<Self> {
+        Ok(Self {})
+    }
+
+    pub fn shared(_file: &File) -> io::Result<Self> {
+        Ok(Self {})
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    fn try_lock(file: &File, operation: libc::c_int) -> bool {
+        let locked = unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0;
+        if locked {
+            unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        }
+        locked
+    }
+
+    #[test]
+    fn test_exclusive_lock() -> io::Result<()> {
+        let path = tempfile::NamedTempFile::new()?.into_temp_path();
+        let file1 = File::open(&path)?;
+        let file2 = File::open(&path)?;
+
+        let guard = FileLock::exclusive(&file1)?;
+        assert!(!try_lock(&file2, libc::LOCK_SH));
+        drop(guard);
+        assert!(try_lock(&file2, libc::LOCK_EX));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_lock() -> io::Result<()> {
+        let path = tempfile::NamedTempFile::new()?.into_temp_path();
+        let file1 = File::open(&path)?;
+        let file2 = File::open(&path)?;
+
+        let guard = FileLock::shared(&file1)?;
+        assert!(try_lock(&file2, libc::LOCK_SH));
+        assert!(!try_lock(&file2, libc::LOCK_EX));
+        drop(guard);
+        assert!(try_lock(&file2, libc::LOCK_EX));
+        Ok(())
+    }
+}
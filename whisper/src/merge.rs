@@ -1,12 +1,15 @@
 use super::*;
 use crate::interval::Interval;
-use std::io;
 use std::path::Path;
 
 /**
- * Merges the data from one whisper file into another. Each file must have
- * the same archive configuration. time_from and time_to can optionally be
- * specified for the merge.
+ * Merges the data from one whisper file into another. time_from and time_to
+ * can optionally be specified for the merge. Both files are locked while they
+ * are accessed.
+ *
+ * When the archive configurations differ, the source is resampled to the
+ * resolution of each destination archive with its aggregation method and
+ * xFilesFactor. Coarser source values fill every finer slot they cover.
  */
 pub fn merge(
     path_src: &Path,
@@ -14,7 +17,7 @@ pub fn merge(
     time_from: u32,
     time_to: u32,
     now: u32,
-) -> Result<(), io::Error> {
+) -> Result<(), Error> {
     // if now is None:
     //     now = int(time.time())
 
@@ -28,24 +31,21 @@ pub fn merge(
     // else:
     //     fromTime = 0
 
-    let mut file_src = WhisperFile::open(path_src)?;
-    let mut file_dst = WhisperFile::open(path_dst)?;
-
-    if file_src.info().archives != file_dst.info().archives {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Archive configurations are unalike. Resize the input before merging",
-        ));
-    }
+    let options = OpenOptions::new().lock(true);
+    let mut file_src = options.open(path_src)?;
+    let mut file_dst = options.open(path_dst)?;
 
     // Sanity check: do not mix the from/to values.
     if time_to < time_from {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "time_to must be >= time_from",
+        return Err(Error::InvalidInterval(
+            "time_to must be >= time_from".to_owned(),
         ));
     }
 
+    if file_src.info().archives != file_dst.info().archives {
+        return merge_resampled(&mut file_src, &mut file_dst, time_from, time_to, now);
+    }
+
     let mut archives = file_src.info().archives.clone();
     archives.sort_by_key(|archive| archive.retention());
 
@@ -56,13 +56,57 @@ pub fn merge(
         }
 
         let from = u32::max(time_from, now - archive.retention());
-        let interval = Interval::new(from, time_to).unwrap();
+        let until = u32::min(time_to, now);
+        if from > until {
+            // Range is in the future
+            continue;
+        }
+        let interval = Interval::new(from, until).unwrap();
+
+        let mut chunk = Vec::with_capacity(iter::CHUNK_POINTS as usize);
+        for point in file_src.iter_archive(archive.seconds_per_point, interval)? {
+            chunk.push(point?);
+            if chunk.len() == chunk.capacity() {
+                file_dst.update_many(&chunk, now)?;
+                chunk.clear();
+            }
+        }
+        file_dst.update_many(&chunk, now)?;
+    }
+    Ok(())
+}
+
+fn merge_resampled(
+    file_src: &mut WhisperFile,
+    file_dst: &mut WhisperFile,
+    time_from: u32,
+    time_to: u32,
+    now: u32,
+) -> Result<(), Error> {
+    let metadata = file_dst.info().clone();
+    for (index, archive) in metadata.archives.iter().enumerate() {
+        let step = archive.seconds_per_point;
+        let from = adjust_instant_up(u32::max(time_from, now - archive.retention()), step);
+        let until = adjust_instant_up(u32::min(time_to, now), step);
+        if from >= until {
+            continue;
+        }
+        let interval = Interval::new(from, until).unwrap();
 
-        let (_adjusted_interval, points) =
-            file_src.fetch_points(archive.seconds_per_point, interval, now)?;
+        let points: Vec<Point> = resample::resample(
+            file_src,
+            interval,
+            step,
+            metadata.archive_aggregation_method(archive),
+            metadata.archive_x_files_factor(archive),
+            now,
+        )?
+        .into_iter()
+        .filter_map(|(interval, value)| value.map(|value| Point { interval, value }))
+        .collect();
 
-        if let Some(ref points) = points {
-            file_dst.update_many(points, now)?;
+        for chunk in points.chunks(iter::CHUNK_POINTS as usize) {
+            file_dst.update_archive(index, chunk)?;
         }
     }
     Ok(())
@@ -0,0 +1,60 @@
+use crate::error::Error;
+use crate::WhisperFile;
+use std::path::Path;
+
+/// How points are read from archives.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ReadMode {
+    /// Seek and read every point from the file.
+    #[default]
+    Buffered,
+    /// Decode points from a memory mapping of the whole file.
+    Mmap,
+}
+
+/**
+ * Options which can be used to configure how a whisper file is opened.
+ *
+ * `WhisperFile::open` is a shortcut for `OpenOptions::new().open(path)`.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    pub(crate) lock: bool,
+    pub(crate) cache_headers: bool,
+    pub(crate) read_mode: ReadMode,
+    pub(crate) strict_compat: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take an exclusive lock for updates and a shared lock for reads.
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Read headers through the process-wide `HeaderCache`.
+    pub fn cache_headers(mut self, cache_headers: bool) -> Self {
+        self.cache_headers = cache_headers;
+        self
+    }
+
+    /// Choose how `fetch` and `dump` read archives. Updates always go through the file.
+    pub fn read_mode(mut self, read_mode: ReadMode) -> Self {
+        self.read_mode = read_mode;
+        self
+    }
+
+    /// Refuse files which Graphite whisper can't read, like ones with extension aggregation methods.
+    pub fn strict_compat(mut self, strict_compat: bool) -> Self {
+        self.strict_compat = strict_compat;
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<WhisperFile, Error> {
+        WhisperFile::open_with(path.as_ref(), self)
+    }
+}
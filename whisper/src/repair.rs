@@ -0,0 +1,470 @@
+use super::*;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Truncated files are extended to at most this many times their size, larger headers are corrupted.
+const MAX_EXTEND_RATIO: u64 = 4;
+
+/// A single change made (or planned, on a dry run) by `repair`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    /// Truncated file is extended with empty points.
+    Extend { from: u64, to: u64 },
+    /// `max_retention` is rebuilt from the archive list.
+    MaxRetention { from: u32, to: u32 },
+    /// Point is misaligned or outside of the archive retention and is zeroed.
+    ClearPoint {
+        archive: usize,
+        index: u32,
+        interval: u32,
+        value: f64,
+    },
+    /**
+     * Points are rotated so the one at `index` becomes the base of the
+     * archive, after its base point was zeroed. Indexes of the other changes
+     * are the ones before the rotation.
+     */
+    Rebase {
+        archive: usize,
+        index: u32,
+        from: u32,
+        to: u32,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Extend { from, to } => write!(f, "size: {} -> {} bytes", from, to),
+            Change::MaxRetention { from, to } => write!(f, "maxRetention: {} -> {}", from, to),
+            Change::ClearPoint {
+                archive,
+                index,
+                interval,
+                value,
+            } => write!(
+                f,
+                "archive {} point {}: {} {} -> 0 0",
+                archive, index, interval, value
+            ),
+            Change::Rebase {
+                archive,
+                index,
+                from,
+                to,
+            } => write!(
+                f,
+                "archive {} base: {} -> {} (point {})",
+                archive, from, to, index
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub path: String,
+    pub changes: Vec<Change>,
+    /// Whether the changes were written to the file.
+    pub applied: bool,
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}: {}", self.path, change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail unless the archives follow the header one after another in a file of plausible size.
+fn validate(header: &WhisperMetadata, actual: u64) -> Result<(), Error> {
+    let expected = header.file_size() as u64;
+    if expected > actual.saturating_mul(MAX_EXTEND_RATIO) {
+        return Err(Error::Corrupted(format!(
+            "Header describes {} bytes, the file has {} bytes",
+            expected, actual
+        )));
+    }
+
+    let mut data_start = header.header_size() as u64;
+    for (index, archive) in header.archives.iter().enumerate() {
+        if u64::from(archive.offset) < data_start {
+            return Err(Error::Corrupted(format!(
+                "Archive {} at offset {} overlaps the header or the previous archive",
+                index, archive.offset
+            )));
+        }
+        data_start = u64::from(archive.offset) + archive.size() as u64;
+    }
+    if data_start > expected {
+        return Err(Error::Corrupted(format!(
+            "Archives end at {} bytes, after the {} bytes described by the header",
+            data_start, expected
+        )));
+    }
+    Ok(())
+}
+
+/// Bytes of the archive, the part missing from a truncated file reads as zeroes.
+fn read_archive_bytes<F: Read + Seek>(
+    file: &mut F,
+    archive: &ArchiveInfo,
+    actual: u64,
+) -> Result<Vec<u8>, io::Error> {
+    let mut bytes = vec![0; archive.size()];
+    let offset = u64::from(archive.offset);
+    let present = u64::min(actual.saturating_sub(offset), bytes.len() as u64) as usize;
+    file.seek(io::SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes[..present])?;
+    Ok(bytes)
+}
+
+/// Changes needed to repair the file of `actual` bytes, read one archive at a time.
+fn plan<F: Read + Seek>(
+    header: &WhisperMetadata,
+    file: &mut F,
+    actual: u64,
+    now: u32,
+) -> Result<Vec<Change>, Error> {
+    let mut changes = Vec::new();
+
+    let expected = header.file_size() as u64;
+    if actual < expected {
+        changes.push(Change::Extend {
+            from: actual,
+            to: expected,
+        });
+    }
+
+    let max_retention = header
+        .archives
+        .iter()
+        .map(|archive| archive.retention())
+        .max()
+        .unwrap_or(0);
+    if header.max_retention != max_retention {
+        changes.push(Change::MaxRetention {
+            from: header.max_retention,
+            to: max_retention,
+        });
+    }
+
+    for (index, archive) in header.archives.iter().enumerate() {
+        if archive.points == 0 || archive.seconds_per_point == 0 {
+            continue;
+        }
+
+        let bytes = read_archive_bytes(file, archive, actual)?;
+        let mut points = Vec::with_capacity(archive.points as usize);
+        decode_points(&bytes, &mut points);
+
+        let is_valid = |point: Point| {
+            let misaligned =
+                adjust_instant(point.interval, archive.seconds_per_point) != point.interval;
+            let expired =
+                u64::from(point.interval) + u64::from(archive.retention()) <= u64::from(now);
+            let future = point.interval > now;
+            !(misaligned || expired || future)
+        };
+
+        for (point_index, point) in points.iter().enumerate() {
+            if *point != Point::default() && !is_valid(*point) {
+                changes.push(Change::ClearPoint {
+                    archive: index,
+                    index: point_index as u32,
+                    interval: point.interval,
+                    value: point.value,
+                });
+            }
+        }
+
+        // Offsets of all points are computed from the base point, which can only be
+        // zeroed once another valid point in its expected slot has taken its place.
+        let base = points[0];
+        if base != Point::default() && !is_valid(base) {
+            let rebase = points
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(point_index, point)| {
+                    **point != Point::default()
+                        && is_valid(**point)
+                        && instant_offset(archive, base.interval, point.interval)
+                            == *point_index as u32
+                });
+            if let Some((point_index, point)) = rebase {
+                changes.push(Change::Rebase {
+                    archive: index,
+                    index: point_index as u32,
+                    from: base.interval,
+                    to: point.interval,
+                });
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/**
+ * Repair recoverable corruption of a whisper file: extend a truncated file
+ * to the size in its header, rebuild `max_retention` and zero points which are
+ * misaligned or outside of the archive retention. An invalid base point of an
+ * archive is replaced by a valid point of the archive, rotating its points.
+ *
+ * With `dry_run` the file is only read. Files with an unreadable header, with
+ * overlapping archives or with a size in the header more than `MAX_EXTEND_RATIO`
+ * times their own can't be repaired. The file is locked while it is repaired.
+ */
+pub fn repair(path: &Path, now: u32, dry_run: bool) -> Result<RepairReport, Error> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(!dry_run)
+        .open(path)?;
+    let _guard = if dry_run {
+        FileLock::shared(&file)?
+    } else {
+        FileLock::exclusive(&file)?
+    };
+
+    let header = WhisperMetadata::read(&mut file)?;
+    let actual = file.metadata()?.len();
+    validate(&header, actual)?;
+
+    let changes = plan(&header, &mut file, actual, now)?;
+
+    if !dry_run && !changes.is_empty() {
+        let mut empty = Vec::with_capacity(POINT_SIZE);
+        Point::default().write(&mut empty)?;
+
+        for change in &changes {
+            match change {
+                Change::Extend { to, .. } => file.set_len(*to)?,
+                Change::MaxRetention { to, .. } => {
+                    file.seek(io::SeekFrom::Start(4))?;
+                    file.write_all(&to.to_be_bytes())?;
+                }
+                Change::ClearPoint { archive, index, .. } => {
+                    let archive = &header.archives[*archive];
+                    file.seek(io::SeekFrom::Start(
+                        u64::from(archive.offset) + u64::from(*index) * POINT_SIZE as u64,
+                    ))?;
+                    file.write_all(&empty)?;
+                }
+                Change::Rebase { archive, index, .. } => {
+                    let archive = &header.archives[*archive];
+                    let mut bytes = read_archive_bytes(&mut file, archive, u64::MAX)?;
+                    bytes.rotate_left(*index as usize * POINT_SIZE);
+                    file.seek(io::SeekFrom::Start(u64::from(archive.offset)))?;
+                    file.write_all(&bytes)?;
+                }
+            }
+        }
+
+        file.sync_all()?;
+        HeaderCache::global().invalidate(path);
+    }
+
+    Ok(RepairReport {
+        path: path.display().to_string(),
+        applied: !dry_run && !changes.is_empty(),
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::check;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    #[test]
+    fn test_repair() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("repair.wsp");
+
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .build(&path)?;
+        file.update_many(
+            &[
+                Point {
+                    interval: NOW - 60,
+                    value: 1.0,
+                },
+                Point {
+                    interval: NOW - 120,
+                    value: 2.0,
+                },
+            ],
+            NOW,
+        )?;
+        drop(file);
+
+        // Misaligned and expired points, a bad maxRetention and a truncated file
+        let mut raw = fs::OpenOptions::new().write(true).open(&path)?;
+        raw.seek(io::SeekFrom::Start(4))?;
+        raw.write_all(&1234u32.to_be_bytes())?;
+        raw.seek(io::SeekFrom::Start(40 + 5 * POINT_SIZE as u64))?;
+        Point {
+            interval: NOW - 59,
+            value: 3.0,
+        }
+        .write(&mut raw)?;
+        Point {
+            interval: NOW - 600,
+            value: 4.0,
+        }
+        .write(&mut raw)?;
+        raw.set_len(200)?;
+        drop(raw);
+
+        let report = repair(&path, NOW, true)?;
+        assert!(!report.applied);
+        assert_eq!(
+            report.changes,
+            vec![
+                Change::Extend { from: 200, to: 280 },
+                Change::MaxRetention {
+                    from: 1234,
+                    to: 3000
+                },
+                Change::ClearPoint {
+                    archive: 0,
+                    index: 5,
+                    interval: NOW - 59,
+                    value: 3.0
+                },
+                Change::ClearPoint {
+                    archive: 0,
+                    index: 6,
+                    interval: NOW - 600,
+                    value: 4.0
+                },
+            ]
+        );
+        assert_eq!(fs::metadata(&path)?.len(), 200);
+
+        let report = repair(&path, NOW, false)?;
+        assert!(report.applied);
+        assert_eq!(report.changes.len(), 4);
+        assert!(check(&path, NOW)?.is_ok());
+        assert!(repair(&path, NOW, false)?.changes.is_empty());
+
+        let data = WhisperFile::open(&path)?.fetch(60, Interval::new(NOW - 180, NOW)?, NOW)?;
+        assert_eq!(data.values, vec![None, Some(2.0), Some(1.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_implausible_header() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("implausible.wsp");
+
+        WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .build(&path)?;
+        let content = fs::read(&path)?;
+
+        // 120 MB of points in a file of 280 bytes
+        let mut raw = fs::OpenOptions::new().write(true).open(&path)?;
+        raw.seek(io::SeekFrom::Start(36))?;
+        raw.write_all(&10_000_000u32.to_be_bytes())?;
+        drop(raw);
+
+        for &dry_run in &[true, false] {
+            match repair(&path, NOW, dry_run) {
+                Err(Error::Corrupted(message)) => assert_eq!(
+                    message,
+                    "Header describes 120000160 bytes, the file has 280 bytes"
+                ),
+                result => panic!("{:?}", result),
+            }
+        }
+        assert_eq!(fs::metadata(&path)?.len(), 280);
+
+        // Second archive starting inside the first one
+        let mut bytes = content.clone();
+        bytes[28..32].copy_from_slice(&100u32.to_be_bytes());
+        fs::write(&path, &bytes)?;
+        assert!(matches!(repair(&path, NOW, true), Err(Error::Corrupted(_))));
+
+        // Retention overflowing 32 bits
+        let mut bytes = content;
+        bytes[20..28].copy_from_slice(&[0, 1, 0, 0, 0, 1, 0, 0]);
+        fs::write(&path, &bytes)?;
+        assert!(matches!(repair(&path, NOW, true), Err(Error::Corrupted(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_base_point() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("base.wsp");
+
+        WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .build(&path)?;
+
+        // Expired base point and live points in the slots computed from it
+        let mut raw = fs::OpenOptions::new().write(true).open(&path)?;
+        raw.seek(io::SeekFrom::Start(40))?;
+        for &(interval, value) in &[(NOW - 720, 1.0), (NOW - 60, 2.0), (NOW, 3.0)] {
+            Point { interval, value }.write(&mut raw)?;
+        }
+        drop(raw);
+
+        let report = repair(&path, NOW, false)?;
+        assert_eq!(
+            report.changes,
+            vec![
+                Change::ClearPoint {
+                    archive: 0,
+                    index: 0,
+                    interval: NOW - 720,
+                    value: 1.0
+                },
+                Change::Rebase {
+                    archive: 0,
+                    index: 1,
+                    from: NOW - 720,
+                    to: NOW - 60
+                },
+            ]
+        );
+        assert!(check(&path, NOW)?.is_ok());
+        assert!(repair(&path, NOW, false)?.changes.is_empty());
+
+        let data =
+            WhisperFile::open(&path)?.fetch(60, Interval::new(NOW - 180, NOW + 60)?, NOW + 60)?;
+        assert_eq!(data.values, vec![None, None, Some(2.0), Some(3.0)]);
+        Ok(())
+    }
+}
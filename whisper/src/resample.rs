@@ -0,0 +1,193 @@
+use super::*;
+
+/**
+ * Values of `file` for every `step` of `interval`, which must be aligned to `step`.
+ *
+ * Each bucket is aggregated with `aggregation_method` from the most precise
+ * archive covering it. Buckets with a smaller share of known values than
+ * `x_files_factor` are unknown. Values of archives coarser than `step` are
+ * repeated as is in every bucket their slot covers.
+ */
+pub(crate) fn resample<F: Read + Write + Seek>(
+    file: &mut WhisperFile<F>,
+    interval: Interval,
+    step: u32,
+    aggregation_method: AggregationMethod,
+    x_files_factor: f32,
+    now: u32,
+) -> Result<Vec<(u32, Option<f64>)>, Error> {
+    let count = (interval.until() - interval.from()) / step;
+    let mut values: Vec<(u32, Option<f64>)> = (0..count)
+        .map(|i| (interval.from() + i * step, None))
+        .collect();
+
+    let archives = file.info().archives.clone();
+    let mut until = interval.until();
+    for archive in &archives {
+        let archive_from = adjust_instant_up(now.saturating_sub(archive.retention()), step);
+        let from = u32::max(interval.from(), archive_from);
+        if from >= until {
+            continue;
+        }
+
+        let segment = Interval::new(from, until).map_err(Error::InvalidInterval)?;
+        if archive.seconds_per_point > step {
+            repeat_slots(file, archive, segment, step, &mut values)?;
+            until = from;
+            continue;
+        }
+
+        let mut bucket = Vec::new();
+        let mut bucket_interval = from;
+        for value in file
+            .iter_archive(archive.seconds_per_point, segment)?
+            .values()
+        {
+            let (interval, value) = value?;
+            if interval < from {
+                continue;
+            }
+            if interval >= until {
+                break;
+            }
+            let slot_bucket = adjust_instant(interval, step);
+            if slot_bucket != bucket_interval {
+                aggregate_bucket(
+                    &mut values,
+                    &bucket,
+                    bucket_interval,
+                    step,
+                    aggregation_method,
+                    x_files_factor,
+                )?;
+                bucket.clear();
+                bucket_interval = slot_bucket;
+            }
+            bucket.push(value);
+        }
+        aggregate_bucket(
+            &mut values,
+            &bucket,
+            bucket_interval,
+            step,
+            aggregation_method,
+            x_files_factor,
+        )?;
+
+        until = from;
+    }
+
+    Ok(values)
+}
+
+fn repeat_slots<F: Read + Write + Seek>(
+    file: &mut WhisperFile<F>,
+    archive: &ArchiveInfo,
+    segment: Interval,
+    step: u32,
+    values: &mut [(u32, Option<f64>)],
+) -> Result<(), Error> {
+    let first = values[0].0;
+    for value in file
+        .iter_archive(archive.seconds_per_point, segment)?
+        .values()
+    {
+        let (interval, value) = value?;
+        if interval >= segment.until() {
+            break;
+        }
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let start = adjust_instant_up(u32::max(interval, segment.from()), step);
+        let end = u32::min(
+            interval.saturating_add(archive.seconds_per_point),
+            segment.until(),
+        );
+        for bucket in (start..end).step_by(step as usize) {
+            values[((bucket - first) / step) as usize].1 = Some(value);
+        }
+    }
+    Ok(())
+}
+
+fn aggregate_bucket(
+    values: &mut [(u32, Option<f64>)],
+    bucket: &[Option<f64>],
+    bucket_interval: u32,
+    step: u32,
+    aggregation_method: AggregationMethod,
+    x_files_factor: f32,
+) -> Result<(), Error> {
+    let known = bucket.iter().filter(|value| value.is_some()).count();
+    if known == 0 || (known as f32 / bucket.len() as f32) < x_files_factor {
+        return Ok(());
+    }
+
+    let index = ((bucket_interval - values[0].0) / step) as usize;
+    values[index].1 = Some(
+        aggregation_method
+            .aggregate(bucket)
+            .map_err(Error::Aggregation)?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    #[test]
+    fn test_resample_coarse_to_fine() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 300,
+                points: 10,
+            })
+            .build_in(io::Cursor::new(Vec::new()))?;
+        file.update_many(
+            &[
+                Point {
+                    interval: NOW - 600,
+                    value: 1.0,
+                },
+                Point {
+                    interval: NOW - 300,
+                    value: 2.0,
+                },
+            ],
+            NOW,
+        )?;
+
+        let interval = Interval::new(NOW - 900, NOW)?;
+        let values = resample(&mut file, interval, 60, AggregationMethod::Sum, 0.5, NOW)?;
+        let expected: Vec<(u32, Option<f64>)> = (0..15)
+            .map(|i| {
+                (
+                    NOW - 900 + i * 60,
+                    [None, Some(1.0), Some(2.0)][i as usize / 5],
+                )
+            })
+            .collect();
+        assert_eq!(values, expected);
+
+        // A slot starting before the interval still fills the buckets it covers
+        let interval = Interval::new(NOW - 480, NOW - 240)?;
+        let values = resample(&mut file, interval, 60, AggregationMethod::Sum, 0.5, NOW)?;
+        assert_eq!(
+            values,
+            vec![
+                (NOW - 480, Some(1.0)),
+                (NOW - 420, Some(1.0)),
+                (NOW - 360, Some(1.0)),
+                (NOW - 300, Some(2.0)),
+            ]
+        );
+        Ok(())
+    }
+}
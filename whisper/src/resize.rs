@@ -5,23 +5,131 @@ use crate::interval::Interval;
 
 use crate::point::Point;
 use crate::retention::Retention;
-use crate::WhisperFile;
+use crate::OpenOptions;
 
 use std::fs::{remove_file, rename};
-use std::io;
 use std::path::{Path, PathBuf};
-use std::process::exit;
 
-fn migrate_aggregate(path_src: &Path, path_dst: &Path, now: u32) -> io::Result<()> {
-    let mut file_src = WhisperFile::open(path_src)?;
-    let mut file_dst = WhisperFile::open(path_dst)?;
+/// What to do with the original database once the resized one replaces it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BackupPolicy {
+    /// Keep the original database as `<path>.bak`.
+    #[default]
+    Keep,
+    /// Remove the backup after the new database is in place.
+    Remove,
+}
+
+/// Steps of `resize`, reported to the progress callback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    RemovedTemporary(PathBuf),
+    Created {
+        path: PathBuf,
+        size: u64,
+    },
+    Migrating {
+        aggregate: bool,
+    },
+    ArchiveMigrated {
+        seconds_per_point: u32,
+        interval: Interval,
+        points: usize,
+    },
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RestoredBackup(PathBuf),
+    RemovedBackup(PathBuf),
+    UpdatedMetadata(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResizeReport {
+    /// Resized database, the new file if one was requested.
+    pub path: PathBuf,
+    /// Original database kept after the resize.
+    pub backup: Option<PathBuf>,
+    /// Number of points written to the new database.
+    pub points: usize,
+    /// Retentions were unchanged and only the header was updated in place.
+    pub metadata_only: bool,
+}
+
+/**
+ * Options of `resize`.
+ *
+ * xFilesFactor and aggregation method default to the ones of the resized database.
+ * When set they replace per-archive settings of an extended header too, otherwise
+ * archives keeping their precision keep their settings.
+ */
+#[derive(Debug, Clone)]
+pub struct ResizeOptions {
+    retentions: Vec<Retention>,
+    x_files_factor: Option<f32>,
+    aggregation_method: Option<AggregationMethod>,
+    aggregate: bool,
+    backup: BackupPolicy,
+    new_file: Option<PathBuf>,
+}
+
+impl ResizeOptions {
+    pub fn new(retentions: &[Retention]) -> Self {
+        Self {
+            retentions: retentions.to_vec(),
+            x_files_factor: None,
+            aggregation_method: None,
+            aggregate: false,
+            backup: BackupPolicy::default(),
+            new_file: None,
+        }
+    }
+
+    pub fn x_files_factor(mut self, x_files_factor: f32) -> Self {
+        self.x_files_factor = Some(x_files_factor);
+        self
+    }
+
+    pub fn aggregation_method(mut self, aggregation_method: AggregationMethod) -> Self {
+        self.aggregation_method = Some(aggregation_method);
+        self
+    }
+
+    /// Aggregate values to fit the new archives better. Slower and uses more memory.
+    pub fn aggregate(mut self, aggregate: bool) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn backup(mut self, backup: BackupPolicy) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Create a new database instead of replacing the original one.
+    pub fn new_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.new_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+fn migrate_aggregate(
+    path_src: &Path,
+    path_dst: &Path,
+    now: u32,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<usize, Error> {
+    let options = OpenOptions::new().lock(true);
+    let mut file_src = options.open(path_src)?;
+    let mut file_dst = options.open(path_dst)?;
 
     let meta = file_src.info().clone();
     let mut until = now;
+    let mut migrated = 0;
 
     for archive in &meta.archives {
-        let interval =
-            Interval::new(0, until).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let interval = Interval::new(0, until).map_err(Error::InvalidInterval)?;
 
         let (adjusted_interval, data) =
             file_src.fetch_points(archive.seconds_per_point, interval, now)?;
@@ -35,41 +143,40 @@ fn migrate_aggregate(path_src: &Path, path_dst: &Path, now: u32) -> io::Result<(
 
             points_to_write.sort_by_key(|point| point.interval);
 
-            println!(
-                "({},{},{})",
-                adjusted_interval.from(),
-                adjusted_interval.until(),
-                archive.seconds_per_point
-            );
-
-            let values = points_to_write
-                .iter()
-                .map(|point| point.interval.to_string())
-                .collect::<Vec<String>>()
-                .join(" ");
-
-            println!("timepoints_to_update: {}", values);
-
             until = points_to_write.get(0).map(|x| x.interval).unwrap_or(now);
             file_dst.update_many(&points_to_write, now)?;
+
+            migrated += points_to_write.len();
+            progress(Progress::ArchiveMigrated {
+                seconds_per_point: archive.seconds_per_point,
+                interval: adjusted_interval,
+                points: points_to_write.len(),
+            });
         }
     }
 
-    Ok(())
+    Ok(migrated)
 }
 
-fn migrate_nonaggregate(path_src: &Path, path_dst: &Path, now: u32) -> io::Result<()> {
-    let mut file_src = WhisperFile::open(path_src)?;
-    let mut file_dst = WhisperFile::open(path_dst)?;
+fn migrate_nonaggregate(
+    path_src: &Path,
+    path_dst: &Path,
+    now: u32,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<usize, Error> {
+    let options = OpenOptions::new().lock(true);
+    let mut file_src = options.open(path_src)?;
+    let mut file_dst = options.open(path_dst)?;
 
-    let interval = Interval::new(0, now).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let interval = Interval::new(0, now).map_err(Error::InvalidInterval)?;
 
     let meta = file_src.info().clone();
     let mut archives = meta.archives;
     archives.sort_by_key(|archive| archive.retention());
+    let mut migrated = 0;
 
     for archive in &archives {
-        let (_adjusted_interval, data) =
+        let (adjusted_interval, data) =
             file_src.fetch_points(archive.seconds_per_point, interval, now)?;
 
         if let Some(ref data) = data {
@@ -81,95 +188,173 @@ fn migrate_nonaggregate(path_src: &Path, path_dst: &Path, now: u32) -> io::Resul
 
             points_to_write.sort_by_key(|point| point.interval);
             file_dst.update_many(&points_to_write, now)?;
+
+            migrated += points_to_write.len();
+            progress(Progress::ArchiveMigrated {
+                seconds_per_point: archive.seconds_per_point,
+                interval: adjusted_interval,
+                points: points_to_write.len(),
+            });
         }
     }
 
-    Ok(())
+    Ok(migrated)
+}
+
+fn same_archives(path_src: &Path, retentions: &[Retention]) -> Result<bool, Error> {
+    let file = OpenOptions::new().lock(true).open(path_src)?;
+    let archives = &file.info().archives;
+
+    let mut retentions = retentions.to_vec();
+    retentions.sort_by_key(|retention| retention.seconds_per_point);
+
+    Ok(retentions.len() == archives.len()
+        && retentions.iter().zip(archives).all(|(retention, archive)| {
+            retention.seconds_per_point == archive.seconds_per_point
+                && retention.points == archive.points
+        }))
 }
 
-fn migrate_points(
+/// Same as `resize_with_progress` without progress reporting.
+pub fn resize(path_src: &Path, options: &ResizeOptions, now: u32) -> Result<ResizeReport, Error> {
+    resize_with_progress(path_src, options, now, &mut |_| {})
+}
+
+/**
+ * Change the retentions, xFilesFactor or aggregation method of a database.
+ *
+ * Data is migrated to a new database which replaces the original one unless
+ * `ResizeOptions::new_file` is set. When only xFilesFactor or aggregation
+ * method change the header is updated in place with `WhisperFile::update_metadata`.
+ */
+pub fn resize_with_progress(
     path_src: &Path,
-    path_dst: &Path,
-    aggregate: bool,
+    options: &ResizeOptions,
     now: u32,
-) -> Result<(), Error> {
+    progress: &mut dyn FnMut(Progress),
+) -> Result<ResizeReport, Error> {
     if !path_src.is_file() {
-        return Err(Error::FileNotExist(path_src.to_owned()).into());
+        return Err(Error::FileNotExist(path_src.to_owned()));
     }
 
-    if aggregate {
-        println!("Migrating data with aggregation...");
-        migrate_aggregate(path_src, path_dst, now)?;
-    } else {
-        println!("Migrating data without aggregation...");
-        migrate_nonaggregate(path_src, path_dst, now)?;
+    let meta = OpenOptions::new().lock(true).open(path_src)?.info().clone();
+    let x_files_factor = options.x_files_factor.unwrap_or(meta.x_files_factor);
+    let aggregation_method = options
+        .aggregation_method
+        .unwrap_or(meta.aggregation_method);
+
+    if options.new_file.is_none() && same_archives(path_src, &options.retentions)? {
+        let mut file = OpenOptions::new().lock(true).open(path_src)?;
+        file.update_metadata(|metadata| {
+            metadata.x_files_factor = x_files_factor;
+            metadata.aggregation_method = aggregation_method;
+            for archive in &mut metadata.archives {
+                if options.x_files_factor.is_some() && archive.x_files_factor.is_some() {
+                    archive.x_files_factor = Some(x_files_factor);
+                }
+                if options.aggregation_method.is_some() && archive.aggregation_method.is_some() {
+                    archive.aggregation_method = Some(aggregation_method);
+                }
+            }
+        })?;
+        progress(Progress::UpdatedMetadata(path_src.to_path_buf()));
+        return Ok(ResizeReport {
+            path: path_src.to_path_buf(),
+            backup: None,
+            points: 0,
+            metadata_only: true,
+        });
     }
 
-    Ok(())
-}
-
-#[allow(clippy::too_many_arguments)]
-pub fn resize(
-    path_src: &Path,
-    path_new: Option<&Path>,
-    retentions: &[Retention],
-    x_files_factor: f32,
-    aggregation_method: AggregationMethod,
-    aggregate: bool,
-    nobackup: bool,
-    now: u32,
-) -> Result<(), Error> {
-    let path_dst = match path_new {
+    let path_dst = match options.new_file {
         None => {
             let tmpfile = PathBuf::from(format!("{}.tmp", path_src.display()));
             if tmpfile.is_file() {
-                println!(
-                    "Removing previous temporary database file: {}",
-                    tmpfile.display()
-                );
                 remove_file(&tmpfile)?;
+                progress(Progress::RemovedTemporary(tmpfile.clone()));
             }
             tmpfile
         }
-        Some(new) => new.to_path_buf(),
+        Some(ref new) => new.to_path_buf(),
     };
 
-    println!("Retrieving all data from the archives");
-    println!("Creating new whisper database: {}", path_dst.display());
-
-    WhisperBuilder::default()
-        .add_retentions(retentions)
+    let mut builder = WhisperBuilder::default()
+        .add_retentions(&options.retentions)
         .x_files_factor(x_files_factor)
         .aggregation_method(aggregation_method)
-        .build(&path_dst)?;
+        .lock(true);
+    for archive in &meta.archives {
+        let spp = archive.seconds_per_point;
+        if !options
+            .retentions
+            .iter()
+            .any(|retention| retention.seconds_per_point == spp)
+        {
+            continue;
+        }
+        if let (None, Some(x_files_factor)) = (options.x_files_factor, archive.x_files_factor) {
+            builder = builder.archive_x_files_factor(spp, x_files_factor);
+        }
+        if let (None, Some(method)) = (options.aggregation_method, archive.aggregation_method) {
+            builder = builder.archive_aggregation_method(spp, method);
+        }
+    }
+    builder.build(&path_dst)?;
 
     let size = path_dst.metadata()?.len();
-    println!("Created: {} ({} bytes)", path_dst.display(), size);
-
-    migrate_points(path_src, &path_dst, aggregate, now)?;
+    progress(Progress::Created {
+        path: path_dst.clone(),
+        size,
+    });
+
+    progress(Progress::Migrating {
+        aggregate: options.aggregate,
+    });
+    let points = if options.aggregate {
+        migrate_aggregate(path_src, &path_dst, now, progress)?
+    } else {
+        migrate_nonaggregate(path_src, &path_dst, now, progress)?
+    };
 
-    if path_new.is_some() {
-        return Ok(());
+    if options.new_file.is_some() {
+        return Ok(ResizeReport {
+            path: path_dst,
+            backup: None,
+            points,
+            metadata_only: false,
+        });
     }
 
-    let backup = format!("{}.bak", path_src.display());
-    println!("Renaming old database to: {}", backup);
+    let backup = PathBuf::from(format!("{}.bak", path_src.display()));
     rename(path_src, &backup)?;
-
-    println!("Renaming new database to: {}", path_src.display());
-    rename(&path_dst, path_src)
-        .map_err(|e| {
-            eprintln!("{}", e);
-            println!("Operation failed, restoring backup");
-            rename(&backup, path_src).unwrap();
-            exit(1);
-        })
-        .unwrap();
-
-    if nobackup {
-        println!("Unlinking backup: {}", &backup);
-        remove_file(backup)?;
+    progress(Progress::Renamed {
+        from: path_src.to_path_buf(),
+        to: backup.clone(),
+    });
+
+    if let Err(e) = rename(&path_dst, path_src) {
+        rename(&backup, path_src)?;
+        progress(Progress::RestoredBackup(backup));
+        return Err(e.into());
     }
+    progress(Progress::Renamed {
+        from: path_dst,
+        to: path_src.to_path_buf(),
+    });
+
+    let backup = match options.backup {
+        BackupPolicy::Keep => Some(backup),
+        BackupPolicy::Remove => {
+            remove_file(&backup)?;
+            progress(Progress::RemovedBackup(backup));
+            None
+        }
+    };
 
-    Ok(())
+    Ok(ResizeReport {
+        path: path_src.to_path_buf(),
+        backup,
+        points,
+        metadata_only: false,
+    })
 }
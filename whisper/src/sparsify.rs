@@ -0,0 +1,203 @@
+use super::*;
+use crate::lock::FileLock;
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::AddAssign;
+use walkdir::WalkDir;
+
+/// Number of blocks read at once while looking for zeroed ones.
+const SCAN_BLOCKS: u64 = 256;
+
+/// Totals of `sparsify`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SparsifyReport {
+    /// Number of files sparsified.
+    pub files: usize,
+    /// Number of holes punched.
+    pub holes: usize,
+    /// Bytes of zeroed blocks punched, including blocks that already were holes.
+    pub punched: u64,
+    /// Bytes of disk space freed.
+    pub reclaimed: u64,
+}
+
+impl AddAssign for SparsifyReport {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.holes += other.holes;
+        self.punched += other.punched;
+        self.reclaimed += other.reclaimed;
+    }
+}
+
+impl fmt::Display for SparsifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "files {}, holes {}, punched {} bytes, reclaimed {} bytes",
+            self.files, self.holes, self.punched, self.reclaimed
+        )
+    }
+}
+
+/**
+ * Deallocate the blocks of the archives that only hold zeroes, like the
+ * slots of points never written. They read back as zeroes, so the content
+ * of the file doesn't change.
+ *
+ * The file is locked while it is scanned, writers which don't lock files must
+ * be stopped. Only works on Linux file systems supporting `FALLOC_FL_PUNCH_HOLE`.
+ */
+pub fn sparsify(path: &Path) -> Result<SparsifyReport, Error> {
+    let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let _guard = FileLock::exclusive(&file)?;
+
+    let mut fh = &file;
+    let metadata = WhisperMetadata::read(&mut fh)?;
+    let block = fallocate::block_size(&file)?;
+    let allocated = fallocate::allocated_size(&file)?;
+
+    let data_end = u64::min(metadata.file_size() as u64, file.metadata()?.len());
+    let header_size = metadata.header_size() as u64;
+    // Blocks shared with the header are never punched
+    let mut offset = header_size.div_ceil(block) * block;
+
+    let mut report = SparsifyReport {
+        files: 1,
+        ..SparsifyReport::default()
+    };
+    let mut hole_start = None;
+    let mut buffer = Vec::new();
+    fh.seek(io::SeekFrom::Start(offset))?;
+    while offset + block <= data_end {
+        let blocks = u64::min((data_end - offset) / block, SCAN_BLOCKS);
+        buffer.resize((blocks * block) as usize, 0);
+        fh.read_exact(&mut buffer)?;
+
+        for chunk in buffer.chunks(block as usize) {
+            if chunk.iter().all(|&byte| byte == 0) {
+                hole_start.get_or_insert(offset);
+            } else if let Some(start) = hole_start.take() {
+                punch_hole(&file, start, offset, &mut report)?;
+            }
+            offset += block;
+        }
+    }
+    if let Some(start) = hole_start {
+        punch_hole(&file, start, offset, &mut report)?;
+    }
+
+    file.sync_all()?;
+    report.reclaimed = allocated.saturating_sub(fallocate::allocated_size(&file)?);
+    Ok(report)
+}
+
+fn punch_hole(
+    file: &fs::File,
+    from: u64,
+    until: u64,
+    report: &mut SparsifyReport,
+) -> Result<(), Error> {
+    fallocate::punch_hole(file, from, until - from)?;
+    report.holes += 1;
+    report.punched += until - from;
+    Ok(())
+}
+
+/// Sparsify every `.wsp` file under `dir`, files or directories that fail are passed to `on_error`.
+pub fn sparsify_tree<E: FnMut(&Path, Error)>(
+    dir: &Path,
+    mut on_error: E,
+) -> Result<SparsifyReport, Error> {
+    let mut report = SparsifyReport::default();
+    for entry in WalkDir::new(dir) {
+        match entry {
+            Ok(ref entry)
+                if entry.file_type().is_file()
+                    && entry.path().extension() == Some(OsStr::new("wsp")) =>
+            {
+                match sparsify(entry.path()) {
+                    Ok(file_report) => report += file_report,
+                    Err(e) => on_error(entry.path(), e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let path = e.path().unwrap_or(dir).to_path_buf();
+                on_error(&path, io::Error::from(e).into());
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::retention::Retention;
+
+    const NOW: u32 = 1_528_240_800;
+
+    #[test]
+    fn test_sparsify() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("metric.wsp");
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10_000,
+            })
+            .add_retention(Retention {
+                seconds_per_point: 3600,
+                points: 1000,
+            })
+            .build(&path)?;
+        file.update(
+            &Point {
+                interval: NOW - 60,
+                value: 1.0,
+            },
+            NOW,
+        )?;
+        drop(file);
+
+        let content = fs::read(&path)?;
+        let size = fs::metadata(&path)?.len();
+
+        let report = sparsify(&path)?;
+        assert_eq!(report.files, 1);
+        assert!(report.holes >= 1);
+        // Only the blocks of the header and both points stay allocated
+        assert!(report.punched >= size - 4 * 4096, "{:?}", report);
+        assert!(report.reclaimed > 0, "{:?}", report);
+
+        assert_eq!(fs::read(&path)?, content);
+        let data =
+            OpenOptions::new()
+                .open(&path)?
+                .fetch(60, Interval::new(NOW - 120, NOW)?, NOW)?;
+        assert_eq!(data.values, vec![None, Some(1.0)]);
+
+        // Nothing left to reclaim
+        let mut errors = Vec::new();
+        let report = sparsify_tree(dir.path(), |path, e| errors.push((path.to_path_buf(), e)))?;
+        assert!(errors.is_empty());
+        assert_eq!(report.files, 1);
+        assert_eq!(report.reclaimed, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparsify_tree_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("broken.wsp"), b"broken")?;
+        fs::write(dir.path().join("other.txt"), b"other")?;
+
+        let mut errors = Vec::new();
+        let report = sparsify_tree(dir.path(), |path, e| errors.push((path.to_path_buf(), e)))?;
+        assert_eq!(report, SparsifyReport::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, dir.path().join("broken.wsp"));
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
 use std::process::Command;
+use tempfile::Builder;
+use whisper::OpenOptions;
 
 const NAME: &str = "find-corrupt-whisper-files";
 
@@ -36,3 +40,82 @@ fn calling_with_invalid_path() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn calling_with_corrupt_files() -> Result<(), Box<dyn Error>> {
+    let dir = Builder::new().prefix("whisper").tempdir()?;
+
+    let good = dir.path().join("good.wsp");
+    fs::copy(PathBuf::new().join("data").join("info.wsp"), &good)?;
+
+    let truncated = dir.path().join("truncated.wsp");
+    fs::copy(&good, &truncated)?;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&truncated)?
+        .set_len(100)?;
+
+    // Fixable by whisper-repair, never deleted
+    let retention = dir.path().join("retention.wsp");
+    let mut bytes = fs::read(&good)?;
+    bytes[4..8].copy_from_slice(&1u32.to_be_bytes());
+    fs::write(&retention, bytes)?;
+
+    let overflow = dir.path().join("overflow.wsp");
+    let mut bytes = fs::read(&good)?;
+    bytes[20..24].copy_from_slice(&0x10000u32.to_be_bytes());
+    bytes[24..28].copy_from_slice(&0x10000u32.to_be_bytes());
+    fs::write(&overflow, bytes)?;
+
+    // Written by go-carbon
+    let compressed = dir.path().join("compressed.wsp");
+    let mut source = OpenOptions::new().open(&good)?;
+    whisper::compressed::compress(
+        &mut source,
+        whisper::compressed::DEFAULT_POINTS_PER_BLOCK,
+        &mut fs::File::create(&compressed)?,
+    )?;
+
+    Command::cargo_bin(NAME)?
+        .args([dir.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("good.wsp: ")
+                .and(predicate::str::contains("compressed.wsp: "))
+                .from_utf8(),
+        )
+        .stderr(
+            predicate::str::contains("truncated.wsp: File size is 100 bytes")
+                .and(predicate::str::contains(
+                    "overflow.wsp: Bad header: Retention of archive 0 overflows",
+                ))
+                .and(predicate::str::contains("Corrupt Whisper file: "))
+                .from_utf8(),
+        );
+
+    Command::cargo_bin(NAME)?
+        .args(["--json", dir.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(r#""problems":[]"#)
+                .and(predicate::str::contains(
+                    r#""problems":[{"kind":"size_mismatch","expected":"#,
+                ))
+                .from_utf8(),
+        )
+        .stderr("");
+
+    Command::cargo_bin(NAME)?
+        .args(["--delete-corrupt", dir.path().to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(good.exists());
+    assert!(retention.exists());
+    assert!(compressed.exists());
+    assert!(!truncated.exists());
+    assert!(!overflow.exists());
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::Builder;
+use whisper::interval::Interval;
+use whisper::point::Point;
+use whisper::retention::Retention;
+use whisper::WhisperBuilder;
+
+const NAME: &str = "whisper-aggregate";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_missing_source() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["total.wsp", "missing.wsp"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("does not exist").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_with_sources() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let destination = temp_dir.path().join("total.wsp");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let now = now - now % 60;
+
+    let mut sources = Vec::new();
+    for host in 1..=3 {
+        let path = temp_dir.path().join(format!("host{}.wsp", host));
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 60,
+            })
+            .build(&path)?;
+        file.update(
+            &Point {
+                interval: now - 60,
+                value: f64::from(host),
+            },
+            now,
+        )?;
+        sources.push(path);
+    }
+
+    Command::cargo_bin(NAME)?
+        .args(["--retention", "1m:1h"])
+        .arg(&destination)
+        .args(&sources)
+        .assert()
+        .success()
+        .stdout("created true, written 1, incomplete 0\n")
+        .stderr("");
+
+    let mut file = whisper::OpenOptions::new().open(&destination)?;
+    let data = file.fetch(60, Interval::new(now - 120, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(6.0)]);
+
+    Command::cargo_bin(NAME)?
+        .args(["--aggregationMethod", "average"])
+        .arg(&destination)
+        .args(&sources)
+        .assert()
+        .success()
+        .stdout("created false, written 1, incomplete 0\n");
+
+    let data = file.fetch(60, Interval::new(now - 120, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(2.0)]);
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::Builder;
+use whisper::WhisperFile;
+
+const NAME: &str = "whisper-convert";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_invalid_path() -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    let error_msg = "No such file or directory (os error 2)";
+    #[cfg(windows)]
+    let error_msg = "The system cannot find the file specified. (os error 2)";
+
+    Command::cargo_bin(NAME)?
+        .args(["--compressed", "invalid"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(error_msg).from_utf8());
+
+    Ok(())
+}
+
+#[test]
+fn calling_round_trip() -> Result<(), Box<dyn Error>> {
+    let dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = dir.path().join("convert.wsp");
+    let compressed = dir.path().join("convert_compressed.wsp");
+    fs::copy(PathBuf::new().join("data").join("dump.wsp"), &path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([
+            "--compressed",
+            path.to_str().unwrap(),
+            compressed.to_str().unwrap(),
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("to the compressed format").from_utf8())
+        .stderr("");
+    assert!(fs::read(&compressed)?.starts_with(b"whisper_compressed"));
+
+    Command::cargo_bin(NAME)?
+        .args(["--compressed", compressed.to_str().unwrap()])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("is already compressed").from_utf8());
+
+    Command::cargo_bin(NAME)?
+        .args(["--standard", compressed.to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr("");
+
+    let mut original = WhisperFile::open(&path)?;
+    let mut converted = WhisperFile::open(&compressed)?;
+    assert_eq!(converted.info().archives, original.info().archives);
+    for archive in original.info().archives.clone() {
+        let mut expected = original.dump(archive.seconds_per_point)?;
+        let mut actual = converted.dump(archive.seconds_per_point)?;
+        expected.retain(|point| point.interval != 0);
+        actual.retain(|point| point.interval != 0);
+        expected.sort_by_key(|point| point.interval);
+        actual.sort_by_key(|point| point.interval);
+        assert_eq!(actual, expected);
+    }
+
+    Ok(())
+}
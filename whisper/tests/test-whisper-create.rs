@@ -5,6 +5,8 @@ use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 use tempfile::Builder;
+use whisper::aggregation::AggregationMethod;
+use whisper::retention::Retention;
 
 const NAME: &str = "whisper-create";
 
@@ -133,6 +135,37 @@ fn calling_creating_multiple_retention() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn calling_creating_with_percentile() -> Result<(), Box<dyn Error>> {
+    let filename = "info.wsp";
+
+    let path = Builder::new()
+        .prefix("whisper")
+        .suffix(filename)
+        .tempdir()?
+        .path()
+        .to_path_buf();
+
+    Command::cargo_bin(NAME)?
+        .args([
+            path.to_str().unwrap(),
+            "--aggregationMethod",
+            "p95",
+            "60:1440",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created: ").from_utf8())
+        .stderr("");
+
+    let file = whisper::WhisperFile::open(&path)?;
+    assert_eq!(
+        file.info().aggregation_method,
+        whisper::aggregation::AggregationMethod::Percentile(95)
+    );
+    Ok(())
+}
+
 #[test]
 fn calling_creating_with_present_file() -> Result<(), Box<dyn Error>> {
     let filename = "info.wsp";
@@ -161,3 +194,133 @@ fn calling_creating_with_present_file() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn calling_creating_like_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("like.wsp");
+    let file_path = PathBuf::new().join("data").join("info.wsp");
+
+    Command::cargo_bin(NAME)?
+        .arg("--like")
+        .arg(&file_path)
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created: ").from_utf8())
+        .stderr("");
+
+    let like = whisper::WhisperFile::open(&file_path)?;
+    let file = whisper::WhisperFile::open(&path)?;
+    assert_eq!(
+        serde_json::to_value(file.info())?,
+        serde_json::to_value(like.info())?
+    );
+    Ok(())
+}
+
+#[test]
+fn calling_creating_with_schema() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("schema.wsp");
+    let schema = temp_dir.path().join("schema.toml");
+
+    fs::write(
+        &schema,
+        r#"
+aggregation_method = "max"
+x_files_factor = 0.0
+
+[[archives]]
+seconds_per_point = 60
+points = 60
+
+[[archives]]
+seconds_per_point = 3600
+points = 24
+aggregation_method = "sum"
+x_files_factor = 0.5
+"#,
+    )?;
+
+    Command::cargo_bin(NAME)?
+        .arg("--schema")
+        .arg(&schema)
+        .args(["--aggregationMethod", "min"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created: ").from_utf8())
+        .stderr("");
+
+    let file = whisper::WhisperFile::open(&path)?;
+    let info = file.info();
+    assert_eq!(info.retentions().len(), 2);
+    assert_eq!(info.max_retention, 86400);
+    assert_eq!(
+        info.archive_aggregation_method(&info.archives[0]),
+        whisper::aggregation::AggregationMethod::Min
+    );
+    // Also replaces the aggregation method of the archive which has its own
+    assert_eq!(
+        info.archive_aggregation_method(&info.archives[1]),
+        whisper::aggregation::AggregationMethod::Min
+    );
+    assert_eq!(info.archive_x_files_factor(&info.archives[0]), 0.0);
+    assert_eq!(info.archive_x_files_factor(&info.archives[1]), 0.5);
+    Ok(())
+}
+
+#[test]
+fn calling_creating_like_extended_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let like = temp_dir.path().join("extended.wsp");
+    let path = temp_dir.path().join("like.wsp");
+
+    whisper::WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .archive_aggregation_method(300, AggregationMethod::Max)
+        .archive_x_files_factor(300, 0.0)
+        .build(&like)?;
+
+    Command::cargo_bin(NAME)?
+        .arg("--like")
+        .arg(&like)
+        .args(["--aggregationMethod", "sum", "--xFilesFactor", "0.25"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr("");
+
+    let file = whisper::WhisperFile::open(&path)?;
+    let info = file.info();
+    for archive in &info.archives {
+        assert_eq!(
+            info.archive_aggregation_method(archive),
+            AggregationMethod::Sum
+        );
+        assert_eq!(info.archive_x_files_factor(archive), 0.25);
+    }
+    Ok(())
+}
+
+#[test]
+fn calling_creating_like_file_with_retentions() -> Result<(), Box<dyn Error>> {
+    let file_path = PathBuf::new().join("data").join("info.wsp");
+
+    Command::cargo_bin(NAME)?
+        .arg("--like")
+        .arg(&file_path)
+        .args(["new.wsp", "60:1440"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("cannot be used with").from_utf8());
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::Builder;
+use whisper::builder::WhisperBuilder;
+use whisper::point::Point;
+use whisper::retention::Retention;
+
+const NAME: &str = "whisper-diff";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_with_summary() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path_a = temp_dir.path().join("a.wsp");
+    let path_b = temp_dir.path().join("b.wsp");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    for (path, value) in &[(&path_a, 1.0), (&path_b, 3.0)] {
+        let mut file = WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10,
+            })
+            .build(path)?;
+        file.update(
+            &Point {
+                interval: now - 60,
+                value: *value,
+            },
+            now,
+        )?;
+    }
+
+    Command::cargo_bin(NAME)?
+        .args(["--summary", "--columns", "--until"])
+        .arg(now.to_string())
+        .arg(&path_a)
+        .arg(&path_b)
+        .assert()
+        .success()
+        .stdout("archive total differing\n0 1 1\n")
+        .stderr("");
+
+    Command::cargo_bin(NAME)?
+        .args(["--summary", "--stats", "--columns", "--until"])
+        .arg(now.to_string())
+        .arg(&path_a)
+        .arg(&path_b)
+        .assert()
+        .success()
+        .stdout("archive total differing max_delta mean_delta over_threshold\n0 1 1 2 2 1\n")
+        .stderr("");
+
+    Ok(())
+}
@@ -225,3 +225,23 @@ Archive 1 data:
         .stderr("");
     Ok(())
 }
+
+#[test]
+fn calling_as_csv() -> Result<(), Box<dyn Error>> {
+    let file_path = PathBuf::new().join("data").join("dump.wsp");
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", "csv"])
+        .arg(&file_path)
+        .assert()
+        .success()
+        .stdout(
+            "whisper,average,600,0.5\n\
+             archive,40,60,5,,,\n\
+             0,0\n0,0\n0,0\n0,0\n0,0\n\
+             archive,100,120,5,,,\n\
+             0,0\n0,0\n0,0\n0,0\n0,0\n",
+        )
+        .stderr("");
+    Ok(())
+}
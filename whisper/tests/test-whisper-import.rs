@@ -0,0 +1,120 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::Builder;
+use whisper::interval::Interval;
+use whisper::OpenOptions;
+
+const NAME: &str = "whisper-import";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_csv_tree() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let tree = temp_dir.path().join("tree");
+    let input = temp_dir.path().join("export.csv");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let now = now - now % 60;
+    fs::write(
+        &input,
+        format!(
+            "metric,timestamp,value\nsys.cpu,{},1\nsys.cpu,{},2\nsys.mem,{},3\nbroken\n",
+            now - 120,
+            now - 60,
+            now - 60
+        ),
+    )?;
+
+    Command::cargo_bin(NAME)?
+        .args(["--tree", "--retention", "1m:1h"])
+        .arg(&tree)
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files 2, created 2, points 3, written 3").from_utf8())
+        .stdout(predicate::str::contains("malformed 1").from_utf8());
+
+    let mut file = OpenOptions::new().open(tree.join("sys").join("cpu.wsp"))?;
+    let values = file.fetch(60, Interval::new(now - 180, now)?, now)?.values;
+    assert_eq!(values, vec![None, Some(1.0), Some(2.0)]);
+    assert!(tree.join("sys").join("mem.wsp").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn calling_with_json_from_fetch() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("imported.wsp");
+    let input = temp_dir.path().join("fetch.json");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let start = now - now % 60 - 180;
+    fs::write(
+        &input,
+        format!(
+            r#"{{"start": {}, "end": {}, "step": 60, "values": [1.0, null, 3.0]}}"#,
+            start,
+            start + 180
+        ),
+    )?;
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", "json"])
+        .arg(&path)
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files 0, created 0, points 2").from_utf8())
+        .stdout(predicate::str::contains("skipped 2").from_utf8());
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", "json", "--retention", "60:60"])
+        .arg(&path)
+        .arg(&input)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("files 1, created 1, points 2, written 2").from_utf8());
+
+    let mut file = OpenOptions::new().open(&path)?;
+    let values = file
+        .fetch(60, Interval::new(start, start + 180)?, now)?
+        .values;
+    assert_eq!(values, vec![Some(1.0), None, Some(3.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn calling_with_invalid_format() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--format", "xml", "target"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Unsupported import format 'xml'.").from_utf8());
+    Ok(())
+}
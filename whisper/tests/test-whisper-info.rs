@@ -290,3 +290,44 @@ fn calling_as_json() -> Result<(), Box<dyn Error>> {
         .stderr("");
     Ok(())
 }
+
+#[test]
+fn calling_as_plain_for_extended_header() -> Result<(), Box<dyn Error>> {
+    let dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = dir.path().join("extended.wsp");
+
+    whisper::WhisperBuilder::default()
+        .add_retention(whisper::retention::Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(whisper::retention::Retention {
+            seconds_per_point: 600,
+            points: 10,
+        })
+        .archive_aggregation_method(600, whisper::aggregation::AggregationMethod::Max)
+        .archive_x_files_factor(600, 0.9)
+        .build(&path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(unindent(
+                "
+                Archive 1
+                retention: 6000
+                secondsPerPoint: 600
+                points: 10
+                size: 120
+                offset: 176
+                aggregationMethod: max
+                xFilesFactor: 0.9
+                ",
+            ))
+            .from_utf8(),
+        )
+        .stderr("");
+    Ok(())
+}
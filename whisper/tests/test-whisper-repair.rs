@@ -0,0 +1,81 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::Builder;
+
+const NAME: &str = "whisper-repair";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_invalid_path() -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    let error_msg = "No such file or directory (os error 2)";
+    #[cfg(windows)]
+    let error_msg = "The system cannot find the file specified. (os error 2)";
+
+    Command::cargo_bin(NAME)?
+        .args(["invalid"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(error_msg).from_utf8());
+
+    Ok(())
+}
+
+#[test]
+fn calling_with_truncated_file() -> Result<(), Box<dyn Error>> {
+    let dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = dir.path().join("truncated.wsp");
+    fs::copy(PathBuf::new().join("data").join("info.wsp"), &path)?;
+
+    let size = fs::metadata(&path)?.len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)?
+        .set_len(10_000)?;
+
+    Command::cargo_bin(NAME)?
+        .args(["--dry-run", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("size: 10000 -> {} bytes", size)).from_utf8());
+    assert_eq!(fs::metadata(&path)?.len(), 10_000);
+
+    Command::cargo_bin(NAME)?
+        .args(["--json", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""applied":true"#).from_utf8());
+    assert_eq!(fs::metadata(&path)?.len(), size);
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("truncated.wsp: OK").from_utf8());
+
+    Ok(())
+}
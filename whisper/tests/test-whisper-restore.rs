@@ -0,0 +1,132 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::Builder;
+
+const NAME: &str = "whisper-restore";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+fn round_trip(format: &str) -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let source = temp_dir.path().join("source.wsp");
+    let dump = temp_dir.path().join("dump.txt");
+    let restored = temp_dir.path().join("restored.wsp");
+
+    Command::new(assert_cmd::cargo::cargo_bin("whisper-create"))
+        .args(["--xFilesFactor", "0", "--aggregationMethod", "max"])
+        .arg(&source)
+        .args(["1m:10m", "5m:1h"])
+        .assert()
+        .success();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Command::new(assert_cmd::cargo::cargo_bin("whisper-update"))
+        .arg(&source)
+        .arg(format!("{}:1.5", now - 120))
+        .arg(format!("{}:-2", now - 60))
+        .assert()
+        .success();
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin("whisper-dump"))
+        .args(["--format", format])
+        .arg(&source)
+        .output()?;
+    assert!(output.status.success());
+    fs::write(&dump, &output.stdout)?;
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", format])
+        .arg(&restored)
+        .arg(&dump)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Restored: ").from_utf8());
+    assert_eq!(fs::read(&restored)?, fs::read(&source)?);
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", format])
+        .arg(&restored)
+        .arg(&dump)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("exists").from_utf8());
+
+    Command::cargo_bin(NAME)?
+        .args(["--format", format, "--overwrite"])
+        .arg(&restored)
+        .arg(&dump)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&restored)?, fs::read(&source)?);
+
+    Ok(())
+}
+
+#[test]
+fn calling_with_json_dump() -> Result<(), Box<dyn Error>> {
+    round_trip("json")
+}
+
+#[test]
+fn calling_with_csv_dump() -> Result<(), Box<dyn Error>> {
+    round_trip("csv")
+}
+
+#[test]
+fn calling_with_data_file_dump() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let source = PathBuf::new().join("data").join("info.wsp");
+    let restored = temp_dir.path().join("restored.wsp");
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin("whisper-dump"))
+        .args(["--format", "json"])
+        .arg(&source)
+        .output()?;
+
+    assert_cmd::Command::cargo_bin(NAME)?
+        .arg(&restored)
+        .write_stdin(output.stdout)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&restored)?, fs::read(&source)?);
+    Ok(())
+}
+
+#[test]
+fn calling_with_invalid_dump() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let restored = temp_dir.path().join("restored.wsp");
+
+    assert_cmd::Command::cargo_bin(NAME)?
+        .args(["--format", "csv"])
+        .arg(&restored)
+        .write_stdin("whisper,average,600,0.5\narchive,40,60,5,,,\n0,0\n")
+        .assert()
+        .code(1)
+        .stderr("Invalid dump: Archive 0 has 1 slots instead of 5\n");
+    assert!(!restored.exists());
+    Ok(())
+}
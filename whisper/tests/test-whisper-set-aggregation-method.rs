@@ -2,12 +2,35 @@ use assert_cmd::prelude::*;
 use predicates::prelude::*;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tempfile::Builder;
+use whisper::aggregation::AggregationMethod;
+use whisper::interval::Interval;
+use whisper::point::Point;
+use whisper::retention::Retention;
 
 const NAME: &str = "whisper-set-aggregation-method";
 
+fn create_extended(path: &Path) -> Result<(), Box<dyn Error>> {
+    whisper::WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .archive_aggregation_method(60, AggregationMethod::Average)
+        .archive_aggregation_method(300, AggregationMethod::Max)
+        .archive_x_files_factor(60, 0.5)
+        .archive_x_files_factor(300, 0.5)
+        .build(path)?;
+    Ok(())
+}
+
 #[test]
 fn calling_without_args() -> Result<(), Box<dyn Error>> {
     Command::cargo_bin(NAME)?
@@ -131,3 +154,86 @@ fn calling_with_sum_and_xfactor() -> Result<(), Box<dyn Error>> {
         .stdout(predicate::str::contains("0.2").not().from_utf8());
     Ok(())
 }
+
+#[test]
+fn calling_with_median() -> Result<(), Box<dyn Error>> {
+    let filename = "info.wsp";
+
+    let path = Builder::new()
+        .prefix("whisper")
+        .suffix(filename)
+        .tempdir()?
+        .path()
+        .to_path_buf();
+
+    let file_path = PathBuf::new().join("data").join(filename);
+
+    fs::copy(&file_path, &path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap(), "median"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(average -> median)").from_utf8());
+
+    Command::cargo_bin("whisper-info")?
+        .args([path.to_str().unwrap(), "aggregationMethod"])
+        .assert()
+        .success()
+        .stdout("median\n");
+    Ok(())
+}
+
+#[test]
+fn calling_with_extended_header() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("extended.wsp");
+    create_extended(&path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap(), "sum", "0.25"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(average -> sum)").from_utf8());
+
+    let file = whisper::WhisperFile::open(&path)?;
+    let info = file.info();
+    assert!(info.is_extended());
+    for archive in &info.archives {
+        assert_eq!(archive.aggregation_method, Some(AggregationMethod::Sum));
+        assert_eq!(archive.x_files_factor, Some(0.25));
+    }
+    Ok(())
+}
+
+#[test]
+fn calling_with_rebuild_of_extended_header() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("extended.wsp");
+    create_extended(&path)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+    let bucket = now - now % 300 - 300;
+    let points: Vec<Point> = (0..5)
+        .map(|i| Point {
+            interval: bucket + i * 60,
+            value: f64::from(i + 1),
+        })
+        .collect();
+    let mut file = whisper::WhisperFile::open(&path)?;
+    file.update_many(&points, now)?;
+    let rollup = file.fetch(300, Interval::new(bucket, bucket + 300)?, now)?;
+    assert_eq!(rollup.values, vec![Some(5.0)]);
+    drop(file);
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap(), "sum", "--rebuild"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rebuilt").from_utf8());
+
+    let mut file = whisper::WhisperFile::open(&path)?;
+    let rollup = file.fetch(300, Interval::new(bucket, bucket + 300)?, now)?;
+    assert_eq!(rollup.values, vec![Some(15.0)]);
+    Ok(())
+}
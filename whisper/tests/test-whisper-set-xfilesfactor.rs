@@ -2,12 +2,32 @@ use assert_cmd::prelude::*;
 use predicates::prelude::*;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use tempfile::Builder;
+use whisper::aggregation::AggregationMethod;
+use whisper::retention::Retention;
 
 const NAME: &str = "whisper-set-xfilesfactor";
 
+fn create_extended(path: &Path) -> Result<(), Box<dyn Error>> {
+    whisper::WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .archive_aggregation_method(60, AggregationMethod::Average)
+        .archive_aggregation_method(300, AggregationMethod::Max)
+        .archive_x_files_factor(60, 0.5)
+        .archive_x_files_factor(300, 0.5)
+        .build(path)?;
+    Ok(())
+}
+
 #[test]
 fn calling_without_args() -> Result<(), Box<dyn Error>> {
     Command::cargo_bin(NAME)?
@@ -117,3 +137,52 @@ fn calling_with_whole_param() -> Result<(), Box<dyn Error>> {
         .stderr("");
     Ok(())
 }
+
+#[test]
+fn calling_with_rebuild() -> Result<(), Box<dyn Error>> {
+    let filename = "info.wsp";
+
+    let path = Builder::new()
+        .prefix("whisper")
+        .suffix(filename)
+        .tempdir()?
+        .path()
+        .to_path_buf();
+
+    let file_path = PathBuf::new().join("data").join(filename);
+
+    fs::copy(&file_path, &path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap(), "0.1", "--rebuild"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(0.5 -> 0.1)").from_utf8())
+        .stdout(predicate::str::contains("Rebuilt").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_extended_header() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("extended.wsp");
+    create_extended(&path)?;
+
+    Command::cargo_bin(NAME)?
+        .args([path.to_str().unwrap(), "0.25"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(0.5 -> 0.25)").from_utf8());
+
+    let file = whisper::WhisperFile::open(&path)?;
+    let info = file.info();
+    for archive in &info.archives {
+        assert_eq!(archive.x_files_factor, Some(0.25));
+    }
+    assert_eq!(
+        info.archives[1].aggregation_method,
+        Some(AggregationMethod::Max)
+    );
+    Ok(())
+}
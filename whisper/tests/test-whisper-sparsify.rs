@@ -0,0 +1,71 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::process::Command;
+use tempfile::Builder;
+use whisper::retention::Retention;
+use whisper::WhisperBuilder;
+
+const NAME: &str = "whisper-sparsify";
+
+#[test]
+fn calling_without_args() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("USAGE").from_utf8());
+    Ok(())
+}
+
+#[test]
+fn calling_help() -> Result<(), Box<dyn Error>> {
+    Command::cargo_bin(NAME)?
+        .args(["--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("USAGE").from_utf8())
+        .stderr("");
+    Ok(())
+}
+
+#[test]
+fn calling_with_invalid_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let path = temp_dir.path().join("broken.wsp");
+    fs::write(&path, b"broken")?;
+
+    Command::cargo_bin(NAME)?
+        .arg(&path)
+        .assert()
+        .code(1)
+        .stdout("files 0, holes 0, punched 0 bytes, reclaimed 0 bytes\n")
+        .stderr(predicate::str::contains("Failed to sparsify 1 files").from_utf8());
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn calling_with_directory() -> Result<(), Box<dyn Error>> {
+    let temp_dir = Builder::new().prefix("whisper").tempdir()?;
+    let nested = temp_dir.path().join("servers").join("host1");
+    fs::create_dir_all(&nested)?;
+
+    for name in &["cpu.wsp", "memory.wsp"] {
+        WhisperBuilder::default()
+            .add_retention(Retention {
+                seconds_per_point: 60,
+                points: 10_000,
+            })
+            .build(nested.join(name))?;
+    }
+
+    Command::cargo_bin(NAME)?
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("files 2, holes 2, punched 229376 bytes").from_utf8())
+        .stderr("");
+    Ok(())
+}
@@ -30,6 +30,27 @@ fn create_file(path: &Path) -> Result<WhisperFile, BuilderError> {
         .build(path)
 }
 
+fn create_backfill_file(path: &Path) -> Result<WhisperFile, BuilderError> {
+    WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 10,
+            points: 8640,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10080,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 3600,
+            points: 720,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 86400,
+            points: 365,
+        })
+        .build(path)
+}
+
 fn current_time() -> u32 {
     let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time");
     since_epoch.as_secs() as u32
@@ -128,11 +149,30 @@ fn test_update_fetch(bench: &mut Bencher) {
     });
 }
 
+fn test_update_many_backfill(bench: &mut Bencher) {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "whisper_backfill");
+    let mut file = create_backfill_file(&path).expect("Create file for backfill");
+
+    let now = current_time();
+    let points: Vec<Point> = (1..8640)
+        .map(|j| Point {
+            interval: now - j * 10,
+            value: f64::from(j) * VALUE_STEP,
+        })
+        .collect();
+
+    bench.iter(|| {
+        file.update_many(&points, now).expect("update_many");
+    });
+}
+
 benchmark_group!(
     benches,
     test_create,
     test_update,
     test_fetch,
     test_update_fetch,
+    test_update_many_backfill,
 );
 benchmark_main!(benches);
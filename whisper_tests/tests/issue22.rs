@@ -67,7 +67,7 @@ fn issue22_many_archives() -> Result<(), Box<dyn Error>> {
                 value: f64::from(delta) * 100.0,
             },
             now,
-        )?
+        )?;
     }
 
     let points = file.dump(2)?;
@@ -213,7 +213,7 @@ fn issue22_many_archives_reverse() -> Result<(), Box<dyn Error>> {
                 value: f64::from(delta) * 100.0,
             },
             now,
-        )?
+        )?;
     }
 
     let points = file.dump(2)?;
@@ -299,7 +299,7 @@ fn issue22_many_archives_once_shuffle() -> Result<(), Box<dyn Error>> {
             value: 1400.0,
         },
     ] {
-        file.update(point, now)?
+        file.update(point, now)?;
     }
 
     let points = file.dump(2)?;
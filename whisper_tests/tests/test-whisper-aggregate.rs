@@ -0,0 +1,156 @@
+use std::error::Error;
+use whisper::aggregate::*;
+use whisper::aggregation::AggregationMethod;
+use whisper::interval::Interval;
+use whisper::point::*;
+use whisper::retention::*;
+use whisper::*;
+use whisper_tests::*;
+
+fn create_with_points(
+    path: &std::path::Path,
+    retention: Retention,
+    points: &[(u32, f64)],
+    now: u32,
+) -> Result<WhisperFile, Box<dyn Error>> {
+    let mut file = WhisperBuilder::default()
+        .add_retention(retention)
+        .x_files_factor(0.0)
+        .build(path)?;
+    let points: Vec<Point> = points
+        .iter()
+        .map(|&(interval, value)| Point { interval, value })
+        .collect();
+    file.update_many(&points, now)?;
+    Ok(file)
+}
+
+#[test]
+#[allow(clippy::unreadable_literal)]
+fn test_aggregate_sum() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+
+    let path1 = get_file_path(&temp_dir, "sum_1");
+    let path2 = get_file_path(&temp_dir, "sum_2");
+    let path_dst = get_file_path(&temp_dir, "sum_dst");
+    let path_full = get_file_path(&temp_dir, "sum_full");
+
+    let now = 1528240800;
+    let retention = Retention {
+        seconds_per_point: 60,
+        points: 30,
+    };
+    let points1: Vec<(u32, f64)> = (1..=4).map(|i| (now - 60 * i, f64::from(i))).collect();
+    let points2: Vec<(u32, f64)> = (3..=6).map(|i| (now - 60 * i, f64::from(i * 10))).collect();
+    create_with_points(&path1, retention, &points1, now)?;
+    create_with_points(&path2, retention, &points2, now)?;
+
+    let options = AggregateOptions::new(AggregationMethod::Sum);
+    let report = aggregate(&[&path1, &path2], &path_dst, 0, now, now, &options)?;
+    assert_eq!(
+        report,
+        AggregateReport {
+            created: true,
+            written: 6,
+            incomplete: 0,
+        }
+    );
+
+    let mut file = OpenOptions::new().open(&path_dst)?;
+    assert_eq!(file.info().retentions(), vec![retention]);
+    let data = file.fetch(60, Interval::new(now - 420, now)?, now)?;
+    assert_eq!(
+        data.values,
+        vec![
+            None,
+            Some(60.0),
+            Some(50.0),
+            Some(44.0),
+            Some(33.0),
+            Some(2.0),
+            Some(1.0)
+        ]
+    );
+
+    // Only the points known in every source
+    let options = options.x_files_factor(1.0);
+    let report = aggregate(&[&path1, &path2], &path_full, 0, now, now, &options)?;
+    assert_eq!(report.written, 2);
+    assert_eq!(report.incomplete, 4);
+
+    let mut file = OpenOptions::new().open(&path_full)?;
+    let data = file.fetch(60, Interval::new(now - 300, now - 120)?, now)?;
+    assert_eq!(data.values, vec![None, Some(44.0), Some(33.0)]);
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::unreadable_literal)]
+fn test_aggregate_resampled() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+
+    let path1 = get_file_path(&temp_dir, "resampled_1");
+    let path2 = get_file_path(&temp_dir, "resampled_2");
+    let path_dst = get_file_path(&temp_dir, "resampled_dst");
+
+    let now = 1528240800;
+    let points1: Vec<(u32, f64)> = (1..=4).map(|i| (now - 60 * i, f64::from(i))).collect();
+    create_with_points(
+        &path1,
+        Retention {
+            seconds_per_point: 60,
+            points: 30,
+        },
+        &points1,
+        now,
+    )?;
+    create_with_points(
+        &path2,
+        Retention {
+            seconds_per_point: 300,
+            points: 10,
+        },
+        &[(now - 300, 100.0), (now - 600, 200.0)],
+        now,
+    )?;
+
+    let mut file_dst = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 5,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .build(&path_dst)?;
+
+    let options = AggregateOptions::new(AggregationMethod::Max).x_files_factor(0.0);
+    let report = aggregate(&[&path1, &path2], &path_dst, 0, now, now, &options)?;
+    assert!(!report.created);
+    assert_eq!(report.written, 7);
+
+    // The point of the second file covers every minute of its 5 minutes
+    let data = file_dst.fetch(60, Interval::new(now - 300, now)?, now)?;
+    assert_eq!(data.values, vec![Some(100.0); 5]);
+
+    // The minute points average to 2.5, below the point of the second file
+    let data = file_dst.fetch(300, Interval::new(now - 900, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(200.0), Some(100.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregate_errors() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path_dst = get_file_path(&temp_dir, "errors_dst");
+
+    let options = AggregateOptions::new(AggregationMethod::Sum);
+    let sources: &[&std::path::Path] = &[];
+    assert!(aggregate(sources, &path_dst, 0, 100, 100, &options).is_err());
+    assert!(aggregate(&[&path_dst], &path_dst, 100, 0, 100, &options).is_err());
+    assert!(!path_dst.exists());
+    Ok(())
+}
@@ -188,7 +188,7 @@ fn test_diff_simple_unfiltered() -> Result<(), Box<dyn Error>> {
 
 #[test]
 #[allow(clippy::unreadable_literal)]
-fn test_diff_error() -> Result<(), Box<dyn Error>> {
+fn test_diff_unalike() -> Result<(), Box<dyn Error>> {
     let temp_dir = get_temp_dir();
 
     let path1 = get_file_path(&temp_dir, "diff1_1");
@@ -210,9 +210,78 @@ fn test_diff_error() -> Result<(), Box<dyn Error>> {
         })
         .build(&path2)?;
 
-    let diff_result = whisper::diff::diff(&path1, &path2, false, now, now);
+    let diff_points = whisper::diff::diff(&path1, &path2, false, now, now)?;
+
+    assert_eq!(diff_points.len(), 1);
+    assert_eq!(diff_points[0].total, 0);
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::unreadable_literal)]
+fn test_diff_tolerance() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+
+    let path1 = get_file_path(&temp_dir, "tolerance_1");
+    let path2 = get_file_path(&temp_dir, "tolerance_2");
+
+    let now = 1528240800;
+
+    let _file1 = create_and_update_points(
+        &path1,
+        &[
+            Point {
+                interval: now - 60,
+                value: 0.3,
+            },
+            Point {
+                interval: now - 120,
+                value: 100.0,
+            },
+            Point {
+                interval: now - 180,
+                value: f64::NAN,
+            },
+        ],
+        now,
+    )?;
+
+    let _file2 = create_and_update_points(
+        &path2,
+        &[
+            Point {
+                interval: now - 60,
+                value: 0.1 + 0.2,
+            },
+            Point {
+                interval: now - 120,
+                value: 101.0,
+            },
+            Point {
+                interval: now - 180,
+                value: f64::NAN,
+            },
+        ],
+        now,
+    )?;
+
+    let diff_points = whisper::diff::diff(&path1, &path2, false, now, now)?;
+    assert_eq!(diff_points[0].total, 3);
+    assert_eq!(diff_points[0].points, 2);
+    assert_eq!(diff_points[0].stats.over_threshold, 2);
+    assert!((diff_points[0].stats.max_delta - 1.0).abs() < 1e-9);
+    assert!((diff_points[0].stats.mean_delta - 0.5).abs() < 1e-9);
+
+    let options = DiffOptions::new().absolute_tolerance(1e-9);
+    let diff_points = whisper::diff::diff_with(&path1, &path2, now, now, options)?;
+    assert_eq!(diff_points[0].points, 1);
+    assert_eq!(diff_points[0].diffs[0].interval, now - 120);
 
-    assert!(diff_result.is_err());
+    let options = DiffOptions::new().relative_tolerance(0.01);
+    let diff_points = whisper::diff::diff_with(&path1, &path2, now, now, options)?;
+    assert_eq!(diff_points[0].points, 0);
+    assert_eq!(diff_points[0].stats.over_threshold, 0);
 
     Ok(())
 }
@@ -107,8 +107,62 @@ fn test_merge_errors() -> Result<(), builder::BuilderError> {
         })
         .build(&path3)?;
 
-    assert!(whisper::merge::merge(&path1, &path2, 0, now, now).is_err());
     assert!(whisper::merge::merge(&path1, &path3, now - 10, now - 20, now).is_err());
+    assert!(whisper::merge::merge(&path1, &path2, now - 10, now - 20, now).is_err());
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::unreadable_literal)]
+fn test_merge_resampled() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+
+    let path1 = get_file_path(&temp_dir, "resampled_1");
+    let path2 = get_file_path(&temp_dir, "resampled_2");
+
+    let now = 1528240800;
+
+    let mut file1 = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 30,
+        })
+        .build(&path1)?;
+    let points: Vec<Point> = (1..=10)
+        .map(|i| Point {
+            interval: now - 60 * i,
+            value: f64::from(i),
+        })
+        .collect();
+    file1.update_many(&points, now)?;
+
+    let mut file2 = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 5,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .aggregation_method(whisper::aggregation::AggregationMethod::Sum)
+        .build(&path2)?;
+
+    whisper::merge::merge(&path1, &path2, 0, now, now)?;
+
+    let data = file2.fetch(60, whisper::interval::Interval::new(now - 300, now)?, now)?;
+    assert_eq!(
+        data.values,
+        vec![Some(5.0), Some(4.0), Some(3.0), Some(2.0), Some(1.0)]
+    );
+
+    let data = file2.fetch(300, whisper::interval::Interval::new(now - 900, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(40.0), Some(15.0)]);
+
+    let diff = whisper::diff::diff(&path1, &path2, false, now, now)?;
+    assert!(diff.iter().all(|archive| archive.points == 0), "{:?}", diff);
+    assert_eq!(diff[1].total, 1);
 
     Ok(())
 }
@@ -247,7 +301,7 @@ fn test_fill_overlap() -> Result<(), Box<dyn Error>> {
         now,
     )?;
 
-    whisper::fill::fill(&path1, &path2, now, now)?;
+    whisper::fill::fill(&path1, &path2, now, now, false)?;
     let points = file2.dump(60)?;
 
     for delta in &[180] {
@@ -1,6 +1,8 @@
 use std::error::Error;
 use whisper::aggregation::*;
+use whisper::interval::Interval;
 use whisper::point::*;
+use whisper::resize::{resize, resize_with_progress, BackupPolicy, Progress, ResizeOptions};
 use whisper::retention::*;
 use whisper::*;
 use whisper_tests::*;
@@ -29,16 +31,12 @@ fn test_resize_simple_long() -> Result<(), Box<dyn Error>> {
         points: 20,
     }];
 
-    whisper::resize::resize(
-        &path1,
-        Some(&path2),
-        retentions,
-        0.5,
-        AggregationMethod::Average,
-        false,
-        true,
-        now,
-    )?;
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.5)
+        .aggregation_method(AggregationMethod::Average)
+        .backup(BackupPolicy::Remove)
+        .new_file(&path2);
+    resize(&path1, &options, now)?;
 
     let mut file2 = WhisperFile::open(&path2)?;
 
@@ -87,16 +85,12 @@ fn test_resize_simple_short() -> Result<(), Box<dyn Error>> {
         points: 5,
     }];
 
-    whisper::resize::resize(
-        &path1,
-        Some(&path2),
-        retentions,
-        0.5,
-        AggregationMethod::Average,
-        false,
-        true,
-        now,
-    )?;
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.5)
+        .aggregation_method(AggregationMethod::Average)
+        .backup(BackupPolicy::Remove)
+        .new_file(&path2);
+    resize(&path1, &options, now)?;
 
     let mut file2 = WhisperFile::open(&path2)?;
 
@@ -148,16 +142,12 @@ fn test_resize_extend_short() -> Result<(), Box<dyn Error>> {
         },
     ];
 
-    whisper::resize::resize(
-        &path1,
-        Some(&path2),
-        retentions,
-        0.5,
-        AggregationMethod::Average,
-        false,
-        true,
-        now,
-    )?;
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.5)
+        .aggregation_method(AggregationMethod::Average)
+        .backup(BackupPolicy::Remove)
+        .new_file(&path2);
+    resize(&path1, &options, now)?;
 
     let mut file2 = WhisperFile::open(&path2)?;
 
@@ -203,16 +193,13 @@ fn test_resize_aggr_simple_long() -> Result<(), Box<dyn Error>> {
         points: 20,
     }];
 
-    whisper::resize::resize(
-        &path1,
-        Some(&path2),
-        retentions,
-        0.5,
-        AggregationMethod::Average,
-        true,
-        true,
-        now,
-    )?;
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.5)
+        .aggregation_method(AggregationMethod::Average)
+        .aggregate(true)
+        .backup(BackupPolicy::Remove)
+        .new_file(&path2);
+    resize(&path1, &options, now)?;
 
     let mut file2 = WhisperFile::open(&path2)?;
 
@@ -261,16 +248,13 @@ fn test_resize_aggr_simple_short() -> Result<(), Box<dyn Error>> {
         points: 5,
     }];
 
-    whisper::resize::resize(
-        &path1,
-        Some(&path2),
-        retentions,
-        0.5,
-        AggregationMethod::Average,
-        true,
-        true,
-        now,
-    )?;
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.5)
+        .aggregation_method(AggregationMethod::Average)
+        .aggregate(true)
+        .backup(BackupPolicy::Remove)
+        .new_file(&path2);
+    resize(&path1, &options, now)?;
 
     let mut file2 = WhisperFile::open(&path2)?;
 
@@ -292,3 +276,95 @@ fn test_resize_aggr_simple_short() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_resize_same_retentions() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "resize_same");
+
+    let now = 1528240800;
+    let original_points = &(1..10)
+        .map(|x| Point {
+            interval: now - 60 * x,
+            value: 60.0 * f64::from(x),
+        })
+        .collect::<Vec<Point>>();
+
+    let original = create_and_update_points(&path, original_points, now)?.dump(60)?;
+
+    let retentions = &[Retention {
+        seconds_per_point: 60,
+        points: 10,
+    }];
+
+    let options = ResizeOptions::new(retentions)
+        .x_files_factor(0.1)
+        .aggregation_method(AggregationMethod::Sum);
+    let report = resize(&path, &options, now)?;
+
+    let mut file = WhisperFile::open(&path)?;
+    assert_eq!(file.info().x_files_factor, 0.1);
+    assert_eq!(file.info().aggregation_method, AggregationMethod::Sum);
+    assert_eq!(file.dump(60)?, original);
+    assert!(!path.with_extension("wsp.bak").exists());
+    assert!(report.metadata_only);
+    assert_eq!(report.backup, None);
+
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::unreadable_literal)]
+fn test_resize_in_place_with_progress() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "resize_in_place");
+
+    let now = 1528240800;
+    let original_points = &(1..10)
+        .map(|x| Point {
+            interval: now - 60 * x,
+            value: 60.0 * f64::from(x),
+        })
+        .collect::<Vec<Point>>();
+
+    create_and_update_points(&path, original_points, now)?;
+
+    let options = ResizeOptions::new(&[Retention {
+        seconds_per_point: 60,
+        points: 20,
+    }]);
+    let mut progress = Vec::new();
+    let report = resize_with_progress(&path, &options, now, &mut |step| progress.push(step))?;
+
+    let backup = path.with_extension("wsp.bak");
+    assert_eq!(report.path, path);
+    assert_eq!(report.backup, Some(backup.clone()));
+    assert_eq!(report.points, 9);
+    assert!(!report.metadata_only);
+    assert!(backup.is_file());
+
+    assert_eq!(
+        progress.first(),
+        Some(&Progress::Created {
+            path: path.with_extension("wsp.tmp"),
+            size: 16 + 12 + 20 * 12,
+        })
+    );
+    assert_eq!(
+        progress.last(),
+        Some(&Progress::Renamed {
+            from: path.with_extension("wsp.tmp"),
+            to: path.clone(),
+        })
+    );
+
+    let mut file = WhisperFile::open(&path)?;
+    assert_eq!(file.info().archives[0].points, 20);
+    let data = file.fetch(60, Interval::new(now - 600, now)?, now)?;
+    assert_eq!(
+        data.values.iter().filter(|value| value.is_some()).count(),
+        9
+    );
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use std::error::Error;
+use whisper::aggregation::AggregationMethod;
 use whisper::builder::WhisperBuilder;
 use whisper::interval::Interval;
 use whisper::point::Point;
 use whisper::retention::Retention;
-use whisper::ArchiveData;
+use whisper::{ArchiveData, OpenOptions, ReadMode, WhisperFile};
 use whisper_tests::*;
 
 #[test]
@@ -125,3 +126,337 @@ fn whisper_fetch_auto_points() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn whisper_locked_update_fetch() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "locked");
+
+    let now = 1528240800;
+
+    let mut file = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .lock(true)
+        .build(&path)?;
+
+    file.update_many(
+        &[Point {
+            interval: now - 60,
+            value: 60.0,
+        }],
+        now,
+    )?;
+
+    let mut reader = OpenOptions::new().lock(true).open(&path)?;
+    let data = reader.fetch(60, Interval::new(now - 120, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(60.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn whisper_cached_headers() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "cached");
+
+    let now = 1528240800;
+    create_and_update_points(
+        &path,
+        &[Point {
+            interval: now - 60,
+            value: 60.0,
+        }],
+        now,
+    )?;
+
+    let options = OpenOptions::new().cache_headers(true);
+
+    let mut file = options.open(&path)?;
+    assert_eq!(file.info().x_files_factor, 0.5);
+    file.set_x_files_factor(0.25)?;
+    file.update(
+        &Point {
+            interval: now - 120,
+            value: 120.0,
+        },
+        now,
+    )?;
+
+    let mut cached = options.open(&path)?;
+    assert_eq!(cached.info().x_files_factor, 0.25);
+    let data = cached.fetch(60, Interval::new(now - 180, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(120.0), Some(60.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn whisper_mmap_fetch() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "mmap");
+
+    let now = 1528240800;
+    // Wrap around the 5 point archive.
+    let points: Vec<Point> = (1..8)
+        .map(|i| Point {
+            interval: now - i * 60,
+            value: f64::from(i),
+        })
+        .collect();
+
+    WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 5,
+        })
+        .x_files_factor(0.5)
+        .build(&path)?
+        .update_many(&points, now)?;
+
+    let mut buffered = OpenOptions::new().open(&path)?;
+    let mut mapped = OpenOptions::new().read_mode(ReadMode::Mmap).open(&path)?;
+
+    let interval = Interval::new(now - 300, now)?;
+    let data = mapped.fetch(60, interval, now)?;
+    assert_eq!(data, buffered.fetch(60, interval, now)?);
+    assert_eq!(
+        data.values,
+        vec![Some(5.0), Some(4.0), Some(3.0), Some(2.0), Some(1.0)]
+    );
+    assert_eq!(mapped.dump(60)?, buffered.dump(60)?);
+
+    Ok(())
+}
+
+#[test]
+fn whisper_update_metadata() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "metadata");
+
+    let now = 1528240800;
+    create_and_update_points(
+        &path,
+        &[Point {
+            interval: now - 60,
+            value: 60.0,
+        }],
+        now,
+    )?;
+
+    let options = OpenOptions::new().lock(true);
+    let mut stale = options.open(&path)?;
+
+    #[cfg(unix)]
+    let inode = {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))?;
+        std::fs::metadata(&path)?.ino()
+    };
+    let content = std::fs::read(&path)?;
+
+    let mut file = options.open(&path)?;
+    file.update_metadata(|metadata| {
+        metadata.x_files_factor = 0.25;
+        metadata.aggregation_method = AggregationMethod::Max;
+    })?;
+
+    // Replaced by a copy with the same permissions and points
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let replaced = std::fs::metadata(&path)?;
+        assert_ne!(replaced.ino(), inode);
+        assert_eq!(replaced.permissions().mode() & 0o777, 0o640);
+    }
+    let replaced = std::fs::read(&path)?;
+    assert_eq!(replaced.len(), content.len());
+    assert_eq!(replaced[16..], content[16..]);
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+    assert_eq!(file.info().x_files_factor, 0.25);
+    assert!(file
+        .update_metadata(|metadata| metadata.archives.clear())
+        .is_err());
+
+    let reopened = WhisperFile::open(&path)?;
+    assert_eq!(reopened.info().x_files_factor, 0.25);
+    assert_eq!(reopened.info().aggregation_method, AggregationMethod::Max);
+
+    // A handle opened before the update switches to the new file when it locks it
+    stale.update(
+        &Point {
+            interval: now - 120,
+            value: 120.0,
+        },
+        now,
+    )?;
+    assert_eq!(stale.info().aggregation_method, AggregationMethod::Max);
+
+    let data = file.fetch(60, Interval::new(now - 180, now)?, now)?;
+    assert_eq!(data.values, vec![None, Some(120.0), Some(60.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn whisper_rebuild() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "rebuild");
+
+    let now = 1528240800;
+    let mut file = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .x_files_factor(0.0)
+        .build(&path)?;
+
+    // Beyond the retention of the highest precision archive
+    file.update(
+        &Point {
+            interval: now - 2100,
+            value: 100.0,
+        },
+        now,
+    )?;
+    let points: Vec<Point> = (1..10)
+        .map(|i| Point {
+            interval: now - i * 60,
+            value: f64::from(i),
+        })
+        .collect();
+    file.update_many(&points, now)?;
+
+    let data = file.fetch(300, Interval::new(now - 2400, now)?, now)?;
+    assert_eq!(data.values[1], Some(100.0));
+    assert_eq!(&data.values[6..], &[Some(7.5), Some(3.0)]);
+
+    file.set_aggregation_method(AggregationMethod::Max)?;
+    let data = file.fetch(300, Interval::new(now - 2400, now)?, now)?;
+    assert_eq!(&data.values[6..], &[Some(7.5), Some(3.0)]);
+
+    file.rebuild(now)?;
+    let data = file.fetch(300, Interval::new(now - 2400, now)?, now)?;
+    assert_eq!(data.values[1], Some(100.0));
+    assert_eq!(&data.values[6..], &[Some(9.0), Some(5.0)]);
+
+    // Intervals which don't meet the new xFilesFactor are cleared
+    file.set_x_files_factor(1.0)?;
+    file.rebuild(now)?;
+    let data = file.fetch(300, Interval::new(now - 2400, now)?, now)?;
+    assert_eq!(data.values[1], Some(100.0));
+    assert_eq!(&data.values[6..], &[None, Some(5.0)]);
+
+    Ok(())
+}
+
+#[test]
+fn whisper_fetch_stitched() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "stitched");
+
+    let now = 1528240800;
+    let mut file = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .add_retention(Retention {
+            seconds_per_point: 300,
+            points: 10,
+        })
+        .build(&path)?;
+
+    let points: Vec<Point> = (1..50)
+        .map(|i| Point {
+            interval: now - i * 60,
+            value: f64::from(i),
+        })
+        .collect();
+    file.update_many(&points, now)?;
+
+    let segments = file.fetch_stitched(Interval::new(now - 3000, now)?, now)?;
+    assert_eq!(segments.len(), 2);
+
+    let (lower, higher) = (&segments[0], &segments[1]);
+    assert_eq!(lower.step, 300);
+    assert_eq!(higher.step, 60);
+    assert_eq!(lower.from_interval, now - 3000);
+    assert_eq!(lower.until_interval, now - 600);
+    assert_eq!(higher.from_interval, now - 600);
+    assert_eq!(higher.until_interval, now);
+
+    assert_eq!(
+        lower.values,
+        file.fetch(300, Interval::new(now - 3000, now - 600)?, now)?
+            .values
+    );
+    assert_eq!(
+        higher.values,
+        file.fetch(60, Interval::new(now - 600, now)?, now)?.values
+    );
+    assert_eq!(higher.values[9], Some(1.0));
+
+    // Ranges covered by the highest resolution archive aren't split
+    let segments = file.fetch_stitched(Interval::new(now - 300, now)?, now)?;
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].step, 60);
+
+    assert!(file
+        .fetch_stitched(Interval::new(now + 60, now + 120)?, now)?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn whisper_typed_errors() -> Result<(), Box<dyn Error>> {
+    let temp_dir = get_temp_dir();
+    let path = get_file_path(&temp_dir, "errors");
+
+    let now = 1528240800;
+
+    let mut file = WhisperBuilder::default()
+        .add_retention(Retention {
+            seconds_per_point: 60,
+            points: 10,
+        })
+        .build(&path)?;
+
+    let error = file
+        .fetch(300, Interval::new(now - 600, now)?, now)
+        .unwrap_err();
+    assert!(
+        matches!(error, whisper::error::Error::ArchiveNotFound(300)),
+        "{:?}",
+        error
+    );
+
+    for interval in &[now - 600, now + 60] {
+        let point = Point {
+            interval: *interval,
+            value: 1.0,
+        };
+        let error = file.update(&point, now).unwrap_err();
+        assert!(
+            matches!(error, whisper::error::Error::TimestampNotCovered(i) if i == *interval),
+            "{:?}",
+            error
+        );
+    }
+
+    match OpenOptions::new().open(temp_dir.path().join("missing.wsp")) {
+        Err(whisper::error::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        Err(e) => panic!("{:?}", e),
+        Ok(_) => panic!("missing file was opened"),
+    }
+
+    Ok(())
+}